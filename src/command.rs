@@ -0,0 +1,683 @@
+use crate::content;
+use crate::editor::Editor;
+use crate::keymap::Action;
+use crate::markup::ParseMode;
+use crate::requests::Reply;
+use crate::search;
+use crate::selected;
+use crate::App;
+use chrono::prelude::*;
+use rtdlib::types::*;
+use serde_json::json;
+use std::fs;
+use std::path::PathBuf;
+use termion::event::Key;
+
+// Most commands kept in the history file
+const HISTORY_LIMIT: usize = 100;
+
+// Tdlib mutes chats for good when asked to mute them for more than a year
+const MUTE_FOREVER: i64 = i32::MAX as i64;
+
+// Commands with their arguments. Any unambiguous start of a name runs the command, e.g.
+// `:q` or `:o Alice`
+const COMMANDS: [(&str, &str); 14] = [
+    ("open", "<chat, user or message link>"),
+    ("search", "<text>"),
+    ("searchall", "<text>"),
+    ("mute", "[duration, e.g. 8h or 2d]"),
+    ("unmute", ""),
+    ("leave", ""),
+    ("pin", ""),
+    ("unpin", ""),
+    ("pinned", ""),
+    ("forward", "<chat>"),
+    ("export", "[file]"),
+    ("set", "[option[=value]]"),
+    ("help", ""),
+    ("quit", ""),
+];
+
+// Options for `:set`. Booleans are turned off with a `no` prefix, e.g. `:set novim_input`
+const OPTIONS: [&str; 2] = ["vim_input", "parse_mode"];
+
+// What the command line is open for
+#[derive(Clone, Copy, PartialEq)]
+pub enum Prompt {
+    Command,
+    Search,
+    SearchAll,
+}
+
+impl Prompt {
+    fn text(self) -> &'static str {
+        match self {
+            Prompt::Command => ":",
+            Prompt::Search => "/",
+            Prompt::SearchAll => "Search all chats: ",
+        }
+    }
+}
+
+// The `:` command line, shown at the bottom of the screen while a command is typed. It's
+// also where searches are typed, after a `/`
+pub struct CommandLine {
+    // Text typed after the prompt, while the command line is open
+    input: Option<Editor>,
+    prompt: Prompt,
+
+    // Commands run before, oldest first, kept between runs in `history_file`
+    history: Vec<String>,
+    history_file: PathBuf,
+
+    // Position in `history` while going through it with Up and Down, and what had been
+    // typed before. Only commands starting with what was typed are gone through
+    history_index: Option<usize>,
+    typed: String,
+
+    // Completions for the word before the cursor, while Tab cycles through them
+    completion: Option<Completion>,
+}
+
+struct Completion {
+    // Byte offset where the word being completed starts
+    start: usize,
+    candidates: Vec<String>,
+    index: usize,
+}
+
+impl CommandLine {
+    pub fn new(history_file: PathBuf) -> CommandLine {
+        let history = fs::read_to_string(&history_file)
+            .map(|h| h.lines().map(str::to_string).collect())
+            .unwrap_or_default();
+        CommandLine {
+            input: None,
+            prompt: Prompt::Command,
+            history,
+            history_file,
+            history_index: None,
+            typed: String::new(),
+            completion: None,
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.input.is_some()
+    }
+
+    pub fn open(&mut self, prompt: Prompt) {
+        self.input = Some(Editor::new());
+        self.prompt = prompt;
+        self.history_index = None;
+        self.completion = None;
+    }
+
+    fn close(&mut self) {
+        self.input = None;
+    }
+
+    // What's shown on the command line, and the cursor's column, if it's open
+    pub fn view(&mut self, width: usize) -> Option<(String, usize)> {
+        let editor = self.input.as_mut()?;
+        let prompt = self.prompt.text();
+        let (lines, (column, _)) = editor.view(width.saturating_sub(prompt.len()), 1);
+        let line = lines.into_iter().next().unwrap_or_default();
+        Some((format!("{}{}", prompt, line), column + prompt.len()))
+    }
+
+    // Handle a key, returning what was typed once Enter is pressed. Only commands are
+    // completed and kept in the history
+    fn handle_key(&mut self, key: Key) -> Option<String> {
+        let editor = self.input.as_mut()?;
+        let command = self.prompt == Prompt::Command;
+        match key {
+            Key::Esc => self.close(),
+            Key::Backspace if editor.is_empty() => self.close(),
+            Key::Char('\n') => {
+                let line = editor.text().trim().to_string();
+                self.close();
+                if command && !line.is_empty() {
+                    self.remember(&line);
+                }
+                return Some(line);
+            }
+            Key::Char('\t') if command => self.cycle_completion(1),
+            Key::BackTab if command => self.cycle_completion(-1),
+            Key::Up if command => self.go_through_history(-1),
+            Key::Down if command => self.go_through_history(1),
+
+            // A single line, so no newlines
+            Key::Alt('\r') | Key::Alt('\n') => {}
+            _ => {
+                editor.handle_key(&key);
+                self.completion = None;
+                self.history_index = None;
+            }
+        }
+        None
+    }
+
+    // Text before the cursor, if Tab would start a new completion of it
+    fn to_complete(&self) -> Option<String> {
+        match (&self.input, &self.completion) {
+            (Some(editor), None) if self.prompt == Prompt::Command => {
+                Some(editor.text()[..editor.cursor()].to_string())
+            }
+            _ => None,
+        }
+    }
+
+    // Replace the word before the cursor with the next or previous completion
+    fn cycle_completion(&mut self, step: i64) {
+        let (editor, completion) = match (self.input.as_mut(), self.completion.as_mut()) {
+            (Some(e), Some(c)) if !c.candidates.is_empty() => (e, c),
+            _ => return,
+        };
+        let len = completion.candidates.len() as i64;
+        let shown = editor.text()[completion.start..].to_string();
+        // The first Tab shows the first candidate, rather than skipping it
+        if shown == completion.candidates[completion.index] || step < 0 {
+            completion.index = (completion.index as i64 + step).rem_euclid(len) as usize;
+        }
+        let end = editor.text().len();
+        editor.delete_range(completion.start, end);
+        editor.insert_str(&completion.candidates[completion.index]);
+    }
+
+    // Move `step` entries through the history, skipping those not starting with what
+    // was typed, and back to what was typed after the newest
+    fn go_through_history(&mut self, step: i64) {
+        let editor = match self.input.as_mut() {
+            Some(e) => e,
+            None => return,
+        };
+        if self.history_index.is_none() {
+            self.typed = editor.text().to_string();
+        }
+        let typed = &self.typed;
+        let mut i = self.history_index.unwrap_or(self.history.len()) as i64;
+        loop {
+            i += step;
+            if i < 0 {
+                return;
+            }
+            if i as usize >= self.history.len() {
+                self.history_index = None;
+                editor.set_text(typed);
+                return;
+            }
+            if self.history[i as usize].starts_with(typed.as_str()) {
+                self.history_index = Some(i as usize);
+                editor.set_text(&self.history[i as usize]);
+                return;
+            }
+        }
+    }
+
+    fn remember(&mut self, line: &str) {
+        self.history.retain(|h| h != line);
+        self.history.push(line.to_string());
+        let skip = self.history.len().saturating_sub(HISTORY_LIMIT);
+        self.history.drain(..skip);
+
+        let mut contents = self.history.join("\n");
+        contents.push('\n');
+        if let Some(dir) = self.history_file.parent() {
+            let _ = fs::create_dir_all(dir);
+        }
+        if let Err(e) = fs::write(&self.history_file, contents) {
+            eprintln!(
+                "Couldn't save command history to {}: {}",
+                self.history_file.display(),
+                e
+            );
+        }
+    }
+}
+
+// Handle a key while the command line is open, running the command once it's entered
+// and returning any action it leads to
+pub fn handle_key(app: &mut App, key: Key) -> Option<Action> {
+    // Completions look through chats and users, so are found without the command line
+    // locked, keeping to one order of locking
+    if let Key::Char('\t') | Key::BackTab = key {
+        let before = app.command_line.lock().unwrap().to_complete();
+        if let Some(before) = before {
+            let (start, candidates) = completions(app, &before);
+            app.command_line.lock().unwrap().completion = Some(Completion {
+                start,
+                candidates,
+                index: 0,
+            });
+        }
+    }
+    let mut command_line = app.command_line.lock().unwrap();
+    let prompt = command_line.prompt;
+    let line = command_line.handle_key(key)?;
+    drop(command_line);
+    match prompt {
+        Prompt::Search => search::search_chat(app, &line),
+        Prompt::SearchAll => search::search_all(app, &line),
+        Prompt::Command => return run_reporting(app, &line),
+    }
+    None
+}
+
+// Run a command line, showing any error
+fn run_reporting(app: &mut App, line: &str) -> Option<Action> {
+    match run(app, line) {
+        Ok(action) => action,
+        Err(e) => {
+            app.set_status(e);
+            None
+        }
+    }
+}
+
+// Where the word being completed starts in `before`, the text before the cursor, and
+// what it could be completed to
+fn completions(app: &App, before: &str) -> (usize, Vec<String>) {
+    let (name, arg) = match before.find(' ') {
+        Some(i) => (&before[..i], &before[i + 1..]),
+        None => {
+            let names = COMMANDS
+                .iter()
+                .map(|(n, _)| n.to_string())
+                .filter(|n| n.starts_with(before))
+                .collect();
+            return (0, names);
+        }
+    };
+    let start = before.len() - arg.trim_start().len();
+    let arg = arg.trim_start().to_lowercase();
+    let mut candidates: Vec<String> = match command_by_name(name) {
+        Ok("open") | Ok("forward") => {
+            let mut names: Vec<String> = app
+                .chat_list
+                .chat_vec
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|c| c.chat.title().to_string())
+                .collect();
+            names.extend(
+                app.users
+                    .lock()
+                    .unwrap()
+                    .values()
+                    .map(|u| content::full_name(u.u.first_name(), u.u.last_name())),
+            );
+            names
+        }
+        Ok("set") => {
+            let mut options: Vec<String> = vec!["vim_input".to_string(), "novim_input".to_string()];
+            options.extend(
+                [ParseMode::Markdown, ParseMode::Html, ParseMode::Plain]
+                    .iter()
+                    .map(|m| format!("parse_mode={}", m.name().to_lowercase())),
+            );
+            options
+        }
+        _ => Vec::new(),
+    };
+
+    // Names starting with what's typed come first, then those containing it elsewhere
+    candidates.retain(|c| c.to_lowercase().contains(&arg));
+    candidates.sort_by_key(|c| (!c.to_lowercase().starts_with(&arg), c.to_lowercase()));
+    candidates.dedup();
+    (start, candidates)
+}
+
+// The command a name is the start of, if just one
+fn command_by_name(name: &str) -> Result<&'static str, String> {
+    if let Some((n, _)) = COMMANDS.iter().find(|(n, _)| *n == name) {
+        return Ok(n);
+    }
+    let matching: Vec<&str> = COMMANDS
+        .iter()
+        .map(|(n, _)| *n)
+        .filter(|n| n.starts_with(name))
+        .collect();
+    match matching.as_slice() {
+        [n] => Ok(n),
+        [] => Err(format!("Not a command: {}", name)),
+        _ => Err(format!(
+            "Ambiguous command {}, could be {}",
+            name,
+            matching.join(", ")
+        )),
+    }
+}
+
+// Run a command line, returning any action for the UI thread to carry out
+pub fn run(app: &mut App, line: &str) -> Result<Option<Action>, String> {
+    let (name, arg) = match line.find(' ') {
+        Some(i) => (&line[..i], line[i + 1..].trim()),
+        None => (line, ""),
+    };
+    if name.is_empty() {
+        return Ok(None);
+    }
+    let command = command_by_name(name)?;
+    match command {
+        "open" => open(app, arg)?,
+        "search" | "searchall" if arg.is_empty() => return Err(usage(command)),
+        "search" => search::search_chat(app, arg),
+        "searchall" => search::search_all(app, arg),
+        "mute" => mute(app, arg)?,
+        "unmute" => mute_for(app, 0)?,
+        "leave" => leave(app)?,
+        "pin" => pin(app, true)?,
+        "unpin" => pin(app, false)?,
+        "pinned" => pinned(app)?,
+        "forward" => forward(app, arg)?,
+        "export" => export(app, arg)?,
+        "set" => set(app, arg)?,
+        "help" => {
+            let usage: Vec<String> = COMMANDS
+                .iter()
+                .map(|(n, args)| format!(":{} {}", n, args).trim_end().to_string())
+                .collect();
+            app.set_status(usage.join("  "));
+        }
+        "quit" => return Ok(Some(Action::Quit)),
+        _ => {}
+    }
+    Ok(None)
+}
+
+fn usage(name: &str) -> String {
+    let args = COMMANDS
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map_or("", |(_, a)| a);
+    format!("Usage: :{} {}", name, args)
+}
+
+// Index in the chat list of the chat called `name`, or failing that the first whose
+// title contains it, ignoring case
+fn find_chat(app: &App, name: &str) -> Option<usize> {
+    let name = name.to_lowercase();
+    let cv = app.chat_list.chat_vec.lock().unwrap();
+    let title = |i: usize| cv[i].chat.title().to_lowercase();
+    (0..cv.len())
+        .find(|&i| title(i) == name)
+        .or_else(|| (0..cv.len()).find(|&i| title(i).contains(&name)))
+}
+
+// ID of the user called `name`, or failing that the first whose name contains it
+fn find_user(app: &App, name: &str) -> Option<i64> {
+    let name = name.to_lowercase();
+    let users = app.users.lock().unwrap();
+    let full_name =
+        |u: &crate::TUser| content::full_name(u.u.first_name(), u.u.last_name()).to_lowercase();
+    users
+        .values()
+        .find(|u| full_name(u) == name)
+        .or_else(|| users.values().find(|u| full_name(u).contains(&name)))
+        .map(|u| u.u.id())
+}
+
+fn open(app: &mut App, name: &str) -> Result<(), String> {
+    if name.is_empty() {
+        return Err(usage("open"));
+    }
+    if name.starts_with("https://t.me/") || name.starts_with("tg:") {
+        return open_link(app, name);
+    }
+    if let Some(i) = find_chat(app, name) {
+        app.open_chat(i);
+        return Ok(());
+    }
+
+    // Users without a chat yet have one created, to be opened once it's in the list
+    let user_id = find_user(app, name).ok_or_else(|| format!("No chat or user called {}", name))?;
+    let req = CreatePrivateChat::builder().user_id(user_id).build();
+    app.requests.send_then(&req, |app, reply| {
+        let chat_id = match reply {
+            Ok(chat) => chat["id"].as_i64().unwrap_or(0),
+            Err(e) => return app.set_status(format!("Couldn't open chat: {}", e)),
+        };
+        if let Some(i) = find_chat_by_id(app, chat_id) {
+            app.open_chat(i);
+        }
+    });
+    Ok(())
+}
+
+// Open the chat a link such as https://t.me/channel/123 is to, at the message it's to
+fn open_link(app: &mut App, url: &str) -> Result<(), String> {
+    app.requests.send_json(
+        json!({"@type": "getMessageLinkInfo", "url": url}),
+        |app, reply| {
+            let info = match reply {
+                Ok(info) => info,
+                Err(e) => return app.set_status(format!("Couldn't open link: {}", e)),
+            };
+            let chat_id = info["chat_id"].as_i64().unwrap_or(0);
+            match info["message"]["id"].as_i64() {
+                Some(message_id) => app.open_message(chat_id, message_id),
+                None => match find_chat_by_id(app, chat_id) {
+                    Some(i) => app.open_chat(i),
+                    None => app.set_status("That chat isn't in the chat list".to_string()),
+                },
+            }
+        },
+    );
+    Ok(())
+}
+
+// Index in the chat list of the chat with `chat_id`
+fn find_chat_by_id(app: &App, chat_id: i64) -> Option<usize> {
+    let cv = app.chat_list.chat_vec.lock().unwrap();
+    cv.iter().position(|c| c.chat.id() == chat_id)
+}
+
+// ID of the chat being shown
+fn current_chat_id(app: &App) -> Result<i64, String> {
+    app.chat_list
+        .selected_chat_id()
+        .ok_or_else(|| "No chat open".to_string())
+}
+
+// The chat and IDs of the messages selected in visual mode
+fn selected_messages(app: &App) -> Result<(i64, Vec<i64>), String> {
+    let (chat_id, msgs) = selected::selection(app)?;
+    Ok((chat_id, msgs.iter().map(|m| m.msg.id()).collect()))
+}
+
+// Parse a duration such as `30m`, `8h`, `2d` or `1w` into seconds
+fn parse_duration(s: &str) -> Option<i64> {
+    let unit = match s.chars().last()? {
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        'w' => 7 * 24 * 60 * 60,
+        _ => return None,
+    };
+    let n: i64 = s[..s.len() - 1].parse().ok()?;
+    if n <= 0 {
+        return None;
+    }
+    n.checked_mul(unit)
+}
+
+fn mute(app: &mut App, duration: &str) -> Result<(), String> {
+    let seconds = if duration.is_empty() {
+        MUTE_FOREVER
+    } else {
+        parse_duration(duration).ok_or_else(|| usage("mute"))?
+    };
+    mute_for(app, seconds.min(MUTE_FOREVER))
+}
+
+fn mute_for(app: &mut App, seconds: i64) -> Result<(), String> {
+    let chat_id = current_chat_id(app)?;
+    let mut settings = app
+        .chat_list
+        .with_chat(chat_id, |c| c.notification_settings.clone())
+        .unwrap_or_default();
+    if !settings.is_object() {
+        settings = json!({"@type": "chatNotificationSettings"});
+    }
+    settings["use_default_mute_for"] = json!(false);
+    settings["mute_for"] = json!(seconds);
+    app.requests.send_json(
+        json!({
+            "@type": "setChatNotificationSettings",
+            "chat_id": chat_id,
+            "notification_settings": settings,
+        }),
+        |app, reply| report_failure(app, reply, "change notifications"),
+    );
+    Ok(())
+}
+
+fn leave(app: &mut App) -> Result<(), String> {
+    let chat_id = current_chat_id(app)?;
+    let req = LeaveChat::builder().chat_id(chat_id).build();
+    app.requests
+        .send_then(&req, |app, reply| report_failure(app, reply, "leave chat"));
+    Ok(())
+}
+
+fn pin(app: &mut App, pin: bool) -> Result<(), String> {
+    let (chat_id, message_ids) = selected_messages(app)?;
+    for message_id in message_ids {
+        selected::pin(app, chat_id, message_id, pin);
+    }
+    selected::clear(app, chat_id);
+    Ok(())
+}
+
+// Go to the chat's pinned message
+fn pinned(app: &mut App) -> Result<(), String> {
+    let chat_id = current_chat_id(app)?;
+    app.requests.send_json(
+        json!({"@type": "getChatPinnedMessage", "chat_id": chat_id}),
+        move |app, reply| match reply {
+            Ok(msg) => match msg["id"].as_i64() {
+                Some(message_id) => app.open_message(chat_id, message_id),
+                None => app.set_status("No pinned message".to_string()),
+            },
+            Err(e) => app.set_status(format!("Couldn't find pinned message: {}", e)),
+        },
+    );
+    Ok(())
+}
+
+fn forward(app: &mut App, to: &str) -> Result<(), String> {
+    if to.is_empty() {
+        return Err(usage("forward"));
+    }
+    let (from_chat_id, message_ids) = selected_messages(app)?;
+    let index = find_chat(app, to).ok_or_else(|| format!("No chat called {}", to))?;
+    let chat_id = app.chat_list.get_chat_id_by_index(index).unwrap_or(0);
+    selected::forward_to(app, from_chat_id, message_ids, chat_id);
+    selected::clear(app, from_chat_id);
+    Ok(())
+}
+
+// Write the messages retrieved so far in the current chat to a text file, oldest first,
+// or only those marked in visual mode if any are
+fn export(app: &mut App, file: &str) -> Result<(), String> {
+    let marked = selected::marked(app);
+    let users = app.users.lock().unwrap();
+    let cv = app.chat_list.chat_vec.lock().unwrap();
+    let chat = app
+        .chat_list
+        .selected_index()
+        .and_then(|i| cv.get(i))
+        .ok_or_else(|| "No chat open".to_string())?;
+    let path = if file.is_empty() {
+        let title: String = chat
+            .chat
+            .title()
+            .chars()
+            .map(|c| if c.is_alphanumeric() { c } else { '_' })
+            .collect();
+        format!("{}.txt", title)
+    } else {
+        file.to_string()
+    };
+
+    let mut lines = Vec::new();
+    let history = chat.history.lock().unwrap();
+    let mut messages: Vec<_> = history
+        .all()
+        .filter(|m| marked.as_ref().is_none_or(|ids| ids.contains(&m.msg.id())))
+        .collect();
+    messages.sort_by_key(|m| m.msg.id());
+    for msg in messages {
+        // Dates out of range are written as they were sent
+        let date = match Local.timestamp_opt(msg.msg.date(), 0).single() {
+            Some(d) => d.format("%Y-%m-%d %H:%M").to_string(),
+            None => msg.msg.date().to_string(),
+        };
+        let sender = match users.get(&msg.msg.sender_user_id()) {
+            Some(u) => content::full_name(u.u.first_name(), u.u.last_name()),
+            None => chat.chat.title().to_string(),
+        };
+        lines.push(format!(
+            "[{}] {}: {}",
+            date,
+            sender,
+            content::describe(msg, &users)
+        ));
+    }
+    let count = lines.len();
+    lines.push(String::new());
+    fs::write(&path, lines.join("\n")).map_err(|e| format!("Couldn't write {}: {}", path, e))?;
+    let chat_id = chat.chat.id();
+    drop(history);
+    drop(cv);
+    drop(users);
+    if marked.is_some() {
+        selected::clear(app, chat_id);
+    }
+    app.set_status(format!("Exported {} messages to {}", count, path));
+    Ok(())
+}
+
+// `:set` on its own shows the options, `:set name=value` sets one, and a boolean
+// option is turned on with `:set name` and off with `:set noname`
+fn set(app: &mut App, arg: &str) -> Result<(), String> {
+    let chat_id = current_chat_id(app).ok();
+    let parse_mode = chat_id.and_then(|id| app.chat_list.with_chat(id, |c| c.parse_mode));
+    if arg.is_empty() {
+        let vim = app.input_box.vim.lock().unwrap().enabled();
+        let mut shown = format!("{}vim_input", if vim { "" } else { "no" });
+        if let Some(mode) = parse_mode {
+            shown.push_str(&format!("  parse_mode={}", mode.name().to_lowercase()));
+        }
+        app.set_status(shown);
+        return Ok(());
+    }
+
+    let (name, value) = match arg.find('=') {
+        Some(i) => (&arg[..i], Some(&arg[i + 1..])),
+        None => (arg, None),
+    };
+    match (name, value) {
+        ("vim_input", None) => app.input_box.vim.lock().unwrap().set_enabled(true),
+        ("novim_input", None) => app.input_box.vim.lock().unwrap().set_enabled(false),
+        ("parse_mode", Some(value)) => {
+            let mode = ParseMode::from_name(value)
+                .ok_or_else(|| "parse_mode must be markdown, html or plain".to_string())?;
+            let chat_id = chat_id.ok_or_else(|| "No chat open".to_string())?;
+            app.chat_list.with_chat(chat_id, |c| c.parse_mode = mode);
+        }
+        _ if OPTIONS.contains(&name.trim_start_matches("no")) => {
+            return Err(format!("Can't set {} like that", arg))
+        }
+        _ => return Err(format!("Unknown option: {}", name)),
+    }
+    Ok(())
+}
+
+pub fn report_failure(app: &mut App, reply: Reply, what: &str) {
+    if let Err(e) = reply {
+        app.set_status(format!("Couldn't {}: {}", what, e));
+    }
+}
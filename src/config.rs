@@ -0,0 +1,619 @@
+use crate::keymap::Keymap;
+use crate::markup::ParseMode;
+use crate::tabs::LocalFilter;
+use serde::Deserialize;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use termion::event::Key;
+use tui::style::Color;
+
+// Command line argument overriding where the config file is read from
+pub const CONFIG_ARG: &str = "--config=";
+
+// Command line argument overriding which account's storage is used
+pub const ACCOUNT_ARG: &str = "--account=";
+
+// Replaced with the account name in storage directory settings
+const ACCOUNT_PLACEHOLDER: &str = "{account}";
+
+// Directory name used under the user's config and data directories
+const APP_DIR: &str = "teletui";
+const CONFIG_FILE: &str = "config.toml";
+
+// Colors handed out to users' names when no palette is configured
+const DEFAULT_PALETTE: [&str; 13] = [
+    "red",
+    "green",
+    "yellow",
+    "blue",
+    "magenta",
+    "cyan",
+    "gray",
+    "lightred",
+    "lightgreen",
+    "lightyellow",
+    "lightblue",
+    "lightmagenta",
+    "lightcyan",
+];
+
+// Everything read from the config file, validated and converted into the types
+// the rest of the app uses
+pub struct Config {
+    pub api_id: i64,
+    pub api_hash: String,
+
+    // May be empty, in which case the login screen starts out blank
+    pub phone_number: String,
+
+    // Name of the account in use, so that several can be logged in side by side
+    pub account: String,
+    pub database_directory: String,
+    pub files_directory: String,
+
+    // File `:` commands are kept in between runs
+    pub command_history: String,
+
+    // File holding the key for the local database, if it should be read from disk
+    pub encryption_key_file: Option<String>,
+
+    // Whether to ask for a passphrase to encrypt the local database when it isn't yet
+    pub encrypt: bool,
+
+    // Tdlib's own log verbosity
+    pub tdlib_verbosity: i64,
+
+    // Whether to print received updates to stderr
+    pub debug: bool,
+
+    // Seconds to wait for message from Tdlib before checking whether to exit
+    pub receive_timeout: f64,
+
+    // TUI box margin
+    pub margin: u16,
+
+    // Percentage of the width taken by the chat list
+    pub chat_list_width: u16,
+
+    // Percentage of the height taken by the input box
+    pub input_height: u16,
+
+    // Colors to cycle through for users' names
+    pub palette: Vec<Color>,
+
+    pub tick_rate: Duration,
+
+    // Least time between redraws, however often the screen changes
+    pub frame_time: Duration,
+    pub exit_key: Key,
+
+    // Keys bound to actions in normal and visual mode
+    pub keymap: Keymap,
+
+    // How typed messages are formatted, until changed for a chat
+    pub parse_mode: ParseMode,
+
+    // Whether Esc in the input box switches to vim-style normal mode before leaving it
+    pub vim_input: bool,
+
+    // Tabs of chats picked out here rather than by Telegram, after its chat folders
+    pub chat_filters: Vec<LocalFilter>,
+}
+
+// Problems with the config file, reported to the user instead of panicking
+pub enum ConfigError {
+    // No config file at the expected location
+    NotFound(PathBuf),
+    Io(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+
+    // A value was present but unusable. Holds the option name and what's wrong with it
+    Invalid(&'static str, String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::NotFound(p) => write!(
+                f,
+                "No config file found at {}. Create one (see config.example.toml) \
+                 or pass {}<path>",
+                p.display(),
+                CONFIG_ARG
+            ),
+            ConfigError::Io(p, e) => write!(f, "Couldn't read {}: {}", p.display(), e),
+            ConfigError::Parse(p, e) => write!(f, "Error in {}: {}", p.display(), e),
+            ConfigError::Invalid(opt, msg) => write!(f, "Invalid value for `{}`: {}", opt, msg),
+        }
+    }
+}
+
+// Layout of the config file itself. Every section is optional apart from `telegram`
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawConfig {
+    telegram: RawTelegram,
+    #[serde(default)]
+    storage: RawStorage,
+    #[serde(default)]
+    log: RawLog,
+    #[serde(default)]
+    layout: RawLayout,
+    #[serde(default)]
+    ui: RawUi,
+
+    // Checked when building the keymap, as its layout depends on the modes and boxes
+    #[serde(default)]
+    keys: toml::value::Table,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawTelegram {
+    api_id: i64,
+    api_hash: String,
+    #[serde(default)]
+    phone_number: String,
+    #[serde(default = "default_receive_timeout")]
+    receive_timeout: f64,
+}
+
+fn default_receive_timeout() -> f64 {
+    0.5
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawStorage {
+    account: String,
+    database_directory: String,
+    files_directory: String,
+    command_history: String,
+    encryption_key_file: Option<String>,
+    encrypt: bool,
+}
+
+impl Default for RawStorage {
+    fn default() -> RawStorage {
+        // e.g. ~/.local/share/teletui/{account}/database
+        let data_dir = dirs::data_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join(APP_DIR)
+            .join(ACCOUNT_PLACEHOLDER);
+        RawStorage {
+            account: "default".to_string(),
+            database_directory: data_dir.join("database").display().to_string(),
+            files_directory: data_dir.join("files").display().to_string(),
+            command_history: data_dir.join("command_history").display().to_string(),
+            encryption_key_file: None,
+            encrypt: false,
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct RawLog {
+    tdlib_verbosity: i64,
+    debug: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawLayout {
+    margin: u16,
+    chat_list_width: u16,
+    input_height: u16,
+}
+
+impl Default for RawLayout {
+    fn default() -> RawLayout {
+        RawLayout {
+            margin: 1,
+            chat_list_width: 20,
+            input_height: 15,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawUi {
+    palette: Vec<String>,
+    tick_rate: u64,
+    max_fps: u32,
+    exit_key: String,
+    parse_mode: String,
+    vim_input: bool,
+    chat_filters: Vec<String>,
+}
+
+impl Default for RawUi {
+    fn default() -> RawUi {
+        RawUi {
+            palette: DEFAULT_PALETTE.iter().map(|c| c.to_string()).collect(),
+            tick_rate: 250,
+            max_fps: 30,
+            exit_key: "F1".to_string(),
+            parse_mode: "markdown".to_string(),
+            vim_input: true,
+            chat_filters: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    // Load the config from `path` if given, otherwise from the XDG config directory.
+    // `account` overrides the account named in the file
+    pub fn load(path: Option<PathBuf>, account: Option<String>) -> Result<Config, ConfigError> {
+        let path = match path {
+            Some(p) => p,
+            None => default_path(),
+        };
+        if !path.exists() {
+            return Err(ConfigError::NotFound(path));
+        }
+        let contents = fs::read_to_string(&path).map_err(|e| ConfigError::Io(path.clone(), e))?;
+        Config::parse(&path, &contents, account)
+    }
+
+    pub(crate) fn parse(
+        path: &Path,
+        contents: &str,
+        account: Option<String>,
+    ) -> Result<Config, ConfigError> {
+        let raw: RawConfig =
+            toml::from_str(contents).map_err(|e| ConfigError::Parse(path.to_path_buf(), e))?;
+        let account = account.unwrap_or_else(|| raw.storage.account.clone());
+
+        if raw.telegram.api_id <= 0 {
+            return Err(invalid("telegram.api_id", "must be a positive number"));
+        }
+        if raw.telegram.api_hash.trim().is_empty() {
+            return Err(invalid("telegram.api_hash", "must not be empty"));
+        }
+        if account.is_empty() || account.contains(['/', '\\']) || account.starts_with('.') {
+            return Err(invalid(
+                "storage.account",
+                "must be a non-empty name usable as a directory",
+            ));
+        }
+        if raw.storage.database_directory.is_empty() {
+            return Err(invalid("storage.database_directory", "must not be empty"));
+        }
+        if raw.storage.files_directory.is_empty() {
+            return Err(invalid("storage.files_directory", "must not be empty"));
+        }
+        let encryption_key_file = raw.storage.encryption_key_file.as_deref().map(expand_home);
+        if let Some(f) = &encryption_key_file {
+            if !Path::new(f).is_file() {
+                return Err(invalid(
+                    "storage.encryption_key_file",
+                    &format!("{} is not a readable file", f),
+                ));
+            }
+        }
+        if !(0..=1024).contains(&raw.log.tdlib_verbosity) {
+            return Err(invalid("log.tdlib_verbosity", "must be between 0 and 1024"));
+        }
+        check_percentage("layout.chat_list_width", raw.layout.chat_list_width)?;
+        check_percentage("layout.input_height", raw.layout.input_height)?;
+        if raw.ui.palette.is_empty() {
+            return Err(invalid("ui.palette", "must contain at least one color"));
+        }
+        let palette = raw
+            .ui
+            .palette
+            .iter()
+            .map(|c| parse_color(c).ok_or_else(|| invalid("ui.palette", &unknown_color(c))))
+            .collect::<Result<Vec<Color>, ConfigError>>()?;
+        if raw.ui.tick_rate == 0 {
+            return Err(invalid("ui.tick_rate", "must be greater than 0"));
+        }
+        if raw.ui.max_fps == 0 {
+            return Err(invalid("ui.max_fps", "must be greater than 0"));
+        }
+        if raw.telegram.receive_timeout <= 0.0 {
+            return Err(invalid(
+                "telegram.receive_timeout",
+                "must be greater than 0",
+            ));
+        }
+        let exit_key = parse_key(&raw.ui.exit_key).ok_or_else(|| {
+            invalid(
+                "ui.exit_key",
+                &format!("unrecognized key \"{}\"", raw.ui.exit_key),
+            )
+        })?;
+
+        let keymap = Keymap::new(exit_key, &raw.keys).map_err(|e| invalid("keys", &e))?;
+
+        let parse_mode = ParseMode::from_name(&raw.ui.parse_mode).ok_or_else(|| {
+            invalid(
+                "ui.parse_mode",
+                "must be \"markdown\", \"html\" or \"plain\"",
+            )
+        })?;
+
+        let chat_filters = raw
+            .ui
+            .chat_filters
+            .iter()
+            .map(|f| {
+                LocalFilter::from_name(f).ok_or_else(|| {
+                    invalid(
+                        "ui.chat_filters",
+                        &format!(
+                            "unknown filter \"{}\"; use \"unread\", \"private\", \"groups\", \
+                             \"channels\", \"bots\" or \"muted\"",
+                            f
+                        ),
+                    )
+                })
+            })
+            .collect::<Result<Vec<LocalFilter>, ConfigError>>()?;
+
+        Ok(Config {
+            api_id: raw.telegram.api_id,
+            api_hash: raw.telegram.api_hash.trim().to_string(),
+            phone_number: raw.telegram.phone_number.trim().to_string(),
+            database_directory: storage_dir(&raw.storage.database_directory, &account),
+            files_directory: storage_dir(&raw.storage.files_directory, &account),
+            command_history: storage_dir(&raw.storage.command_history, &account),
+            account,
+            encryption_key_file,
+            encrypt: raw.storage.encrypt,
+            tdlib_verbosity: raw.log.tdlib_verbosity,
+            debug: raw.log.debug,
+            receive_timeout: raw.telegram.receive_timeout,
+            margin: raw.layout.margin,
+            chat_list_width: raw.layout.chat_list_width,
+            input_height: raw.layout.input_height,
+            palette,
+            tick_rate: Duration::from_millis(raw.ui.tick_rate),
+            frame_time: Duration::from_secs(1) / raw.ui.max_fps,
+            exit_key,
+            keymap,
+            parse_mode,
+            vim_input: raw.ui.vim_input,
+            chat_filters,
+        })
+    }
+}
+
+// Where the config file is looked for by default, e.g. ~/.config/teletui/config.toml
+pub fn default_path() -> PathBuf {
+    let base = dirs::config_dir().unwrap_or_else(|| PathBuf::from("."));
+    base.join(APP_DIR).join(CONFIG_FILE)
+}
+
+fn invalid(opt: &'static str, msg: &str) -> ConfigError {
+    ConfigError::Invalid(opt, msg.to_string())
+}
+
+fn unknown_color(c: &str) -> String {
+    format!("unknown color \"{}\"", c)
+}
+
+fn check_percentage(opt: &'static str, p: u16) -> Result<(), ConfigError> {
+    if p == 0 || p >= 100 {
+        return Err(invalid(opt, "must be a percentage between 1 and 99"));
+    }
+    Ok(())
+}
+
+// Fill in the account name and home directory in a storage directory setting
+fn storage_dir(template: &str, account: &str) -> String {
+    expand_home(&template.replace(ACCOUNT_PLACEHOLDER, account))
+}
+
+// Replace a leading `~` with the user's home directory
+fn expand_home(path: &str) -> String {
+    match (path.strip_prefix('~'), dirs::home_dir()) {
+        (Some(rest), Some(home)) => format!("{}{}", home.display(), rest),
+        _ => path.to_string(),
+    }
+}
+
+// Parse a color name (as in tui's `Color`, case insensitive) or a `#rrggbb` hex code
+pub fn parse_color(s: &str) -> Option<Color> {
+    if let Some(hex) = s.strip_prefix('#') {
+        // Not even a sign, which from_str_radix would take
+        if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        let v = u32::from_str_radix(hex, 16).ok()?;
+        return Some(Color::Rgb((v >> 16) as u8, (v >> 8) as u8, v as u8));
+    }
+    let c = match s.to_lowercase().replace(['_', '-', ' '], "").as_str() {
+        "black" => Color::Black,
+        "red" => Color::Red,
+        "green" => Color::Green,
+        "yellow" => Color::Yellow,
+        "blue" => Color::Blue,
+        "magenta" => Color::Magenta,
+        "cyan" => Color::Cyan,
+        "gray" | "grey" => Color::Gray,
+        "darkgray" | "darkgrey" => Color::DarkGray,
+        "lightred" => Color::LightRed,
+        "lightgreen" => Color::LightGreen,
+        "lightyellow" => Color::LightYellow,
+        "lightblue" => Color::LightBlue,
+        "lightmagenta" => Color::LightMagenta,
+        "lightcyan" => Color::LightCyan,
+        "white" => Color::White,
+        _ => return None,
+    };
+    Some(c)
+}
+
+// Parse a single key: a character, `F1`-`F12`, `C-x` for Ctrl, `A-x` for Alt,
+// or one of `Esc`, `Tab`, `Enter`, `Space`, `Backspace`, `Delete`, the arrow keys
+// (`Up` etc.), `Home`, `End`, `PageUp` and `PageDown`
+pub fn parse_key(s: &str) -> Option<Key> {
+    let mut chars = s.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Some(Key::Char(c));
+    }
+    if let Some(c) = s.strip_prefix("C-") {
+        return single_char(c).map(Key::Ctrl);
+    }
+    if let Some(c) = s.strip_prefix("A-") {
+        return single_char(c).map(Key::Alt);
+    }
+    if let Some(n) = s.strip_prefix('F') {
+        return match n.parse::<u8>() {
+            Ok(n) if (1..=12).contains(&n) => Some(Key::F(n)),
+            _ => None,
+        };
+    }
+    match s.to_lowercase().as_str() {
+        "esc" => Some(Key::Esc),
+        "tab" => Some(Key::Char('\t')),
+        "enter" => Some(Key::Char('\n')),
+        "space" => Some(Key::Char(' ')),
+        "backspace" => Some(Key::Backspace),
+        "delete" => Some(Key::Delete),
+        "up" => Some(Key::Up),
+        "down" => Some(Key::Down),
+        "left" => Some(Key::Left),
+        "right" => Some(Key::Right),
+        "home" => Some(Key::Home),
+        "end" => Some(Key::End),
+        "pageup" => Some(Key::PageUp),
+        "pagedown" => Some(Key::PageDown),
+        _ => None,
+    }
+}
+
+fn single_char(s: &str) -> Option<char> {
+    let mut chars = s.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(c),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TELEGRAM: &str = "[telegram]\napi_id = 1\napi_hash = \"hash\"\n";
+
+    fn parse(contents: &str) -> Result<Config, ConfigError> {
+        Config::parse(Path::new("config.toml"), contents, None)
+    }
+
+    // The option a config with `rest` after the telegram section is rejected for
+    fn rejected(rest: &str) -> &'static str {
+        match parse(&format!("{}{}", TELEGRAM, rest)) {
+            Err(ConfigError::Invalid(opt, _)) => opt,
+            Err(e) => panic!("{}: {}", rest, e),
+            Ok(_) => panic!("{}: accepted", rest),
+        }
+    }
+
+    #[test]
+    fn defaults() {
+        let config = match parse(TELEGRAM) {
+            Ok(c) => c,
+            Err(e) => panic!("{}", e),
+        };
+        assert_eq!(config.account, "default");
+        assert!(config.database_directory.ends_with("/default/database"));
+        assert_eq!(config.palette.len(), DEFAULT_PALETTE.len());
+        assert!(config.exit_key == Key::F(1));
+        assert_eq!(config.frame_time, Duration::from_secs(1) / 30);
+        assert!(!config.debug);
+
+        // The account given on the command line is the one used
+        let config = Config::parse(Path::new("config.toml"), TELEGRAM, Some("work".into()));
+        assert!(config.is_ok_and(|c| c.files_directory.ends_with("/work/files")));
+    }
+
+    #[test]
+    fn rejects_invalid_telegram_values() {
+        let telegram = |values: &str| match parse(&format!("[telegram]\n{}", values)) {
+            Err(ConfigError::Invalid(opt, _)) => opt,
+            _ => panic!("{} wasn't rejected", values),
+        };
+        assert_eq!(telegram("api_id = 0\napi_hash = \"h\""), "telegram.api_id");
+        assert_eq!(
+            telegram("api_id = 1\napi_hash = \" \""),
+            "telegram.api_hash"
+        );
+        assert_eq!(
+            telegram("api_id = 1\napi_hash = \"h\"\nreceive_timeout = 0.0"),
+            "telegram.receive_timeout"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        let cases = [
+            ("[storage]\naccount = \"\"", "storage.account"),
+            ("[storage]\naccount = \"../other\"", "storage.account"),
+            ("[storage]\naccount = \".hidden\"", "storage.account"),
+            (
+                "[storage]\ndatabase_directory = \"\"",
+                "storage.database_directory",
+            ),
+            (
+                "[storage]\nfiles_directory = \"\"",
+                "storage.files_directory",
+            ),
+            (
+                "[storage]\nencryption_key_file = \"/nonexistent/key\"",
+                "storage.encryption_key_file",
+            ),
+            ("[log]\ntdlib_verbosity = 1025", "log.tdlib_verbosity"),
+            ("[log]\ntdlib_verbosity = -1", "log.tdlib_verbosity"),
+            ("[layout]\nchat_list_width = 0", "layout.chat_list_width"),
+            ("[layout]\ninput_height = 100", "layout.input_height"),
+            ("[ui]\npalette = []", "ui.palette"),
+            ("[ui]\npalette = [\"red\", \"nope\"]", "ui.palette"),
+            ("[ui]\npalette = [\"#+12345\"]", "ui.palette"),
+            ("[ui]\ntick_rate = 0", "ui.tick_rate"),
+            ("[ui]\nmax_fps = 0", "ui.max_fps"),
+            ("[ui]\nexit_key = \"C-\"", "ui.exit_key"),
+            ("[ui]\nparse_mode = \"rtf\"", "ui.parse_mode"),
+            (
+                "[ui]\nchat_filters = [\"unread\", \"x\"]",
+                "ui.chat_filters",
+            ),
+            ("[keys.normal]\nj = \"fly\"", "keys"),
+        ];
+        for (rest, opt) in cases.iter() {
+            assert_eq!(rejected(rest), *opt, "{}", rest);
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_and_missing_fields() {
+        for contents in &[
+            "",
+            "[telegram]\napi_id = 1",
+            "[telegram]\napi_id = 1\napi_hash = \"h\"\napi_key = 2",
+            "[telegram]\napi_id = 1\napi_hash = \"h\"\n[ui]\ncolour = \"red\"",
+            "[telegram]\napi_id = \"one\"\napi_hash = \"h\"",
+        ] {
+            assert!(
+                matches!(parse(contents), Err(ConfigError::Parse(..))),
+                "{}",
+                contents
+            );
+        }
+    }
+
+    #[test]
+    fn parses_colors() {
+        assert!(parse_color("#ff8000") == Some(Color::Rgb(255, 128, 0)));
+        assert!(parse_color("Light_Red") == Some(Color::LightRed));
+        assert!(parse_color("dark grey") == Some(Color::DarkGray));
+        for bad in &["#ff800", "#ff80000", "#gg0000", "#+12345", "purple"] {
+            assert!(parse_color(bad).is_none(), "{}", bad);
+        }
+    }
+}
@@ -0,0 +1,428 @@
+use crate::format;
+use crate::{TMessage, TUser};
+use serde_json::Value;
+use std::collections::HashMap;
+use tui::style::Style;
+use tui::text::Span;
+
+// Describe the content of a message as styled text, with the formatting of its text or
+// caption, its spoilers hidden unless `reveal`. Otherwise as `describe`
+pub fn describe_styled(
+    msg: &TMessage,
+    users: &HashMap<i64, TUser>,
+    base: Style,
+    reveal: bool,
+) -> Vec<Span<'static>> {
+    let description = describe(msg, users);
+    let formatted = match msg.content["@type"].as_str() {
+        Some("messageText") => &msg.content["text"],
+        _ => &msg.content["caption"],
+    };
+    let text = str_field(formatted, "text");
+    if text.is_empty() {
+        return vec![Span::styled(description, base)];
+    }
+
+    // Text comes first in a description, captions last
+    let start = if msg.content["@type"] == "messageText" {
+        0
+    } else {
+        description.len() - text.len()
+    };
+    let end = start + text.len();
+    let mut spans = vec![Span::styled(description[..start].to_string(), base)];
+    spans.extend(format::styled(text, &formatted["entities"], base, reveal));
+    spans.push(Span::styled(description[end..].to_string(), base));
+    spans
+}
+
+// Describe the content of a message as text. Media is shown as a bracketed summary,
+// followed by its caption if it has one
+pub fn describe(msg: &TMessage, users: &HashMap<i64, TUser>) -> String {
+    let content = &msg.content;
+    let sender = msg.msg.sender_user_id();
+    let td_type = content["@type"].as_str().unwrap_or("");
+    match td_type {
+        "messageText" => text_with_web_page(content),
+        "messageAnimation" => {
+            let a = &content["animation"];
+            let summary = media_summary(
+                "GIF",
+                &[
+                    file_name(a),
+                    dimensions(a),
+                    duration(a),
+                    file_size(&a["animation"]),
+                ],
+            );
+            with_caption(summary, content)
+        }
+        "messageAudio" => {
+            let a = &content["audio"];
+            let title = match (str_field(a, "performer"), str_field(a, "title")) {
+                ("", "") => String::new(),
+                (p, "") => p.to_string(),
+                ("", t) => t.to_string(),
+                (p, t) => format!("{} - {}", p, t),
+            };
+            let summary = media_summary(
+                "Audio",
+                &[title, file_name(a), duration(a), file_size(&a["audio"])],
+            );
+            with_caption(summary, content)
+        }
+        "messageDocument" => {
+            let d = &content["document"];
+            let summary = media_summary("File", &[file_name(d), file_size(&d["document"])]);
+            with_caption(summary, content)
+        }
+        "messagePhoto" => {
+            // Sizes are listed smallest first
+            let largest = content["photo"]["sizes"]
+                .as_array()
+                .and_then(|s| s.last())
+                .cloned()
+                .unwrap_or_default();
+            let kind = if content["is_secret"].as_bool().unwrap_or(false) {
+                "Secret photo"
+            } else {
+                "Photo"
+            };
+            let summary =
+                media_summary(kind, &[dimensions(&largest), file_size(&largest["photo"])]);
+            with_caption(summary, content)
+        }
+        "messageSticker" => {
+            let emoji = str_field(&content["sticker"], "emoji");
+            media_summary("Sticker", &[emoji.to_string()])
+        }
+        "messageVideo" => {
+            let v = &content["video"];
+            let kind = if content["is_secret"].as_bool().unwrap_or(false) {
+                "Secret video"
+            } else {
+                "Video"
+            };
+            let summary = media_summary(
+                kind,
+                &[
+                    file_name(v),
+                    dimensions(v),
+                    duration(v),
+                    file_size(&v["video"]),
+                ],
+            );
+            with_caption(summary, content)
+        }
+        "messageVideoNote" => {
+            let v = &content["video_note"];
+            media_summary("Video message", &[duration(v), file_size(&v["video"])])
+        }
+        "messageVoiceNote" => {
+            let v = &content["voice_note"];
+            let summary = media_summary("Voice message", &[duration(v), file_size(&v["voice"])]);
+            with_caption(summary, content)
+        }
+        "messageExpiredPhoto" => "[Photo has expired]".to_string(),
+        "messageExpiredVideo" => "[Video has expired]".to_string(),
+        "messageLocation" => {
+            let kind = if content["live_period"].as_i64().unwrap_or(0) > 0 {
+                "Live location"
+            } else {
+                "Location"
+            };
+            media_summary(kind, &[coordinates(&content["location"])])
+        }
+        "messageVenue" => {
+            let v = &content["venue"];
+            media_summary(
+                "Venue",
+                &[
+                    str_field(v, "title").to_string(),
+                    str_field(v, "address").to_string(),
+                    coordinates(&v["location"]),
+                ],
+            )
+        }
+        "messageContact" => {
+            let c = &content["contact"];
+            let name = full_name(str_field(c, "first_name"), str_field(c, "last_name"));
+            media_summary("Contact", &[name, str_field(c, "phone_number").to_string()])
+        }
+        "messageDice" => {
+            let emoji = str_field(content, "emoji");
+            match content["value"].as_i64() {
+                // Still rolling
+                Some(0) | None => format!("[{}]", emoji),
+                Some(v) => format!("[{} rolled {}]", emoji, v),
+            }
+        }
+        "messageGame" => {
+            let g = &content["game"];
+            let summary = media_summary("Game", &[str_field(g, "title").to_string()]);
+            match str_field(g, "description") {
+                "" => summary,
+                d => format!("{}\n{}", summary, d),
+            }
+        }
+        "messagePoll" => poll(&content["poll"]),
+        "messageInvoice" => {
+            let price = money(content, "total_amount");
+            let summary =
+                media_summary("Invoice", &[str_field(content, "title").to_string(), price]);
+            match str_field(content, "description") {
+                "" => summary,
+                d => format!("{}\n{}", summary, d),
+            }
+        }
+        "messageCall" => {
+            let kind = if content["is_video"].as_bool().unwrap_or(false) {
+                "Video call"
+            } else {
+                "Call"
+            };
+            let reason = match content["discard_reason"]["@type"].as_str() {
+                Some("callDiscardReasonMissed") => "missed".to_string(),
+                Some("callDiscardReasonDeclined") => "declined".to_string(),
+                Some("callDiscardReasonDisconnected") => "disconnected".to_string(),
+                _ => duration(content),
+            };
+            media_summary(kind, &[reason])
+        }
+
+        // Service messages, saying what the sender did
+        "messageBasicGroupChatCreate" => format!(
+            "[created group \"{}\" with {}]",
+            str_field(content, "title"),
+            user_list(&content["member_user_ids"], users)
+        ),
+        "messageSupergroupChatCreate" => {
+            format!("[created \"{}\"]", str_field(content, "title"))
+        }
+        "messageChatChangeTitle" => {
+            format!("[changed the title to \"{}\"]", str_field(content, "title"))
+        }
+        "messageChatChangePhoto" => "[changed the chat photo]".to_string(),
+        "messageChatDeletePhoto" => "[removed the chat photo]".to_string(),
+        "messageChatAddMembers" => match content["member_user_ids"].as_array() {
+            Some(ids) if ids.len() == 1 && ids[0].as_i64() == Some(sender) => {
+                "[joined the group]".to_string()
+            }
+            _ => format!("[added {}]", user_list(&content["member_user_ids"], users)),
+        },
+        "messageChatJoinByLink" => "[joined via invite link]".to_string(),
+        "messageChatDeleteMember" if content["user_id"].as_i64() == Some(sender) => {
+            "[left the group]".to_string()
+        }
+        "messageChatDeleteMember" => {
+            format!("[removed {}]", user_name(&content["user_id"], users))
+        }
+        "messageChatUpgradeTo" => "[upgraded the group to a supergroup]".to_string(),
+        "messageChatUpgradeFrom" => format!(
+            "[upgraded group \"{}\" to a supergroup]",
+            str_field(content, "title")
+        ),
+        "messagePinMessage" => "[pinned a message]".to_string(),
+        "messageScreenshotTaken" => "[took a screenshot]".to_string(),
+        "messageChatSetTtl" => match content["ttl"].as_i64().unwrap_or(0) {
+            0 => "[disabled the self-destruct timer]".to_string(),
+            ttl => format!("[set the self-destruct timer to {}]", format_duration(ttl)),
+        },
+        "messageCustomServiceAction" => format!("[{}]", str_field(content, "text")),
+        "messageGameScore" => format!("[scored {}]", content["score"].as_i64().unwrap_or(0)),
+        "messagePaymentSuccessful" => {
+            format!("[paid {}]", money(content, "total_amount"))
+        }
+        "messageContactRegistered" => "[joined Telegram]".to_string(),
+        "messageWebsiteConnected" => format!(
+            "[logged in to {} with Telegram]",
+            str_field(content, "domain_name")
+        ),
+        "messageVoiceChatStarted" => "[started a voice chat]".to_string(),
+        "messageVoiceChatEnded" => format!("[voice chat ended, {}]", duration(content)),
+        "messageInviteVoiceChatParticipants" => format!(
+            "[invited {} to the voice chat]",
+            user_list(&content["user_ids"], users)
+        ),
+        "messageProximityAlertTriggered" => "[is nearby]".to_string(),
+        "messagePassportDataSent" => "[sent Telegram Passport data]".to_string(),
+        "messageUnsupported" => "[Not supported by this version of Telegram]".to_string(),
+        "" => "[Unknown message]".to_string(),
+        t => format!("[{}]", t.trim_start_matches("message")),
+    }
+}
+
+// Text of a text message, followed by a preview of the web page it links to, if any
+fn text_with_web_page(content: &Value) -> String {
+    let text = str_field(&content["text"], "text");
+    let wp = &content["web_page"];
+    if !wp.is_object() {
+        return text.to_string();
+    }
+    // Older versions of Tdlib send the description as a plain string
+    let description = wp["description"]
+        .as_str()
+        .unwrap_or_else(|| str_field(&wp["description"], "text"));
+    let preview: Vec<&str> = [
+        str_field(wp, "site_name"),
+        str_field(wp, "title"),
+        description,
+    ]
+    .iter()
+    .copied()
+    .filter(|s| !s.is_empty())
+    .collect();
+    if preview.is_empty() {
+        return text.to_string();
+    }
+    format!("{}\n{}", text, preview.join("\n"))
+}
+
+// A poll's question and options, with the share of votes each got if any are in
+fn poll(p: &Value) -> String {
+    let kind = match p["type"]["@type"].as_str() {
+        Some("pollTypeQuiz") => "Quiz",
+        _ => "Poll",
+    };
+    let mut details = vec![str_field(p, "question").to_string()];
+    if p["is_closed"].as_bool().unwrap_or(false) {
+        details.push("closed".to_string());
+    }
+    let mut s = media_summary(kind, &details);
+    let total = p["total_voter_count"].as_i64().unwrap_or(0);
+    for option in p["options"].as_array().into_iter().flatten() {
+        let chosen = if option["is_chosen"].as_bool().unwrap_or(false) {
+            "*"
+        } else {
+            "-"
+        };
+        s.push_str(&format!("\n{} {}", chosen, str_field(option, "text")));
+        if total > 0 {
+            s.push_str(&format!(
+                " ({}%)",
+                option["vote_percentage"].as_i64().unwrap_or(0)
+            ));
+        }
+    }
+    s
+}
+
+// Bracketed summary of media, e.g. "[Video clip.mp4, 1280x720, 0:42, 3.1 MB]"
+fn media_summary(kind: &str, details: &[String]) -> String {
+    let details: Vec<&str> = details
+        .iter()
+        .map(String::as_str)
+        .filter(|d| !d.is_empty())
+        .collect();
+    if details.is_empty() {
+        format!("[{}]", kind)
+    } else {
+        format!("[{} {}]", kind, details.join(", "))
+    }
+}
+
+// Append the message's caption, if it has one, on a line of its own
+fn with_caption(summary: String, content: &Value) -> String {
+    match str_field(&content["caption"], "text") {
+        "" => summary,
+        caption => format!("{}\n{}", summary, caption),
+    }
+}
+
+fn str_field<'a>(obj: &'a Value, field: &str) -> &'a str {
+    obj[field].as_str().unwrap_or("")
+}
+
+fn file_name(obj: &Value) -> String {
+    str_field(obj, "file_name").to_string()
+}
+
+fn dimensions(obj: &Value) -> String {
+    match (obj["width"].as_i64(), obj["height"].as_i64()) {
+        (Some(w), Some(h)) if w > 0 && h > 0 => format!("{}x{}", w, h),
+        _ => String::new(),
+    }
+}
+
+fn duration(obj: &Value) -> String {
+    match obj["duration"].as_i64() {
+        Some(d) if d > 0 => format_duration(d),
+        _ => String::new(),
+    }
+}
+
+// Size of a Tdlib file, or its expected size when not yet known exactly
+fn file_size(file: &Value) -> String {
+    let size = match file["size"].as_i64() {
+        Some(s) if s > 0 => s,
+        _ => file["expected_size"].as_i64().unwrap_or(0),
+    };
+    if size > 0 {
+        format_size(size)
+    } else {
+        String::new()
+    }
+}
+
+fn coordinates(location: &Value) -> String {
+    match (
+        location["latitude"].as_f64(),
+        location["longitude"].as_f64(),
+    ) {
+        (Some(lat), Some(lon)) => format!("{:.5}, {:.5}", lat, lon),
+        _ => String::new(),
+    }
+}
+
+// An amount of money given in the smallest units of its currency, e.g. cents
+fn money(obj: &Value, field: &str) -> String {
+    let amount = obj[field].as_i64().unwrap_or(0);
+    let currency = str_field(obj, "currency");
+    format!("{}.{:02} {}", amount / 100, amount % 100, currency)
+}
+
+pub fn full_name(first: &str, last: &str) -> String {
+    format!("{} {}", first, last).trim().to_string()
+}
+
+fn user_name(id: &Value, users: &HashMap<i64, TUser>) -> String {
+    match id.as_i64().and_then(|id| users.get(&id)) {
+        Some(u) => full_name(u.u.first_name(), u.u.last_name()),
+        None => "Unknown User".to_string(),
+    }
+}
+
+fn user_list(ids: &Value, users: &HashMap<i64, TUser>) -> String {
+    let names: Vec<String> = ids
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|id| user_name(id, users))
+        .collect();
+    names.join(", ")
+}
+
+// e.g. "0:42", or "1:02:03" for an hour or more
+fn format_duration(seconds: i64) -> String {
+    let (h, m, s) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if h > 0 {
+        format!("{}:{:02}:{:02}", h, m, s)
+    } else {
+        format!("{}:{:02}", m, s)
+    }
+}
+
+// e.g. "512 B", "3.1 MB"
+fn format_size(bytes: i64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
@@ -0,0 +1,509 @@
+use termion::event::Key;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+// Text being written in the input box, edited a grapheme at a time so that emoji and
+// combined characters are never split. Lines are scrolled rather than wrapped
+#[derive(Clone, Default)]
+pub struct Editor {
+    text: String,
+
+    // Byte offset in `text` of the cursor, always on a grapheme boundary
+    cursor: usize,
+
+    // Column to return to when moving up or down through shorter lines
+    goal_column: Option<usize>,
+
+    // First line and column shown
+    scroll_row: usize,
+    scroll_column: usize,
+
+    // Earlier states to go back to, most recent last, and states undone since the last change
+    undo: Vec<Snapshot>,
+    redo: Vec<Snapshot>,
+}
+
+// Most changes that can be undone
+const UNDO_LIMIT: usize = 100;
+
+#[derive(Clone, PartialEq)]
+struct Snapshot {
+    text: String,
+    cursor: usize,
+}
+
+// Kinds of character, for moving by word
+#[derive(PartialEq)]
+enum CharClass {
+    Space,
+    Word,
+    Punctuation,
+}
+
+fn class(grapheme: &str) -> CharClass {
+    match grapheme.chars().next() {
+        Some(c) if c.is_whitespace() => CharClass::Space,
+        Some(c) if c.is_alphanumeric() || c == '_' => CharClass::Word,
+        _ => CharClass::Punctuation,
+    }
+}
+
+impl Editor {
+    pub fn new() -> Editor {
+        Editor::default()
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    // Replace the text, leaving the cursor at its end
+    pub fn set_text(&mut self, text: &str) {
+        self.text = text.to_string();
+        self.cursor = self.text.len();
+        self.goal_column = None;
+    }
+
+    pub fn clear(&mut self) {
+        self.set_text("");
+    }
+
+    // Move the cursor to byte offset `pos`, or as near before it as is a grapheme boundary
+    pub fn set_cursor(&mut self, pos: usize) {
+        let pos = pos.min(self.text.len());
+        self.cursor = self
+            .text
+            .grapheme_indices(true)
+            .map(|(i, _)| i)
+            .take_while(|i| *i <= pos)
+            .last()
+            .unwrap_or(0);
+        if pos == self.text.len() {
+            self.cursor = pos;
+        }
+        self.goal_column = None;
+    }
+
+    pub fn insert_str(&mut self, s: &str) {
+        self.text.insert_str(self.cursor, s);
+        self.cursor += s.len();
+        self.goal_column = None;
+    }
+
+    pub fn insert_char(&mut self, c: char) {
+        self.insert_str(c.encode_utf8(&mut [0; 4]));
+    }
+
+    // Remove the text between byte offsets `from` and `to`, in either order, returning it
+    pub fn delete_range(&mut self, from: usize, to: usize) -> String {
+        let (start, end) = (from.min(to), from.max(to));
+        let removed: String = self.text.drain(start..end).collect();
+        self.cursor = start;
+        self.goal_column = None;
+        removed
+    }
+
+    // Remember the current state, to return to with `undo` once it's been changed
+    pub fn checkpoint(&mut self) {
+        let now = self.snapshot();
+        if self.undo.last() != Some(&now) {
+            self.undo.push(now);
+            if self.undo.len() > UNDO_LIMIT {
+                self.undo.remove(0);
+            }
+        }
+        self.redo.clear();
+    }
+
+    // Go back to the last checkpoint that differs from now, returning whether there was one
+    pub fn undo(&mut self) -> bool {
+        let now = self.snapshot();
+        while let Some(before) = self.undo.pop() {
+            if before.text != now.text {
+                self.redo.push(now);
+                self.restore(before);
+                return true;
+            }
+        }
+        false
+    }
+
+    // Reapply the last change undone, returning whether there was one
+    pub fn redo(&mut self) -> bool {
+        match self.redo.pop() {
+            Some(after) => {
+                self.undo.push(self.snapshot());
+                self.restore(after);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            text: self.text.clone(),
+            cursor: self.cursor,
+        }
+    }
+
+    fn restore(&mut self, s: Snapshot) {
+        self.text = s.text;
+        self.set_cursor(s.cursor);
+    }
+
+    // Handle a key press, returning whether it did anything. Enter isn't handled here,
+    // as it's what sends the message
+    pub fn handle_key(&mut self, key: &Key) -> bool {
+        match key {
+            Key::Alt('\r') | Key::Alt('\n') => self.insert_char('\n'),
+            Key::Char('\n') => return false,
+            Key::Char(c) if !c.is_control() => self.insert_char(*c),
+
+            Key::Backspace | Key::Ctrl('h') => {
+                let start = self.prev_boundary(self.cursor);
+                self.delete_range(start, self.cursor);
+            }
+            Key::Delete | Key::Ctrl('d') => {
+                let end = self.next_boundary(self.cursor);
+                self.delete_range(self.cursor, end);
+            }
+            Key::Ctrl('w') | Key::Alt('\x7f') => {
+                let start = self.word_start_before(self.cursor);
+                self.delete_range(start, self.cursor);
+            }
+            Key::Alt('d') => {
+                let end = self.word_end_after(self.cursor);
+                self.delete_range(self.cursor, end);
+            }
+            Key::Ctrl('u') => {
+                let start = self.line_start(self.cursor);
+                self.delete_range(start, self.cursor);
+            }
+            Key::Ctrl('k') => {
+                let end = self.line_end(self.cursor);
+                self.delete_range(self.cursor, end);
+            }
+
+            Key::Left | Key::Ctrl('b') => self.set_cursor(self.prev_boundary(self.cursor)),
+            Key::Right | Key::Ctrl('f') => self.set_cursor(self.next_boundary(self.cursor)),
+            Key::Alt('b') => self.set_cursor(self.word_start_before(self.cursor)),
+            Key::Alt('f') => self.set_cursor(self.word_end_after(self.cursor)),
+            Key::Home | Key::Ctrl('a') => self.set_cursor(self.line_start(self.cursor)),
+            Key::End | Key::Ctrl('e') => self.set_cursor(self.line_end(self.cursor)),
+            Key::Up | Key::Ctrl('p') => self.move_lines(-1),
+            Key::Down | Key::Ctrl('n') => self.move_lines(1),
+            Key::Alt('<') => self.set_cursor(0),
+            Key::Alt('>') => self.set_cursor(self.text.len()),
+            _ => return false,
+        }
+        true
+    }
+
+    // Start of the grapheme before byte offset `pos`
+    pub fn prev_boundary(&self, pos: usize) -> usize {
+        self.text[..pos]
+            .grapheme_indices(true)
+            .next_back()
+            .map_or(0, |(i, _)| i)
+    }
+
+    // End of the grapheme at byte offset `pos`
+    pub fn next_boundary(&self, pos: usize) -> usize {
+        self.text[pos..]
+            .graphemes(true)
+            .next()
+            .map_or(pos, |g| pos + g.len())
+    }
+
+    // Start of the word before `pos`, skipping any whitespace first
+    pub fn word_start_before(&self, pos: usize) -> usize {
+        let mut graphemes = self.text[..pos].grapheme_indices(true).rev().peekable();
+        while graphemes
+            .next_if(|(_, g)| class(g) == CharClass::Space)
+            .is_some()
+        {}
+        let word_class = match graphemes.peek() {
+            Some((_, g)) => class(g),
+            None => return 0,
+        };
+        let mut start = pos;
+        while let Some((i, _)) = graphemes.next_if(|(_, g)| class(g) == word_class) {
+            start = i;
+        }
+        start
+    }
+
+    // Start of the next word after `pos`, as with vim's `w`. Stops at empty lines
+    pub fn next_word_start(&self, pos: usize) -> usize {
+        let mut graphemes = self.text[pos..].grapheme_indices(true).peekable();
+        if let Some((_, g)) = graphemes.next() {
+            let start_class = class(g);
+            if start_class != CharClass::Space {
+                while graphemes
+                    .next_if(|(_, g)| class(g) == start_class)
+                    .is_some()
+                {}
+            }
+        }
+        let mut newline = self.text[pos..].starts_with('\n');
+        while let Some((i, g)) = graphemes.peek() {
+            if class(g) != CharClass::Space {
+                return pos + i;
+            }
+            if *g == "\n" {
+                if newline {
+                    return pos + i;
+                }
+                newline = true;
+            }
+            graphemes.next();
+        }
+        self.text.len()
+    }
+
+    // End of the word after `pos`, skipping any whitespace first
+    pub fn word_end_after(&self, pos: usize) -> usize {
+        let mut graphemes = self.text[pos..].grapheme_indices(true).peekable();
+        while graphemes
+            .next_if(|(_, g)| class(g) == CharClass::Space)
+            .is_some()
+        {}
+        let word_class = match graphemes.peek() {
+            Some((_, g)) => class(g),
+            None => return self.text.len(),
+        };
+        let mut end = pos;
+        while let Some((i, g)) = graphemes.next_if(|(_, g)| class(g) == word_class) {
+            end = pos + i + g.len();
+        }
+        end
+    }
+
+    pub fn line_start(&self, pos: usize) -> usize {
+        self.text[..pos].rfind('\n').map_or(0, |i| i + 1)
+    }
+
+    pub fn line_end(&self, pos: usize) -> usize {
+        self.text[pos..]
+            .find('\n')
+            .map_or(self.text.len(), |i| pos + i)
+    }
+
+    // First character on the line containing `pos` that isn't whitespace, or its end
+    pub fn first_non_blank(&self, pos: usize) -> usize {
+        let start = self.line_start(pos);
+        let line = &self.text[start..self.line_end(pos)];
+        start + line.len() - line.trim_start().len()
+    }
+
+    // Display column of byte offset `pos` within its line
+    fn column(&self, pos: usize) -> usize {
+        self.text[self.line_start(pos)..pos].width()
+    }
+
+    // Byte offset in the line starting at `line_start` that's at, or just before, `column`
+    fn at_column(&self, line_start: usize, column: usize) -> usize {
+        let line = &self.text[line_start..self.line_end(line_start)];
+        let mut width = 0;
+        for (i, g) in line.grapheme_indices(true) {
+            width += g.width();
+            if width > column {
+                return line_start + i;
+            }
+        }
+        line_start + line.len()
+    }
+
+    // Move the cursor `n` lines down, or up if negative, keeping to the same column
+    pub fn move_lines(&mut self, n: i64) {
+        let goal = self.goal_column.unwrap_or_else(|| self.column(self.cursor));
+        let mut line_start = self.line_start(self.cursor);
+        for _ in 0..n.abs() {
+            if n < 0 {
+                if line_start == 0 {
+                    break;
+                }
+                line_start = self.line_start(line_start - 1);
+            } else {
+                let end = self.line_end(line_start);
+                if end == self.text.len() {
+                    break;
+                }
+                line_start = end + 1;
+            }
+        }
+        self.cursor = self.at_column(line_start, goal);
+        self.goal_column = Some(goal);
+    }
+
+    // The lines to show in a box `width` columns by `height` rows, scrolled so that the
+    // cursor is in view, along with the cursor's column and row within the box
+    pub fn view(&mut self, width: usize, height: usize) -> (Vec<String>, (usize, usize)) {
+        let (width, height) = (width.max(1), height.max(1));
+        let row = self.text[..self.cursor].matches('\n').count();
+        let column = self.column(self.cursor);
+        if row < self.scroll_row {
+            self.scroll_row = row;
+        } else if row >= self.scroll_row + height {
+            self.scroll_row = row + 1 - height;
+        }
+        if column < self.scroll_column {
+            self.scroll_column = column;
+        } else if column >= self.scroll_column + width {
+            self.scroll_column = column + 1 - width;
+        }
+
+        let lines = self
+            .text
+            .split('\n')
+            .skip(self.scroll_row)
+            .take(height)
+            .map(|line| {
+                // Only whole graphemes that fit between the scrolled edges
+                let mut start = 0;
+                let mut shown = String::new();
+                for g in line.graphemes(true) {
+                    let end = start + g.width();
+                    if start >= self.scroll_column && end <= self.scroll_column + width {
+                        shown.push_str(g);
+                    } else if start < self.scroll_column && end > self.scroll_column {
+                        // Half of a wide character scrolled off the left
+                        shown.push_str(&" ".repeat(end - self.scroll_column));
+                    }
+                    start = end;
+                }
+                shown
+            })
+            .collect();
+        (lines, (column - self.scroll_column, row - self.scroll_row))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An editor holding `text` with the cursor at its end
+    fn editor(text: &str) -> Editor {
+        let mut e = Editor::new();
+        e.set_text(text);
+        e
+    }
+
+    fn press(e: &mut Editor, keys: &[Key]) {
+        for key in keys {
+            assert!(e.handle_key(key), "{:?}", key);
+        }
+    }
+
+    #[test]
+    fn moves_by_grapheme() {
+        // e and a combining accent, a family joined with zero width joiners, a flag
+        let (accented, family, flag) = ("e\u{301}", "👩\u{200d}👩\u{200d}👧", "🇳🇿");
+        let mut e = editor(&format!("{}{}{}", accented, family, flag));
+        press(&mut e, &[Key::Left]);
+        assert_eq!(e.cursor(), accented.len() + family.len());
+        press(&mut e, &[Key::Left, Key::Left]);
+        assert_eq!(e.cursor(), 0);
+        press(&mut e, &[Key::Left]);
+        assert_eq!(e.cursor(), 0);
+        press(&mut e, &[Key::Right]);
+        assert_eq!(e.cursor(), accented.len());
+
+        // Within a grapheme, the cursor goes to its start
+        e.set_cursor(accented.len() + 4);
+        assert_eq!(e.cursor(), accented.len());
+    }
+
+    #[test]
+    fn deletes_by_grapheme() {
+        let mut e = editor("ae\u{301}👩\u{200d}👧b");
+        e.set_cursor(1);
+        press(&mut e, &[Key::Delete]);
+        assert_eq!(e.text(), "a👩\u{200d}👧b");
+        press(&mut e, &[Key::Right, Key::Backspace]);
+        assert_eq!(e.text(), "ab");
+        press(&mut e, &[Key::Backspace, Key::Backspace]);
+        assert_eq!(e.text(), "b");
+        assert_eq!(e.cursor(), 0);
+    }
+
+    #[test]
+    fn deletes_by_word_and_line() {
+        let mut e = editor("one two.three  ");
+        press(&mut e, &[Key::Ctrl('w')]);
+        assert_eq!(e.text(), "one two.");
+        press(&mut e, &[Key::Ctrl('w')]);
+        assert_eq!(e.text(), "one two");
+
+        press(&mut e, &[Key::Ctrl('a'), Key::Alt('d')]);
+        assert_eq!(e.text(), " two");
+
+        let mut e = editor("first\nsecond line");
+        e.set_cursor(9);
+        press(&mut e, &[Key::Ctrl('k')]);
+        assert_eq!(e.text(), "first\nsec");
+        press(&mut e, &[Key::Ctrl('u')]);
+        assert_eq!(e.text(), "first\n");
+    }
+
+    #[test]
+    fn word_motions() {
+        let e = editor("foo.bar  baz\n\nqux");
+        assert_eq!(e.next_word_start(0), 3);
+        assert_eq!(e.next_word_start(3), 4);
+        assert_eq!(e.next_word_start(4), 9);
+        // An empty line counts as a word
+        assert_eq!(e.next_word_start(9), 13);
+        assert_eq!(e.word_end_after(4), 7);
+        assert_eq!(e.word_start_before(9), 4);
+    }
+
+    #[test]
+    fn keeps_column_between_lines() {
+        // 日 is two columns wide
+        let mut e = editor("abcdef\n日x\nabcdef");
+        e.set_cursor(2);
+        e.move_lines(1);
+        assert_eq!(&e.text()[e.cursor()..], "x\nabcdef");
+
+        // The column is kept through shorter lines
+        e.set_cursor(4);
+        e.move_lines(1);
+        assert_eq!(&e.text()[e.cursor()..], "\nabcdef");
+        e.move_lines(1);
+        assert_eq!(e.cursor(), e.text().len() - 2);
+        e.move_lines(-5);
+        assert_eq!(e.cursor(), 4);
+    }
+
+    #[test]
+    fn undo_and_redo() {
+        let mut e = editor("a");
+        e.checkpoint();
+        e.insert_str("b");
+        e.checkpoint();
+        e.insert_str("c");
+        assert!(e.undo());
+        assert_eq!(e.text(), "ab");
+        assert!(e.undo());
+        assert_eq!(e.text(), "a");
+        assert!(!e.undo());
+        assert!(e.redo());
+        assert_eq!(e.text(), "ab");
+
+        // A new change leaves nothing to redo
+        e.checkpoint();
+        e.insert_str("d");
+        assert!(!e.redo());
+    }
+}
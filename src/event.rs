@@ -1,18 +1,22 @@
 #[allow(dead_code)]
 use std::io;
 use std::sync::mpsc;
-use std::sync::{
-    atomic::{AtomicBool, Ordering},
-    Arc,
-};
 use std::thread;
 use std::time::Duration;
 
-use termion::event::Key;
+use termion::event::{self as term_event, Key};
 use termion::input::TermRead;
 
+// Sent by terminals around pasted text, once bracketed paste is enabled
+pub const ENABLE_BRACKETED_PASTE: &str = "\x1b[?2004h";
+pub const DISABLE_BRACKETED_PASTE: &str = "\x1b[?2004l";
+const PASTE_START: &[u8] = b"\x1b[200~";
+const PASTE_END: &[u8] = b"\x1b[201~";
+
 pub enum Event<I> {
     Input(I),
+    // Text pasted all at once, rather than typed
+    Paste(String),
     Tick,
 }
 
@@ -22,20 +26,17 @@ pub enum Event<I> {
 pub struct Events {
     rx: mpsc::Receiver<Event<Key>>,
     input_handle: thread::JoinHandle<()>,
-    ignore_exit_key: Arc<AtomicBool>,
     tick_handle: thread::JoinHandle<()>,
 }
 
 #[derive(Debug, Clone, Copy)]
 pub struct Config {
-    pub exit_key: Key,
     pub tick_rate: Duration,
 }
 
 impl Default for Config {
     fn default() -> Config {
         Config {
-            exit_key: Key::Char('q'),
             tick_rate: Duration::from_millis(250),
         }
     }
@@ -49,21 +50,41 @@ impl Events {
 
     pub fn with_config(config: Config) -> Events {
         let (tx, rx) = mpsc::channel();
-        let ignore_exit_key = Arc::new(AtomicBool::new(false));
         let input_handle = {
             let tx = tx.clone();
-            let ignore_exit_key = ignore_exit_key.clone();
             thread::spawn(move || {
                 let stdin = io::stdin();
-                for evt in stdin.keys() {
-                    if let Ok(key) = evt {
-                        if let Err(err) = tx.send(Event::Input(key)) {
-                            eprintln!("{}", err);
-                            return;
+                // Text pasted so far, while in the middle of a paste
+                let mut paste: Option<String> = None;
+                for evt in stdin.events() {
+                    let evt = match evt {
+                        Ok(e) => e,
+                        Err(_) => continue,
+                    };
+                    let key = match (evt, paste.as_mut()) {
+                        (term_event::Event::Unsupported(seq), _) if seq == PASTE_START => {
+                            paste = Some(String::new());
+                            continue;
+                        }
+                        (term_event::Event::Unsupported(seq), Some(_)) if seq == PASTE_END => {
+                            let text = paste.take().unwrap_or_default();
+                            if tx.send(Event::Paste(text)).is_err() {
+                                return;
+                            }
+                            continue;
                         }
-                        if !ignore_exit_key.load(Ordering::Relaxed) && key == config.exit_key {
-                            return;
+                        (term_event::Event::Key(key), Some(text)) => {
+                            if let Key::Char(c) = key {
+                                text.push(c);
+                            }
+                            continue;
                         }
+                        (term_event::Event::Key(key), None) => key,
+                        _ => continue,
+                    };
+                    if let Err(err) = tx.send(Event::Input(key)) {
+                        eprintln!("{}", err);
+                        return;
                     }
                 }
             })
@@ -78,7 +99,6 @@ impl Events {
         };
         Events {
             rx,
-            ignore_exit_key,
             input_handle,
             tick_handle,
         }
@@ -87,12 +107,4 @@ impl Events {
     pub fn next(&self) -> Result<Event<Key>, mpsc::RecvError> {
         self.rx.recv()
     }
-
-    pub fn disable_exit_key(&mut self) {
-        self.ignore_exit_key.store(true, Ordering::Relaxed);
-    }
-
-    pub fn enable_exit_key(&mut self) {
-        self.ignore_exit_key.store(false, Ordering::Relaxed);
-    }
 }
@@ -0,0 +1,290 @@
+use crate::content;
+use crate::editor::Editor;
+use crate::selected;
+use crate::App;
+use std::cmp::Reverse;
+use termion::event::Key;
+use tui::{
+    backend::Backend,
+    layout::Rect,
+    style::{Color, Modifier, Style},
+    text::{Span, Spans},
+    widgets::{Block, Borders, Clear, List, ListItem, ListState, Paragraph},
+    Frame,
+};
+
+// Popup for switching chats by typing part of a chat's title, or of the username or
+// name of someone in it. Letters only need to appear in order, e.g. `rsta` finds
+// "Rustaceans"
+pub struct Finder {
+    query: Editor,
+
+    // Chats matching the query, best first
+    results: Vec<Found>,
+    selected: usize,
+
+    // What choosing a chat does
+    purpose: Purpose,
+}
+
+enum Purpose {
+    Open,
+
+    // Forward messages to the chat chosen
+    Forward {
+        from_chat_id: i64,
+        message_ids: Vec<i64>,
+    },
+}
+
+pub struct Found {
+    chat_id: i64,
+    title: String,
+
+    // What matched, when not the title
+    matched: Option<String>,
+}
+
+// Extra score for matching characters that start a word, or follow the previous match
+const WORD_START_BONUS: i64 = 8;
+const CONSECUTIVE_BONUS: i64 = 4;
+
+impl Finder {
+    pub fn new(app: &App) -> Finder {
+        Finder {
+            query: Editor::new(),
+            results: find(app, ""),
+            selected: 0,
+            purpose: Purpose::Open,
+        }
+    }
+
+    // A finder choosing where to forward messages
+    pub fn forwarding(app: &App, from_chat_id: i64, message_ids: Vec<i64>) -> Finder {
+        Finder {
+            purpose: Purpose::Forward {
+                from_chat_id,
+                message_ids,
+            },
+            ..Finder::new(app)
+        }
+    }
+}
+
+// Handle a key while the finder is open
+pub fn handle_key(app: &mut App, key: Key) {
+    let mut finder = app.finder.lock().unwrap();
+    let state = match finder.as_mut() {
+        Some(f) => f,
+        None => return,
+    };
+    match key {
+        Key::Esc => *finder = None,
+        Key::Char('\n') => {
+            let chat_id = state.results.get(state.selected).map(|f| f.chat_id);
+            let purpose = finder.take().map(|f| f.purpose);
+            drop(finder);
+            let chat_id = match chat_id {
+                Some(id) => id,
+                None => return,
+            };
+            if let Some(Purpose::Forward {
+                from_chat_id,
+                message_ids,
+            }) = purpose
+            {
+                selected::forward_to(app, from_chat_id, message_ids, chat_id);
+                return selected::clear(app, from_chat_id);
+            }
+            let index = {
+                let cv = app.chat_list.chat_vec.lock().unwrap();
+                cv.iter().position(|c| c.chat.id() == chat_id)
+            };
+            if let Some(i) = index {
+                app.open_chat(i);
+            }
+        }
+        Key::Up | Key::Ctrl('p') | Key::Ctrl('k') => {
+            state.selected = state.selected.saturating_sub(1);
+        }
+        Key::Down | Key::Ctrl('n') | Key::Ctrl('j') => {
+            if state.selected + 1 < state.results.len() {
+                state.selected += 1;
+            }
+        }
+
+        // A single line, so no newlines
+        Key::Alt('\r') | Key::Alt('\n') => {}
+        _ => {
+            if !state.query.handle_key(&key) {
+                return;
+            }
+            // Chats and users are locked to search them, so not alongside the finder
+            let query = state.query.text().to_string();
+            drop(finder);
+            let results = find(app, &query);
+            if let Some(state) = app.finder.lock().unwrap().as_mut() {
+                state.results = results;
+                state.selected = 0;
+            }
+        }
+    }
+}
+
+// Chats matching `query`, best match first and otherwise most recent first
+fn find(app: &App, query: &str) -> Vec<Found> {
+    let query: Vec<char> = query.to_lowercase().chars().collect();
+    let users = app.users.lock().unwrap();
+    let basic_groups = app.basic_groups.lock().unwrap();
+    let cv = app.chat_list.chat_vec.lock().unwrap();
+
+    let mut scored = Vec::new();
+    for chat in cv.iter() {
+        let title = chat.chat.title().to_string();
+
+        // Who's in the chat, for matching their names and usernames
+        let type_ = chat.chat.type_();
+        let member_ids: Vec<i64> = if let Some(private) = type_.as_private() {
+            vec![private.user_id()]
+        } else if let Some(secret) = type_.as_secret() {
+            vec![secret.user_id()]
+        } else if let Some(group) = type_.as_basic_group() {
+            basic_groups
+                .get(&group.basic_group_id())
+                .map(|g| g.full_info.members().iter().map(|m| m.user_id()).collect())
+                .unwrap_or_default()
+        } else {
+            Vec::new()
+        };
+        let mut others = Vec::new();
+        for u in member_ids.iter().filter_map(|id| users.get(id)) {
+            if !u.u.username().is_empty() {
+                others.push(format!("@{}", u.u.username()));
+            }
+            others.push(content::full_name(u.u.first_name(), u.u.last_name()));
+        }
+
+        // The title is preferred when it matches as well as anything else
+        let mut best = score(&query, &title).map(|s| (s, None));
+        for other in others {
+            if let Some(s) = score(&query, &other) {
+                if best.as_ref().is_none_or(|(b, _)| s > *b) {
+                    best = Some((s, Some(other)));
+                }
+            }
+        }
+        if let Some((s, matched)) = best {
+            let recency = *chat.last_msg_date.lock().unwrap();
+            scored.push((
+                s,
+                recency,
+                Found {
+                    chat_id: chat.chat.id(),
+                    title,
+                    matched,
+                },
+            ));
+        }
+    }
+    scored.sort_by_key(|(s, recency, _)| Reverse((*s, *recency)));
+    scored.into_iter().map(|(_, _, f)| f).collect()
+}
+
+// How well `text` matches `query`, which must be lowercase, if every character of the
+// query appears in it in order. Matches are taken as early as possible
+fn score(query: &[char], text: &str) -> Option<i64> {
+    let mut total = 0;
+    let mut q = query.iter().peekable();
+    let mut prev: Option<char> = None;
+    let mut prev_matched = false;
+    for c in text.chars() {
+        let lower = c.to_lowercase().next().unwrap_or(c);
+        let matched = q.peek() == Some(&&lower);
+        if matched {
+            q.next();
+            total += 1;
+            if prev.is_none_or(|p| !p.is_alphanumeric()) {
+                total += WORD_START_BONUS;
+            }
+            if prev_matched {
+                total += CONSECUTIVE_BONUS;
+            }
+        }
+        prev = Some(c);
+        prev_matched = matched;
+    }
+    if q.peek().is_some() {
+        return None;
+    }
+    Some(total)
+}
+
+// Draw the finder over the middle of the screen
+pub fn render_finder<B: Backend>(f: &mut Frame<B>, finder: &mut Finder) {
+    let size = f.size();
+    let width = (size.width * 3 / 5).max(30).min(size.width);
+    let height = (finder.results.len() as u16 + 5)
+        .min(size.height * 3 / 5)
+        .max(6)
+        .min(size.height);
+    let area = Rect::new(
+        size.x + (size.width - width) / 2,
+        size.y + (size.height - height) / 2,
+        width,
+        height,
+    );
+    f.render_widget(Clear, area);
+    let title = match finder.purpose {
+        Purpose::Open => "Switch chat",
+        Purpose::Forward { .. } => "Forward to",
+    };
+    f.render_widget(Block::default().title(title).borders(Borders::ALL), area);
+
+    // Query on the first line, results below
+    let inner = Rect::new(
+        area.x + 1,
+        area.y + 1,
+        area.width.saturating_sub(2),
+        area.height.saturating_sub(2),
+    );
+    let (lines, (column, _)) = finder.query.view(inner.width.saturating_sub(2) as usize, 1);
+    let query = Paragraph::new(format!(
+        "> {}",
+        lines.into_iter().next().unwrap_or_default()
+    ));
+    f.render_widget(query, Rect::new(inner.x, inner.y, inner.width, 1));
+    f.set_cursor(inner.x + 2 + column as u16, inner.y);
+
+    let items: Vec<ListItem> = finder
+        .results
+        .iter()
+        .map(|found| {
+            let mut spans = vec![Span::raw(found.title.clone())];
+            if let Some(m) = &found.matched {
+                spans.push(Span::styled(
+                    format!("  {}", m),
+                    Style::default().fg(Color::DarkGray),
+                ));
+            }
+            ListItem::new(Spans::from(spans))
+        })
+        .collect();
+    let list_area = Rect::new(
+        inner.x,
+        inner.y + 2,
+        inner.width,
+        inner.height.saturating_sub(2),
+    );
+    if items.is_empty() {
+        f.render_widget(Paragraph::new("No matching chats"), list_area);
+        return;
+    }
+    let list = List::new(items).highlight_style(
+        Style::default()
+            .fg(Color::Blue)
+            .add_modifier(Modifier::BOLD | Modifier::REVERSED),
+    );
+    let mut state = ListState::default();
+    state.select(Some(finder.selected));
+    f.render_stateful_widget(list, list_area, &mut state);
+}
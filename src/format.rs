@@ -0,0 +1,343 @@
+use serde_json::Value;
+use tui::style::{Color, Modifier, Style};
+use tui::text::{Span, Spans};
+use unicode_width::UnicodeWidthChar;
+
+// Style `text` according to the Telegram entities over it, given as in a Tdlib
+// formattedText, showing spoilers if `reveal`. Entity offsets and lengths are in UTF-16
+// code units
+pub fn styled(text: &str, entities: &Value, base: Style, reveal: bool) -> Vec<Span<'static>> {
+    let chars: Vec<char> = text.chars().collect();
+
+    // UTF-16 offset at which each character starts
+    let mut starts = Vec::with_capacity(chars.len());
+    let mut offset = 0;
+    for c in &chars {
+        starts.push(offset);
+        offset += c.len_utf16();
+    }
+    let char_index = |utf16: usize| starts.iter().take_while(|&&s| s < utf16).count();
+
+    let mut styles = vec![base; chars.len()];
+    // Targets of links with their own text, shown after the text that ends at each index
+    let mut links: Vec<(usize, String)> = Vec::new();
+    for entity in entities.as_array().into_iter().flatten() {
+        let offset = entity["offset"].as_u64().unwrap_or(0) as usize;
+        let length = entity["length"].as_u64().unwrap_or(0) as usize;
+        let (start, end) = (char_index(offset), char_index(offset + length));
+        let td_type = entity["type"]["@type"].as_str().unwrap_or("");
+        for style in &mut styles[start..end] {
+            *style = entity_style(*style, td_type, reveal);
+        }
+        if td_type == "textEntityTypeTextUrl" {
+            if let Some(url) = entity["type"]["url"].as_str() {
+                links.push((end, url.to_string()));
+            }
+        }
+    }
+    links.sort_by_key(|(end, _)| *end);
+
+    // Join characters of the same style back into spans
+    let mut spans = Vec::new();
+    let mut run = String::new();
+    let mut links = links.into_iter().peekable();
+    for (i, (c, style)) in chars.iter().zip(&styles).enumerate() {
+        if i > 0 && styles[i - 1] != *style {
+            spans.push(Span::styled(std::mem::take(&mut run), styles[i - 1]));
+        }
+        run.push(*c);
+        while let Some((_, url)) = links.next_if(|(end, _)| *end == i + 1) {
+            spans.push(Span::styled(std::mem::take(&mut run), *style));
+            spans.push(Span::styled(format!(" <{}>", url), link_target_style(base)));
+        }
+    }
+    if let Some(style) = styles.last() {
+        if !run.is_empty() {
+            spans.push(Span::styled(run, *style));
+        }
+    }
+    spans
+}
+
+// Style of text covered by an entity of type `td_type`, on top of `style`
+fn entity_style(style: Style, td_type: &str, reveal: bool) -> Style {
+    match td_type {
+        "textEntityTypeBold" => style.add_modifier(Modifier::BOLD),
+        "textEntityTypeItalic" => style.add_modifier(Modifier::ITALIC),
+        "textEntityTypeUnderline" => style.add_modifier(Modifier::UNDERLINED),
+        "textEntityTypeStrikethrough" => style.add_modifier(Modifier::CROSSED_OUT),
+        "textEntityTypeCode" | "textEntityTypePre" | "textEntityTypePreCode" => {
+            style.fg(Color::Yellow)
+        }
+        // Hidden until revealed, then still set apart
+        "textEntityTypeSpoiler" if reveal => style.bg(Color::DarkGray),
+        "textEntityTypeSpoiler" => style.fg(Color::DarkGray).bg(Color::DarkGray),
+        "textEntityTypeMention"
+        | "textEntityTypeMentionName"
+        | "textEntityTypeHashtag"
+        | "textEntityTypeCashtag"
+        | "textEntityTypeBotCommand" => style.fg(Color::Cyan),
+        "textEntityTypeUrl"
+        | "textEntityTypeTextUrl"
+        | "textEntityTypeEmailAddress"
+        | "textEntityTypePhoneNumber"
+        | "textEntityTypeBankCardNumber" => {
+            style.fg(Color::Blue).add_modifier(Modifier::UNDERLINED)
+        }
+        _ => style,
+    }
+}
+
+fn link_target_style(base: Style) -> Style {
+    base.fg(Color::DarkGray)
+}
+
+// Wrap styled text to `width` columns, breaking between words where possible. Runs
+// of a style broken across lines keep their style on each. Newlines always break
+pub fn wrap(spans: &[Span], width: usize) -> Vec<Spans<'static>> {
+    let width = width.max(1);
+    let cells: Vec<(char, Style)> = spans
+        .iter()
+        .flat_map(|s| s.content.chars().map(move |c| (c, s.style)))
+        .collect();
+
+    let mut lines = Vec::new();
+    for paragraph in cells.split(|(c, _)| *c == '\n') {
+        let mut line: Vec<(char, Style)> = Vec::new();
+        let mut line_width = 0;
+        for word in words(paragraph) {
+            let word_width: usize = word.iter().map(|(c, _)| char_width(*c)).sum();
+            let is_space = word[0].0.is_whitespace();
+            if line_width + word_width <= width {
+                line.extend_from_slice(word);
+                line_width += word_width;
+                continue;
+            }
+
+            // Doesn't fit. Spaces are dropped at the break, and words start a new line,
+            // split further only if too long for a line of their own
+            if line_width > 0 {
+                lines.push(to_spans(&line));
+                line.clear();
+                line_width = 0;
+            }
+            if is_space {
+                continue;
+            }
+            for &(c, style) in word {
+                let w = char_width(c);
+                if line_width + w > width && line_width > 0 {
+                    lines.push(to_spans(&line));
+                    line.clear();
+                    line_width = 0;
+                }
+                line.push((c, style));
+                line_width += w;
+            }
+        }
+        lines.push(to_spans(&line));
+    }
+    lines
+}
+
+// Patch `highlight` over every occurrence in `spans` of each word of `query`, ignoring case
+pub fn highlight(spans: Vec<Span<'static>>, query: &str, highlight: Style) -> Vec<Span<'static>> {
+    let mut cells: Vec<(char, Style)> = spans
+        .iter()
+        .flat_map(|s| s.content.chars().map(move |c| (c, s.style)))
+        .collect();
+    let lower: Vec<char> = cells.iter().map(|(c, _)| fold(*c)).collect();
+    for word in query.split_whitespace() {
+        let word: Vec<char> = word.chars().map(fold).collect();
+        let mut i = 0;
+        while i + word.len() <= lower.len() {
+            if lower[i..i + word.len()] == word[..] {
+                for cell in &mut cells[i..i + word.len()] {
+                    cell.1 = cell.1.patch(highlight);
+                }
+                i += word.len();
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    let mut joined = Vec::new();
+    let mut run = String::new();
+    for (i, (c, style)) in cells.iter().enumerate() {
+        if i > 0 && cells[i - 1].1 != *style {
+            joined.push(Span::styled(std::mem::take(&mut run), cells[i - 1].1));
+        }
+        run.push(*c);
+    }
+    if let Some((_, style)) = cells.last() {
+        joined.push(Span::styled(run, *style));
+    }
+    joined
+}
+
+// A character as compared when searching, one for one so that positions still line up
+fn fold(c: char) -> char {
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+// Cut `text` down to `width` columns, ending it with an ellipsis if anything was cut
+pub fn truncate(text: &str, width: usize) -> String {
+    let total: usize = text.chars().map(char_width).sum();
+    if total <= width {
+        return text.to_string();
+    }
+    let mut cut = String::new();
+    let mut cut_width = 0;
+    for c in text.chars() {
+        if cut_width + char_width(c) + 1 > width {
+            break;
+        }
+        cut.push(c);
+        cut_width += char_width(c);
+    }
+    cut.push('…');
+    cut
+}
+
+// Split into alternating runs of whitespace and non-whitespace
+fn words(cells: &[(char, Style)]) -> Vec<&[(char, Style)]> {
+    let mut words = Vec::new();
+    let mut start = 0;
+    for i in 1..=cells.len() {
+        if i == cells.len() || cells[i].0.is_whitespace() != cells[start].0.is_whitespace() {
+            words.push(&cells[start..i]);
+            start = i;
+        }
+    }
+    words
+}
+
+fn char_width(c: char) -> usize {
+    c.width().unwrap_or(0)
+}
+
+// Join a line of styled characters into spans, without trailing whitespace
+fn to_spans(line: &[(char, Style)]) -> Spans<'static> {
+    let end = line
+        .iter()
+        .rposition(|(c, _)| !c.is_whitespace())
+        .map_or(0, |i| i + 1);
+    let mut spans = Vec::new();
+    let mut run = String::new();
+    for (i, (c, style)) in line[..end].iter().enumerate() {
+        if i > 0 && line[i - 1].1 != *style {
+            spans.push(Span::styled(std::mem::take(&mut run), line[i - 1].1));
+        }
+        run.push(*c);
+    }
+    if end > 0 {
+        spans.push(Span::styled(run, line[end - 1].1));
+    }
+    Spans::from(spans)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    // The text and style of each span on each line
+    fn lines(wrapped: Vec<Spans>) -> Vec<Vec<(String, Style)>> {
+        wrapped
+            .into_iter()
+            .map(|line| {
+                line.0
+                    .into_iter()
+                    .map(|s| (s.content.to_string(), s.style))
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn plain(text: &str) -> Vec<Span<'static>> {
+        vec![Span::raw(text.to_string())]
+    }
+
+    #[test]
+    fn wraps_across_styled_runs() {
+        let bold = Style::default().add_modifier(Modifier::BOLD);
+        let red = Style::default().fg(Color::Red);
+        let spans = vec![
+            Span::styled("hello ", bold),
+            Span::styled("wor", red),
+            Span::raw("ld again"),
+        ];
+        assert_eq!(
+            lines(wrap(&spans, 8)),
+            vec![
+                vec![("hello".to_string(), bold)],
+                vec![
+                    ("wor".to_string(), red),
+                    ("ld".to_string(), Style::default())
+                ],
+                vec![("again".to_string(), Style::default())],
+            ]
+        );
+
+        // A run split over lines keeps its style on each
+        let spans = vec![Span::raw("a "), Span::styled("bcdefgh", red)];
+        assert_eq!(
+            lines(wrap(&spans, 4)),
+            vec![
+                vec![("a".to_string(), Style::default())],
+                vec![("bcde".to_string(), red)],
+                vec![("fgh".to_string(), red)],
+            ]
+        );
+    }
+
+    #[test]
+    fn wraps_long_words_wide_characters_and_newlines() {
+        let text = |wrapped: Vec<Spans>| -> Vec<String> {
+            lines(wrapped)
+                .into_iter()
+                .map(|l| l.into_iter().map(|(s, _)| s).collect())
+                .collect()
+        };
+        assert_eq!(
+            text(wrap(&plain("abcdefghij"), 4)),
+            vec!["abcd", "efgh", "ij"]
+        );
+        // Each of these takes two columns
+        assert_eq!(text(wrap(&plain("日本語"), 5)), vec!["日本", "語"]);
+        assert_eq!(text(wrap(&plain("a\n\nb c"), 1)), vec!["a", "", "b", "c"]);
+        assert_eq!(text(wrap(&plain("ab"), 0)), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn spoilers_hidden_unless_revealed() {
+        let entities = json!([{
+            "@type": "textEntity", "offset": 2, "length": 6,
+            "type": {"@type": "textEntityTypeSpoiler"},
+        }]);
+        let base = Style::default().fg(Color::White);
+        let hidden = styled("a secret", &entities, base, false);
+        assert_eq!(hidden[1].content, "secret");
+        assert_eq!(hidden[1].style.fg, hidden[1].style.bg);
+
+        let shown = styled("a secret", &entities, base, true);
+        assert_eq!(shown[1].content, "secret");
+        assert_eq!(shown[1].style.fg, Some(Color::White));
+        assert_ne!(shown[1].style, base);
+    }
+
+    #[test]
+    fn offsets_are_utf16() {
+        // 😀 takes two UTF-16 code units
+        let entities = json!([{
+            "@type": "textEntity", "offset": 3, "length": 2,
+            "type": {"@type": "textEntityTypeBold"},
+        }]);
+        let spans = styled("😀 😀b", &entities, Style::default(), false);
+        let bold = Style::default().add_modifier(Modifier::BOLD);
+        assert_eq!(spans[1].content, "😀");
+        assert_eq!(spans[1].style, bold);
+        assert_eq!(spans[2].content, "b");
+    }
+}
@@ -0,0 +1,219 @@
+use crate::TMessage;
+use std::cmp::Reverse;
+
+// Messages retrieved in a chat, kept as segments of consecutive messages. A chat opened at
+// an arbitrary message (e.g. a search hit) starts a new segment around it, separate from
+// the newest messages, and segments merge once retrieving more of one reaches another
+pub struct History {
+    // Ordered newest first, never overlapping
+    segments: Vec<Segment>,
+
+    // Index in `segments` of the one being shown
+    current: usize,
+}
+
+#[derive(Default)]
+struct Segment {
+    // Newest first
+    messages: Vec<TMessage>,
+
+    // Whether this reaches the chat's newest message, and its first
+    latest: bool,
+    first: bool,
+}
+
+impl Segment {
+    fn newest_id(&self) -> Option<i64> {
+        self.messages.first().map(|m| m.msg.id())
+    }
+
+    fn oldest_id(&self) -> Option<i64> {
+        self.messages.last().map(|m| m.msg.id())
+    }
+
+    fn contains(&self, message_id: i64) -> bool {
+        self.messages.iter().any(|m| m.msg.id() == message_id)
+    }
+
+    // Whether messages with IDs from `oldest` to `newest` would overlap this
+    fn overlaps(&self, oldest: i64, newest: i64) -> bool {
+        match (self.oldest_id(), self.newest_id()) {
+            (Some(o), Some(n)) => o <= newest && n >= oldest,
+            _ => false,
+        }
+    }
+
+    fn absorb(&mut self, other: Segment) {
+        self.messages.extend(other.messages);
+        self.latest |= other.latest;
+        self.first |= other.first;
+    }
+}
+
+impl History {
+    // An empty history, showing the newest messages once they're retrieved
+    pub fn new() -> History {
+        History {
+            segments: vec![Segment {
+                latest: true,
+                ..Segment::default()
+            }],
+            current: 0,
+        }
+    }
+
+    fn shown(&self) -> &Segment {
+        &self.segments[self.current]
+    }
+
+    // Messages of the segment being shown, newest first
+    pub fn messages(&self) -> &[TMessage] {
+        &self.shown().messages
+    }
+
+    // Every message retrieved, newest first, with gaps where not everything in between has been
+    pub fn all(&self) -> impl Iterator<Item = &TMessage> {
+        self.segments.iter().flat_map(|s| s.messages.iter())
+    }
+
+    // Whether the segment shown goes back to the chat's first message, and whether it
+    // reaches its newest
+    pub fn shows_first(&self) -> bool {
+        self.shown().first
+    }
+
+    pub fn shows_latest(&self) -> bool {
+        self.shown().latest
+    }
+
+    // IDs of the oldest and newest messages shown, or 0 when there are none, which asks
+    // Tdlib for the newest
+    pub fn oldest_id(&self) -> i64 {
+        self.shown().oldest_id().unwrap_or(0)
+    }
+
+    pub fn newest_id(&self) -> i64 {
+        self.shown().newest_id().unwrap_or(0)
+    }
+
+    // Show the segment with the newest messages, starting one if there's none yet
+    pub fn show_latest(&mut self) {
+        match self.segments.iter().position(|s| s.latest) {
+            Some(i) => self.current = i,
+            None => {
+                self.segments.insert(
+                    0,
+                    Segment {
+                        latest: true,
+                        ..Segment::default()
+                    },
+                );
+                self.current = 0;
+            }
+        }
+    }
+
+    // Show the segment holding message `message_id`, returning its index there, if it's
+    // been retrieved
+    pub fn show(&mut self, message_id: i64) -> Option<usize> {
+        let segment = self.segments.iter().position(|s| s.contains(message_id))?;
+        self.current = segment;
+        self.index_of(message_id)
+    }
+
+    // Index of message `message_id` in the segment shown
+    pub fn index_of(&self, message_id: i64) -> Option<usize> {
+        self.messages()
+            .iter()
+            .position(|m| m.msg.id() == message_id)
+    }
+
+    // Message `message_id`, in whichever segment it's in
+    pub fn get_mut(&mut self, message_id: i64) -> Option<&mut TMessage> {
+        self.segments
+            .iter_mut()
+            .flat_map(|s| s.messages.iter_mut())
+            .find(|m| m.msg.id() == message_id)
+    }
+
+    // Forget messages that have been deleted
+    pub fn remove(&mut self, message_ids: &[i64]) {
+        for segment in self.segments.iter_mut() {
+            segment
+                .messages
+                .retain(|m| !message_ids.contains(&m.msg.id()));
+        }
+    }
+
+    // Add a message just sent or received, which is the chat's newest
+    pub fn add_newest(&mut self, msg: TMessage) {
+        match self.segments.iter_mut().find(|s| s.latest) {
+            Some(s) => s.messages.insert(0, msg),
+            None => {
+                self.segments.insert(
+                    0,
+                    Segment {
+                        messages: vec![msg],
+                        latest: true,
+                        first: false,
+                    },
+                );
+                self.current += 1;
+            }
+        }
+    }
+
+    // Add messages retrieved with GetChatHistory from `from_message_id` with `offset`,
+    // merging them with the segments they touch
+    pub fn add(&mut self, from_message_id: i64, offset: i64, messages: Vec<TMessage>) {
+        let ids: Vec<i64> = messages.iter().map(|m| m.msg.id()).collect();
+
+        // Messages from 0 are the newest. Otherwise the reply runs on from the message it
+        // started at, which may not itself be included
+        let from = if from_message_id == 0 {
+            None
+        } else {
+            Some(from_message_id)
+        };
+        let bounds = ids.iter().copied().chain(from);
+        let (oldest, newest) = match (bounds.clone().min(), bounds.max()) {
+            (Some(o), Some(n)) => (o, n),
+            _ => (0, 0),
+        };
+
+        // Nothing older than where it started means that's the first message, and
+        // nothing newer the latest
+        let latest = match from {
+            None => true,
+            Some(f) => offset < 0 && !ids.iter().any(|&id| id > f),
+        };
+        let first = offset >= 0 && from.map_or(ids.is_empty(), |f| !ids.iter().any(|&id| id < f));
+
+        let mut merged = Segment {
+            messages,
+            latest,
+            first,
+        };
+        let mut shows_merged = false;
+        let mut kept = Vec::new();
+        for (i, segment) in self.segments.drain(..).enumerate() {
+            let touches = segment.overlaps(oldest, newest) || (latest && segment.latest);
+            if touches {
+                shows_merged |= i == self.current;
+                merged.absorb(segment);
+            } else {
+                kept.push((i == self.current, segment));
+            }
+        }
+        merged.messages.sort_by_key(|m| Reverse(m.msg.id()));
+        merged.messages.dedup_by_key(|m| m.msg.id());
+        // Nothing at all around a message jumped to isn't worth keeping
+        if shows_merged || merged.latest || !merged.messages.is_empty() {
+            kept.push((shows_merged, merged));
+        }
+
+        kept.sort_by_key(|(_, s)| Reverse((s.latest, s.newest_id())));
+        self.current = kept.iter().position(|(shown, _)| *shown).unwrap_or(0);
+        self.segments = kept.into_iter().map(|(_, s)| s).collect();
+    }
+}
@@ -0,0 +1,579 @@
+use crate::config::parse_key;
+use crate::{InputMode, TBlocks};
+use std::collections::HashMap;
+use termion::event::Key;
+use tui::{
+    backend::Backend,
+    layout::Rect,
+    style::{Color, Modifier, Style},
+    text::{Span, Spans},
+    widgets::{Block, Borders, Clear, Paragraph},
+    Frame,
+};
+
+// Something a key can be bound to
+#[derive(Clone, Copy, PartialEq)]
+pub enum Action {
+    Quit,
+    NextBlock,
+    Insert,
+    Visual,
+    Help,
+    ScrollDown,
+    ScrollUp,
+    PageDown,
+    PageUp,
+    Top,
+    Bottom,
+    Reply,
+    Edit,
+    Command,
+    FindChat,
+    PinChat,
+    ArchiveChat,
+    ShowArchive,
+    NextTab,
+    PrevTab,
+    Search,
+    NextMatch,
+    PrevMatch,
+    SearchAll,
+    GoToReplied,
+    Delete,
+    Forward,
+    Copy,
+    TogglePin,
+    Details,
+    Mark,
+    SelectRange,
+}
+
+// Each action's name in the config file and description in the help overlay, in the
+// order the overlay lists them
+const ACTIONS: [(Action, &str, &str); 32] = [
+    (Action::Quit, "quit", "Quit"),
+    (Action::NextBlock, "next_block", "Move to the next box"),
+    (Action::Insert, "insert", "Write a message"),
+    (Action::Visual, "visual", "Select messages"),
+    (Action::Help, "help", "Show this help"),
+    (
+        Action::Command,
+        "command",
+        "Type a command, e.g. :open <chat>",
+    ),
+    (Action::FindChat, "find_chat", "Switch to a chat by name"),
+    (
+        Action::PinChat,
+        "pin_chat",
+        "Pin the selected chat at the top, or unpin it",
+    ),
+    (
+        Action::ArchiveChat,
+        "archive_chat",
+        "Archive the selected chat, or unarchive it",
+    ),
+    (
+        Action::ShowArchive,
+        "show_archive",
+        "Show archived chats, or go back",
+    ),
+    (Action::NextTab, "next_tab", "Show the next tab of chats"),
+    (
+        Action::PrevTab,
+        "prev_tab",
+        "Show the previous tab of chats",
+    ),
+    (Action::Search, "search", "Search the open chat"),
+    (
+        Action::NextMatch,
+        "next_match",
+        "Go to the next older match",
+    ),
+    (
+        Action::PrevMatch,
+        "prev_match",
+        "Go to the next newer match",
+    ),
+    (Action::SearchAll, "search_all", "Search every chat"),
+    (Action::ScrollDown, "scroll_down", "Move down"),
+    (Action::ScrollUp, "scroll_up", "Move up"),
+    (Action::PageDown, "page_down", "Move down a page"),
+    (Action::PageUp, "page_up", "Move up a page"),
+    (Action::Top, "top", "Go to the top"),
+    (Action::Bottom, "bottom", "Go to the bottom"),
+    (
+        Action::Mark,
+        "mark",
+        "Add the message to the selection, or take it out",
+    ),
+    (
+        Action::SelectRange,
+        "select_range",
+        "Start or end a range of selected messages",
+    ),
+    (Action::Reply, "reply", "Reply to the selected message"),
+    (Action::Edit, "edit", "Edit the selected message"),
+    (
+        Action::GoToReplied,
+        "go_to_replied",
+        "Go to the message the selected one replies to",
+    ),
+    (Action::Delete, "delete", "Delete the selected message"),
+    (
+        Action::Forward,
+        "forward",
+        "Forward the selected message to another chat",
+    ),
+    (Action::Copy, "copy", "Copy the selected message's text"),
+    (
+        Action::TogglePin,
+        "pin",
+        "Pin or unpin the selected message",
+    ),
+    (
+        Action::Details,
+        "details",
+        "Show the selected message's details",
+    ),
+];
+
+// Bound unless the config file says otherwise, as (mode, keys, action)
+const DEFAULTS: [(&str, &str, &str); 40] = [
+    ("normal", "<Tab>", "next_block"),
+    ("normal", "i", "insert"),
+    ("normal", "v", "visual"),
+    ("normal", "?", "help"),
+    ("normal", ":", "command"),
+    ("normal", "<C-p>", "find_chat"),
+    ("normal", "P", "pin_chat"),
+    ("normal", "A", "archive_chat"),
+    ("normal", "ga", "show_archive"),
+    ("normal", "gt", "next_tab"),
+    ("normal", "gT", "prev_tab"),
+    ("normal", "/", "search"),
+    ("normal", "n", "next_match"),
+    ("normal", "N", "prev_match"),
+    ("normal", "g/", "search_all"),
+    ("normal", "j", "scroll_down"),
+    ("normal", "k", "scroll_up"),
+    ("normal", "<C-f>", "page_down"),
+    ("normal", "<C-b>", "page_up"),
+    ("normal", "gg", "top"),
+    ("normal", "G", "bottom"),
+    ("visual", "j", "scroll_down"),
+    ("visual", "k", "scroll_up"),
+    ("visual", "<Space>", "mark"),
+    ("visual", "V", "select_range"),
+    ("visual", "r", "reply"),
+    ("visual", "e", "edit"),
+    ("visual", "gr", "go_to_replied"),
+    ("visual", "d", "delete"),
+    ("visual", "f", "forward"),
+    ("visual", "y", "copy"),
+    ("visual", "p", "pin"),
+    ("visual", "i", "details"),
+    ("visual", "?", "help"),
+    ("visual", ":", "command"),
+    ("visual", "<C-p>", "find_chat"),
+    ("visual", "/", "search"),
+    ("visual", "n", "next_match"),
+    ("visual", "N", "prev_match"),
+    ("visual", "g/", "search_all"),
+];
+
+// Names of the modes and blocks bindings can be given for in the config file
+const MODES: [(&str, InputMode); 2] =
+    [("normal", InputMode::Normal), ("visual", InputMode::Visual)];
+const BLOCKS: [(&str, TBlocks); 3] = [
+    ("chat_list", TBlocks::ChatList),
+    ("chat", TBlocks::CurrChat),
+    ("input", TBlocks::Input),
+];
+
+// Binding to this removes a default binding
+const UNBOUND: &str = "none";
+
+// Where bindings apply: a mode, and either every block or just one
+type Context = (InputMode, Option<TBlocks>);
+
+// Keys, and the action they're bound to. Unbound keys are kept as `None`, to hide a
+// binding for the whole mode
+type Binding = (Vec<Key>, Option<Action>);
+
+// Sequences of keys bound to actions in normal and visual mode. Bindings for a block
+// take precedence over those for the whole mode
+pub struct Keymap {
+    bindings: HashMap<Context, Vec<Binding>>,
+}
+
+// Keys typed so far towards a binding, along with any count typed before them
+#[derive(Default)]
+pub struct Pending {
+    count: Option<usize>,
+    keys: Vec<Key>,
+}
+
+impl Keymap {
+    // The default bindings, with `exit_key` quitting from normal mode, overridden by the
+    // `keys` table of the config file
+    pub fn new(exit_key: Key, config: &toml::value::Table) -> Result<Keymap, String> {
+        let mut keymap = Keymap {
+            bindings: HashMap::new(),
+        };
+        keymap.bindings.insert(
+            (InputMode::Normal, None),
+            vec![(vec![exit_key], Some(Action::Quit))],
+        );
+        for (mode, keys, action) in DEFAULTS.iter() {
+            keymap.bind((mode_by_name(mode).unwrap(), None), keys, action)?;
+        }
+
+        // e.g. `[keys.normal]` with `j = "scroll_down"`, and `[keys.normal.chat_list]`
+        for (mode_name, table) in config {
+            let mode = mode_by_name(mode_name).ok_or_else(|| {
+                format!(
+                    "unknown mode \"{}\", expected \"normal\" or \"visual\"",
+                    mode_name
+                )
+            })?;
+            let table = table
+                .as_table()
+                .ok_or_else(|| format!("keys.{} must be a table", mode_name))?;
+            for (keys, value) in table {
+                match value {
+                    toml::Value::String(action) => keymap.bind((mode, None), keys, action)?,
+                    toml::Value::Table(block_table) => {
+                        let block_name = keys;
+                        let block = block_by_name(block_name).ok_or_else(|| {
+                            format!(
+                                "unknown box \"{}\", expected \"chat_list\", \"chat\" or \"input\"",
+                                block_name
+                            )
+                        })?;
+                        for (keys, action) in block_table {
+                            let action = action.as_str().ok_or_else(|| {
+                                format!(
+                                    "keys.{}.{}.{} must be an action name",
+                                    mode_name, block_name, keys
+                                )
+                            })?;
+                            keymap.bind((mode, Some(block)), keys, action)?;
+                        }
+                    }
+                    _ => {
+                        return Err(format!(
+                            "keys.{}.{} must be an action name",
+                            mode_name, keys
+                        ))
+                    }
+                }
+            }
+        }
+        Ok(keymap)
+    }
+
+    fn bind(&mut self, context: Context, keys: &str, action: &str) -> Result<(), String> {
+        let seq = parse_keys(keys).ok_or_else(|| format!("unrecognized keys \"{}\"", keys))?;
+        let action = if action == UNBOUND {
+            None
+        } else {
+            Some(
+                action_by_name(action)
+                    .ok_or_else(|| format!("unknown action \"{}\" for \"{}\"", action, keys))?,
+            )
+        };
+        let bindings = self.bindings.entry(context).or_default();
+        bindings.retain(|(k, _)| *k != seq);
+        bindings.push((seq, action));
+        Ok(())
+    }
+
+    // The binding for exactly `keys`, if any, and whether any longer binding starts with them
+    fn find(
+        &self,
+        mode: InputMode,
+        block: TBlocks,
+        keys: &[Key],
+    ) -> (Option<Option<Action>>, bool) {
+        let mut exact = None;
+        let mut prefix = false;
+        for context in [(mode, Some(block)), (mode, None)].iter() {
+            for (seq, action) in self.bindings.get(context).into_iter().flatten() {
+                if seq == keys {
+                    exact = exact.or(Some(*action));
+                } else if seq.starts_with(keys) {
+                    prefix = true;
+                }
+            }
+        }
+        (exact, prefix)
+    }
+
+    // Add `key` to those typed so far, returning the action and count once they make up
+    // a binding. Digits typed first are a count, unless bound themselves
+    pub(crate) fn feed(
+        &self,
+        pending: &mut Pending,
+        mode: InputMode,
+        block: TBlocks,
+        key: Key,
+    ) -> Option<(Action, usize)> {
+        if let Key::Char(c) = key {
+            // A count can't start with 0, as in vim
+            let counting = pending.keys.is_empty() && (c != '0' || pending.count.is_some());
+            if let Some(d) = c.to_digit(10).filter(|_| counting) {
+                if let (None, false) = self.find(mode, block, &[key]) {
+                    let count = pending.count.unwrap_or(0);
+                    pending.count = Some(count.saturating_mul(10).saturating_add(d as usize));
+                    return None;
+                }
+            }
+        }
+
+        pending.keys.push(key);
+        let (exact, prefix) = self.find(mode, block, &pending.keys);
+        if exact.is_none() && prefix {
+            return None;
+        }
+        let count = pending.count.unwrap_or(1);
+        *pending = Pending::default();
+        exact.flatten().map(|action| (action, count))
+    }
+
+    // The keys bound in `mode` with `block` selected, with what they do, in the order
+    // actions are listed
+    pub(crate) fn active(&self, mode: InputMode, block: TBlocks) -> Vec<(String, &'static str)> {
+        let mut seen: Vec<&Vec<Key>> = Vec::new();
+        let mut active = Vec::new();
+        for context in [(mode, Some(block)), (mode, None)].iter() {
+            for (seq, action) in self.bindings.get(context).into_iter().flatten() {
+                if seen.contains(&seq) {
+                    continue;
+                }
+                seen.push(seq);
+                if let Some(action) = action {
+                    let i = ACTIONS.iter().position(|(a, _, _)| a == action).unwrap();
+                    active.push((i, keys_name(seq)));
+                }
+            }
+        }
+        active.sort();
+        active
+            .into_iter()
+            .map(|(i, keys)| (keys, ACTIONS[i].2))
+            .collect()
+    }
+}
+
+fn action_by_name(name: &str) -> Option<Action> {
+    ACTIONS
+        .iter()
+        .find(|(_, n, _)| *n == name)
+        .map(|(a, _, _)| *a)
+}
+
+fn mode_by_name(name: &str) -> Option<InputMode> {
+    MODES.iter().find(|(n, _)| *n == name).map(|(_, m)| *m)
+}
+
+fn block_by_name(name: &str) -> Option<TBlocks> {
+    BLOCKS.iter().find(|(n, _)| *n == name).map(|(_, b)| *b)
+}
+
+// Parse a sequence of keys, e.g. `gg` or `<C-w>j`. Keys that aren't a single character
+// are named in angle brackets, as for `parse_key`
+pub fn parse_keys(s: &str) -> Option<Vec<Key>> {
+    let mut keys = Vec::new();
+    let mut rest = s;
+    while let Some(c) = rest.chars().next() {
+        if c == '<' {
+            if let Some(end) = rest.find('>') {
+                let name = &rest[1..end];
+                if name.chars().count() > 1 {
+                    keys.push(parse_key(name)?);
+                    rest = &rest[end + 1..];
+                    continue;
+                }
+            }
+        }
+        keys.push(Key::Char(c));
+        rest = &rest[c.len_utf8()..];
+    }
+    if keys.is_empty() {
+        return None;
+    }
+    Some(keys)
+}
+
+// Show a sequence of keys as it would be written in the config file
+fn keys_name(keys: &[Key]) -> String {
+    keys.iter()
+        .map(|k| match k {
+            Key::Char('\t') => "<Tab>".to_string(),
+            Key::Char('\n') => "<Enter>".to_string(),
+            Key::Char(' ') => "<Space>".to_string(),
+            Key::Char(c) => c.to_string(),
+            Key::Ctrl(c) => format!("<C-{}>", c),
+            Key::Alt(c) => format!("<A-{}>", c),
+            Key::F(n) => format!("<F{}>", n),
+            Key::Esc => "<Esc>".to_string(),
+            Key::Backspace => "<Backspace>".to_string(),
+            Key::Delete => "<Delete>".to_string(),
+            Key::Up => "<Up>".to_string(),
+            Key::Down => "<Down>".to_string(),
+            Key::Left => "<Left>".to_string(),
+            Key::Right => "<Right>".to_string(),
+            Key::Home => "<Home>".to_string(),
+            Key::End => "<End>".to_string(),
+            Key::PageUp => "<PageUp>".to_string(),
+            Key::PageDown => "<PageDown>".to_string(),
+            _ => "<?>".to_string(),
+        })
+        .collect()
+}
+
+// Draw the keys bound in `mode` with `block` selected over the middle of the screen
+pub fn render_help<B: Backend>(f: &mut Frame<B>, keymap: &Keymap, mode: InputMode, block: TBlocks) {
+    let active = keymap.active(mode, block);
+    let key_width = active.iter().map(|(k, _)| k.len()).max().unwrap_or(0);
+    let lines: Vec<Spans> = active
+        .into_iter()
+        .map(|(keys, description)| {
+            Spans::from(vec![
+                Span::styled(
+                    format!("{:width$}  ", keys, width = key_width),
+                    Style::default().add_modifier(Modifier::BOLD),
+                ),
+                Span::raw(description),
+            ])
+        })
+        .collect();
+
+    let size = f.size();
+    let width = 50.min(size.width);
+    let height = (lines.len() as u16 + 2).min(size.height);
+    let area = Rect::new(
+        size.x + (size.width - width) / 2,
+        size.y + (size.height - height) / 2,
+        width,
+        height,
+    );
+    let mode_name = MODES
+        .iter()
+        .find(|(_, m)| *m == mode)
+        .map_or("insert", |(n, _)| *n);
+    let title = format!("Keys in {} mode (any key to close)", mode_name);
+    let help = Paragraph::new(lines).block(
+        Block::default()
+            .title(Span::styled(title, Style::default().fg(Color::Yellow)))
+            .borders(Borders::ALL),
+    );
+    f.render_widget(Clear, area);
+    f.render_widget(help, area);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_config(config: &str) -> Result<Keymap, String> {
+        let table: toml::value::Table = toml::from_str(config).unwrap();
+        Keymap::new(Key::Char('q'), &table)
+    }
+
+    // Feed each of `keys` in, returning what the last one completes
+    fn feed(keymap: &Keymap, block: TBlocks, keys: &str) -> Option<(Action, usize)> {
+        let mut pending = Pending::default();
+        let mut done = None;
+        for key in parse_keys(keys).unwrap() {
+            done = keymap.feed(&mut pending, InputMode::Normal, block, key);
+        }
+        done
+    }
+
+    #[test]
+    fn parses_key_sequences() {
+        assert_eq!(parse_keys("gg"), Some(vec![Key::Char('g'), Key::Char('g')]));
+        assert_eq!(
+            parse_keys("<C-w>j<Space>"),
+            Some(vec![Key::Ctrl('w'), Key::Char('j'), Key::Char(' ')])
+        );
+        // Angle brackets around a single character, or none at all, are typed as is
+        assert_eq!(
+            parse_keys("<x><"),
+            Some(vec![
+                Key::Char('<'),
+                Key::Char('x'),
+                Key::Char('>'),
+                Key::Char('<')
+            ])
+        );
+        assert_eq!(parse_keys("<Nope>"), None);
+        assert_eq!(parse_keys(""), None);
+    }
+
+    #[test]
+    fn prefixes_wait_for_the_rest() {
+        let keymap = with_config("").unwrap();
+        let mut pending = Pending::default();
+        let g = Key::Char('g');
+        assert!(keymap
+            .feed(&mut pending, InputMode::Normal, TBlocks::ChatList, g)
+            .is_none());
+        assert!(
+            keymap.feed(&mut pending, InputMode::Normal, TBlocks::ChatList, g)
+                == Some((Action::Top, 1))
+        );
+
+        // A sequence bound to nothing is dropped, and the next starts afresh
+        assert!(feed(&keymap, TBlocks::ChatList, "gx").is_none());
+        assert!(feed(&keymap, TBlocks::ChatList, "gxj") == Some((Action::ScrollDown, 1)));
+        assert!(feed(&keymap, TBlocks::ChatList, "gxgg") == Some((Action::Top, 1)));
+    }
+
+    #[test]
+    fn counts_come_before_keys() {
+        let keymap = with_config("").unwrap();
+        assert!(feed(&keymap, TBlocks::CurrChat, "12j") == Some((Action::ScrollDown, 12)));
+        assert!(feed(&keymap, TBlocks::CurrChat, "10gg") == Some((Action::Top, 10)));
+        // Not 0 first, nor in the middle of a sequence
+        assert!(feed(&keymap, TBlocks::CurrChat, "0j") == Some((Action::ScrollDown, 1)));
+        assert!(feed(&keymap, TBlocks::CurrChat, "g2g").is_none());
+        assert!(
+            feed(&keymap, TBlocks::CurrChat, "99999999999999999999999j")
+                == Some((Action::ScrollDown, usize::MAX))
+        );
+
+        // A digit that's bound does its action instead
+        let keymap = with_config("[normal]\n1 = \"top\"").unwrap();
+        assert!(feed(&keymap, TBlocks::CurrChat, "1") == Some((Action::Top, 1)));
+        assert!(feed(&keymap, TBlocks::CurrChat, "21") == Some((Action::Top, 2)));
+    }
+
+    #[test]
+    fn config_overrides_defaults() {
+        let keymap = with_config(
+            "[normal]\nj = \"none\"\n\"<C-d>\" = \"page_down\"\n\
+             [normal.chat_list]\nj = \"scroll_up\"",
+        )
+        .unwrap();
+        assert!(feed(&keymap, TBlocks::CurrChat, "j").is_none());
+        assert!(feed(&keymap, TBlocks::ChatList, "j") == Some((Action::ScrollUp, 1)));
+        assert!(feed(&keymap, TBlocks::CurrChat, "<C-d>") == Some((Action::PageDown, 1)));
+        assert!(feed(&keymap, TBlocks::CurrChat, "<C-f>") == Some((Action::PageDown, 1)));
+
+        let active = keymap.active(InputMode::Normal, TBlocks::CurrChat);
+        assert!(!active.iter().any(|(keys, _)| keys == "j"));
+        assert!(active.contains(&("<C-d>".to_string(), "Move down a page")));
+    }
+
+    #[test]
+    fn rejects_bad_bindings() {
+        let error = |config| with_config(config).err().unwrap();
+        assert!(error("[insert]\nj = \"top\"").contains("unknown mode"));
+        assert!(error("[normal]\nj = \"fly\"").contains("unknown action"));
+        assert!(error("[normal]\n\"<Nope>\" = \"top\"").contains("unrecognized keys"));
+        assert!(error("[normal.sidebar]\nj = \"top\"").contains("unknown box"));
+        assert!(error("[normal]\nj = 1").contains("must be an action name"));
+    }
+}
@@ -0,0 +1,2324 @@
+extern crate chrono;
+use chrono::prelude::*;
+mod command;
+pub mod config;
+mod content;
+mod editor;
+mod event;
+mod finder;
+mod format;
+mod history;
+mod keymap;
+mod login;
+mod markup;
+mod positions;
+mod redraw;
+mod requests;
+mod search;
+mod selected;
+mod tabs;
+pub mod transport;
+mod updates;
+mod vim;
+#[cfg(test)]
+#[path = "../tests/fixtures/mod.rs"]
+mod fixtures;
+use command::{CommandLine, Prompt};
+use config::Config;
+use crossbeam::channel::{self, select, Receiver, Sender};
+use crossbeam::thread;
+use editor::Editor;
+use event::{Event, Events};
+use finder::Finder;
+use history::History;
+use keymap::Action;
+use login::LoginState;
+use markup::ParseMode;
+use positions::{ChatListId, Paging, Position};
+use redraw::Redraw;
+use requests::Requests;
+use rtdlib::types::*;
+use rtdlib::Tdlib;
+use search::{ChatSearch, SearchResults};
+use selected::Popup;
+use serde_json::{json, Value};
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, HashMap};
+use std::io::{self, Error, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::vec;
+use tabs::{TabsState, View};
+use termion::{event::Key, input::MouseTerminal, raw::IntoRawMode, screen::AlternateScreen};
+use transport::{FakeTransport, Transport};
+use tui::{
+    backend::{Backend, TermionBackend},
+    layout::{Constraint, Corner, Direction, Layout, Rect},
+    style::{Color, Modifier, Style},
+    text::{Span, Spans, Text},
+    widgets::{Block, Borders, Clear, List, ListItem, ListState, Paragraph, Tabs},
+    Frame, Terminal,
+};
+use unicode_width::UnicodeWidthStr;
+use updates::UpdateError;
+use vim::Vim;
+pub enum MsgCode {
+    Exit,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+enum InputMode {
+    Normal,
+    Insert,
+    Visual,
+}
+#[derive(Clone)]
+enum MsgAction {
+    Edit,
+    Reply,
+}
+
+// Whether the input box is editing a message or replying to one, by its ID
+#[derive(Clone)]
+enum MsgState {
+    Normal,
+    Edit(i64),
+    Reply(i64),
+}
+
+// TUI Blocks
+#[derive(PartialEq, Eq, Hash, Clone, Copy)]
+enum TBlocks {
+    ChatList,
+    CurrChat,
+    Input,
+}
+
+// The whole application
+#[derive(Clone)]
+pub struct App {
+    config: Arc<Config>,
+    curr_mode: Arc<Mutex<InputMode>>,
+
+    // Requests outgoing to Tdlib, and those awaiting a reply
+    requests: Requests,
+    users: Arc<Mutex<HashMap<i64, TUser>>>,
+    basic_groups: Arc<Mutex<HashMap<i64, TBasicGroup>>>,
+
+    // Supergroups and channels, which Tdlib treats as one, and secret chats
+    supergroups: Arc<Mutex<HashMap<i64, TSupergroup>>>,
+    secret_chats: Arc<Mutex<HashMap<i64, TSecretChat>>>,
+    chat_list: TChats,
+    input_box: InputBox,
+    chat_history_state: ListState,
+
+    // Which chat list entry is highlighted, and how far the list is scrolled to show it
+    chat_list_state: ListState,
+    me: User,
+    selected_block: Arc<Mutex<TBlocks>>,
+
+    // Login screen, active until Tdlib reports that authorization is ready
+    login: Arc<Mutex<LoginState>>,
+
+    // Message shown at the bottom of the screen, e.g. a failed request's error
+    status: Arc<Mutex<Option<String>>>,
+
+    // Whether the screen needs redrawing
+    redraw: Redraw,
+
+    // Whether the list of key bindings is shown
+    help: Arc<Mutex<bool>>,
+
+    // The `:` command line
+    command_line: Arc<Mutex<CommandLine>>,
+
+    // The chat switcher, while it's open
+    finder: Arc<Mutex<Option<Finder>>>,
+
+    // The search through the open chat that `n` and `N` go through
+    search: Arc<Mutex<Option<ChatSearch>>>,
+
+    // Messages found searching every chat, while they're listed
+    search_results: Arc<Mutex<Option<SearchResults>>>,
+
+    // Popup asking to confirm deleting messages, or showing a message's details
+    popup: Arc<Mutex<Option<Popup>>>,
+
+    // Text copied, waiting for the render thread to hand it to the terminal
+    clipboard: Arc<Mutex<Option<String>>>,
+
+    // Chat Tdlib has been told is open, which it sends extra updates for, like how many
+    // members are online
+    viewing: Arc<Mutex<Option<i64>>>,
+}
+impl App {
+    pub fn new(config: Config) -> App {
+        let command_line = CommandLine::new(PathBuf::from(&config.command_history));
+        let app = App {
+            config: Arc::new(config),
+            curr_mode: Arc::new(Mutex::new(InputMode::Normal)),
+            requests: Requests::new(),
+            users: Arc::new(Mutex::new(HashMap::new())),
+            basic_groups: Arc::new(Mutex::new(HashMap::new())),
+            supergroups: Arc::new(Mutex::new(HashMap::new())),
+            secret_chats: Arc::new(Mutex::new(HashMap::new())),
+            chat_list: TChats::new("Chats"),
+            input_box: InputBox::new("Input"),
+            chat_history_state: ListState::default(),
+            chat_list_state: ListState::default(),
+            me: User::default(),
+            selected_block: Arc::new(Mutex::new(TBlocks::ChatList)),
+            login: Arc::new(Mutex::new(LoginState::new())),
+            status: Arc::new(Mutex::new(None)),
+            redraw: Redraw::new(),
+            help: Arc::new(Mutex::new(false)),
+            command_line: Arc::new(Mutex::new(command_line)),
+            finder: Arc::new(Mutex::new(None)),
+            search: Arc::new(Mutex::new(None)),
+            search_results: Arc::new(Mutex::new(None)),
+            popup: Arc::new(Mutex::new(None)),
+            clipboard: Arc::new(Mutex::new(None)),
+            viewing: Arc::new(Mutex::new(None)),
+        };
+        *app.input_box.vim.lock().unwrap() = Vim::new(app.config.vim_input);
+        *app.chat_list.tabs.lock().unwrap() = TabsState::new(&app.config.chat_filters);
+        app
+    }
+    fn set_selected_block(&mut self, b: TBlocks) {
+        *self.selected_block.lock().unwrap() = b;
+    }
+
+    fn selected_block(&self) -> TBlocks {
+        return *self.selected_block.lock().unwrap();
+    }
+    fn set_curr_mode(&mut self, m: InputMode) {
+        *self.curr_mode.lock().unwrap() = m;
+    }
+
+    fn curr_mode(&self) -> InputMode {
+        return *self.curr_mode.lock().unwrap();
+    }
+    fn set_status(&self, s: String) {
+        *self.status.lock().unwrap() = Some(s);
+        self.redraw.request();
+    }
+
+    // Show the chat at `index` in the chat list
+    fn open_chat(&mut self, index: usize) {
+        self.chat_list.set_selected_index(index);
+        self.chat_list.reveal_selected();
+        self.set_selected_block(TBlocks::CurrChat);
+        self.set_curr_mode(InputMode::Normal);
+    }
+
+    // Show the chat with `chat_id` at message `message_id`, retrieving the history around
+    // it if need be
+    fn open_message(&mut self, chat_id: i64, message_id: i64) {
+        let index = self
+            .chat_list
+            .chat_vec
+            .lock()
+            .unwrap()
+            .iter()
+            .position(|c| c.chat.id() == chat_id);
+        match index {
+            Some(i) => {
+                self.open_chat(i);
+                search::jump_to_message(self, chat_id, message_id);
+            }
+            None => self.set_status("That chat isn't in the chat list".to_string()),
+        }
+    }
+}
+
+// A wrapper for Tdlib's Basic Group
+struct TBasicGroup {
+    g: BasicGroup,
+    full_info: BasicGroupFullInfo,
+}
+
+// A wrapper for Tdlib's Supergroup, which may be a channel
+struct TSupergroup {
+    g: Supergroup,
+
+    // Full info as received from Tdlib, as rtdlib can't parse Tdlib 1.7's. Null until
+    // it's been retrieved
+    full_info: Value,
+    retrieving_full_info: bool,
+}
+
+impl TSupergroup {
+    // Retrieve full info, once, for the member count
+    fn retrieve_full_info(&mut self, requests: &Requests) {
+        if self.retrieving_full_info || !self.full_info.is_null() {
+            return;
+        }
+        self.retrieving_full_info = true;
+        let supergroup_id = self.g.id();
+        requests.send_json(
+            json!({"@type": "getSupergroupFullInfo", "supergroup_id": supergroup_id}),
+            move |app, reply| {
+                let mut supergroups = app.supergroups.lock().unwrap();
+                if let Some(sg) = supergroups.get_mut(&supergroup_id) {
+                    match reply {
+                        Ok(full_info) => sg.full_info = full_info,
+                        Err(e) => eprintln!("Couldn't get info on {}: {}", supergroup_id, e),
+                    }
+                }
+            },
+        );
+    }
+}
+
+// A secret chat's state, and whether we started it. Its user is in the chat's type
+struct TSecretChat {
+    state: SecretChatState,
+    is_outbound: bool,
+}
+
+// The message input box
+#[derive(Clone)]
+struct InputBox {
+    // Box title
+    name: &'static str,
+
+    // Vim-style editing state, shared by every chat's input
+    vim: Arc<Mutex<Vim>>,
+}
+
+// The box containing the list of chats
+#[derive(Clone)]
+struct TChats {
+    // Vector containing each chat
+    chat_vec: Arc<Mutex<Vec<TChat>>>,
+
+    // Title associated with block
+    name: &'static str,
+
+    // Index within chat_vec of currently selected chat, if any. None are when the chats
+    // shown were switched to an empty list
+    selected_index: Arc<Mutex<Option<usize>>>,
+
+    // Chats shown, and how many there are. They're sorted to the start of chat_vec,
+    // followed by chats that aren't
+    view: Arc<Mutex<View>>,
+    shown: Arc<Mutex<usize>>,
+
+    // Tabs to switch between views, and the chat last selected in each
+    tabs: Arc<Mutex<TabsState>>,
+    selected_in: Arc<Mutex<HashMap<View, i64>>>,
+
+    // How far through each list chats have been got, a page at a time
+    paging: Arc<Mutex<HashMap<ChatListId, Paging>>>,
+
+    // Whether updates have changed the order since the chats were last sorted. They're
+    // sorted once a batch of updates has been handled, rather than after each
+    unsorted: Arc<AtomicBool>,
+}
+
+// A wrapper for Tdlib User with extra information
+#[derive(Clone)]
+struct TUser {
+    u: User,
+
+    // Color of users name in chat; calculated to be as globally unique as possible
+    color: Color,
+    #[allow(dead_code)] // Nothing shows it yet
+    full_info: UserFullInfo,
+    status: UserStatus,
+}
+
+// A wrapper for Tdlib Message, keeping its content as received. rtdlib can't parse
+// every kind of content Tdlib sends, so content is shown from the JSON instead
+#[derive(Clone)]
+struct TMessage {
+    msg: Message,
+    content: Value,
+
+    // Chat of the message this replies to, which for comments isn't this one's
+    reply_in_chat_id: i64,
+
+    // Chat the message was sent on behalf of, e.g. a channel's posts, or 0 if sent by a
+    // user
+    sender_chat_id: i64,
+
+    // Fields from Tdlib 1.7 that rtdlib doesn't know: whether the message is pinned, and
+    // where it was forwarded from, or null
+    is_pinned: bool,
+    forward_info: Value,
+}
+
+impl TMessage {
+    // Chat and ID of the message this replies to, if it's a reply
+    fn replied_to(&self) -> Option<(i64, i64)> {
+        match self.msg.reply_to_message_id() {
+            0 => None,
+            id if self.reply_in_chat_id != 0 => Some((self.reply_in_chat_id, id)),
+            id => Some((self.msg.chat_id(), id)),
+        }
+    }
+
+    // Text of a text message, as written by the sender
+    fn text(&self) -> Option<&str> {
+        match self.content["@type"].as_str() {
+            Some("messageText") => self.content["text"]["text"].as_str(),
+            _ => None,
+        }
+    }
+}
+
+// A wrapper for Tdlib Chat with extra information
+#[derive(Clone)]
+struct TChat {
+    // Message being written in this chat
+    input: Arc<Mutex<Editor>>,
+
+    // History of messages in this chat
+    history: Arc<Mutex<History>>,
+
+    // The relevant chat
+    chat: Chat,
+
+    // Starting message id and offset of current request, if there is one
+    // Used to prevent redundant requests
+    retrieving: Option<(i64, i64)>,
+
+    // Starting message id and offset of a request that failed, which would only fail
+    // again if repeated straight away. Not retried until the chat is scrolled or opened
+    // again
+    history_failed: Option<(i64, i64)>,
+
+    // Number of messages currently displayed on screen
+    num_onscreen: usize,
+
+    // Index (within history) of the message at the bottom of the screen
+    bottom_index: usize,
+
+    // Message to scroll to once the history it's in has been retrieved
+    jump_to: Option<i64>,
+
+    // Messages replied to that aren't in history, by ID. None while being retrieved, or
+    // if they couldn't be
+    replied: HashMap<i64, Option<TMessage>>,
+
+    // Timestamp of most recent message in chat
+    last_msg_date: Arc<Mutex<i64>>,
+
+    // Where the chat is in each list it's in
+    positions: HashMap<ChatListId, Position>,
+
+    // Whether it's with a bot, which Tdlib only says of the user
+    is_bot: bool,
+
+    // Index of currently selected index in visual mode
+    select_index: usize,
+
+    // Messages picked out in visual mode to act on together: those marked one at a time,
+    // and the range from the message `V` was pressed on to the one under the cursor
+    marked: BTreeSet<i64>,
+    range_start: Option<i64>,
+
+    // What to do with what's going in input box (new message, edit, reply, etc.)
+    msg_state: MsgState,
+
+    // How text in the input box is formatted when sent
+    parse_mode: ParseMode,
+
+    // Notification settings as received from Tdlib, kept whole to send back changed
+    notification_settings: Value,
+
+    // Members online, which Tdlib only says for chats it's been told are open
+    online_member_count: i64,
+
+    // Unread messages and mentions of us, and the newest messages read by us and by
+    // whoever we're writing to
+    unread_count: i64,
+    unread_mention_count: i64,
+    last_read_inbox_message_id: i64,
+    last_read_outbox_message_id: i64,
+
+    // Newest message Tdlib has been told was seen, while it hasn't yet said it's read
+    viewed_up_to: i64,
+}
+impl App {}
+
+impl TChat {
+    fn get_selected_msg(&self) -> Option<TMessage> {
+        let history = self.history.lock().unwrap();
+        history
+            .messages()
+            .get(self.bottom_index + self.select_index)
+            .cloned()
+    }
+    fn select_msg(&mut self, action: MsgAction) {
+        let m = match self.get_selected_msg() {
+            Some(m) => m,
+            None => return,
+        };
+        match action {
+            MsgAction::Reply => {
+                self.msg_state = MsgState::Reply(m.msg.id());
+            }
+            MsgAction::Edit => {
+                // Only text can be edited here, not captions
+                if m.text().is_some() && m.msg.can_be_edited() {
+                    self.msg_state = MsgState::Edit(m.msg.id());
+                    let text = markup::unparse(&m.content["text"], self.parse_mode);
+                    self.input.lock().unwrap().set_text(&text);
+                } else {
+                    self.msg_state = MsgState::Normal;
+                }
+            }
+        }
+    }
+    fn selection_change(&mut self) {
+        if let MsgState::Edit(_) = self.msg_state {
+            self.input.lock().unwrap().clear();
+        }
+    }
+    fn select_up(&mut self) {
+        self.selection_change();
+        self.msg_state = MsgState::Normal;
+        if self.select_index >= self.num_onscreen {
+            self.select_index = 0;
+        } else {
+            self.select_index += 1;
+        }
+    }
+    fn select_down(&mut self) {
+        self.selection_change();
+        self.msg_state = MsgState::Normal;
+        if self.select_index == 0 {
+            self.select_index = self.num_onscreen - 1;
+        } else {
+            self.select_index -= 1;
+        }
+    }
+    // ID of the message under the cursor in visual mode
+    fn cursor_message_id(&self) -> Option<i64> {
+        let history = self.history.lock().unwrap();
+        let msg = history
+            .messages()
+            .get(self.bottom_index + self.select_index)?;
+        Some(msg.msg.id())
+    }
+
+    // Mark the message under the cursor, or unmark it if it's marked
+    fn toggle_mark(&mut self) {
+        if let Some(id) = self.cursor_message_id() {
+            if !self.marked.remove(&id) {
+                self.marked.insert(id);
+            }
+        }
+    }
+
+    // Start a range at the message under the cursor, or end the one started there,
+    // marking what's in it
+    fn toggle_range(&mut self) {
+        if self.range_start.is_some() {
+            let history = self.history.lock().unwrap();
+            let ids = self.selected_ids(&history);
+            drop(history);
+            self.marked.extend(ids);
+            self.range_start = None;
+        } else {
+            self.range_start = self.cursor_message_id();
+        }
+    }
+
+    fn clear_selection(&mut self) {
+        self.marked.clear();
+        self.range_start = None;
+    }
+
+    // Whether any messages have been marked or a range started, rather than only the
+    // one under the cursor being selected
+    fn is_marking(&self) -> bool {
+        !self.marked.is_empty() || self.range_start.is_some()
+    }
+
+    // IDs of the messages selected in visual mode, newest first: those marked or in the
+    // range, or the one under the cursor if there are none
+    fn selected_ids(&self, history: &History) -> Vec<i64> {
+        let messages = history.messages();
+        let cursor = self.bottom_index + self.select_index;
+        let mut ids: Vec<i64> = self.marked.iter().copied().collect();
+        if let Some(start) = self.range_start.and_then(|id| history.index_of(id)) {
+            let (from, to) = (start.min(cursor), start.max(cursor));
+            ids.extend(messages.iter().take(to + 1).skip(from).map(|m| m.msg.id()));
+        }
+        if ids.is_empty() {
+            ids.extend(messages.get(cursor).map(|m| m.msg.id()));
+        }
+        ids.sort_by_key(|&id| Reverse(id));
+        ids.dedup();
+        ids
+    }
+
+    fn set_last_msg_date(&mut self, d: i64) {
+        *self.last_msg_date.lock().unwrap() = d;
+    }
+    // Retrieve history of messages in chat, starting at message with id `start_id`,
+    // retrieving up to `limit` messages. A negative `offset` also retrieves that many
+    // newer messages. The messages are added to history once they arrive
+    fn retrieve_history(&mut self, requests: &Requests, start_id: i64, offset: i64, limit: i64) {
+        let request = Some((start_id, offset));
+        if self.retrieving == request || self.history_failed == request {
+            return;
+        }
+        self.retrieving = request;
+        let chat_history_req = GetChatHistory::builder()
+            .chat_id(self.chat.id())
+            .from_message_id(start_id)
+            .offset(offset)
+            .limit(limit)
+            .only_local(false)
+            .build();
+
+        let chat_id = self.chat.id();
+        requests.send_then(&chat_history_req, move |app, reply| {
+            let failed = match reply {
+                Ok(mut msgs) => {
+                    match updates::messages(app, chat_id, start_id, offset, &mut msgs) {
+                        Ok(()) => false,
+                        Err(e) => {
+                            eprintln!("Skipping history of {}: {}", chat_id, e);
+                            true
+                        }
+                    }
+                }
+                Err(e) => {
+                    app.set_status(format!("Couldn't load history: {}", e));
+                    true
+                }
+            };
+
+            // Allow the same request to be retried unless it failed, and carry on to any
+            // message being jumped to
+            let jump_to = app.chat_list.with_chat(chat_id, |c| {
+                c.retrieving = None;
+                if failed {
+                    c.history_failed = Some((start_id, offset));
+                }
+                c.jump_to
+            });
+            if let Some(Some(message_id)) = jump_to {
+                search::jump_to_message(app, chat_id, message_id);
+            }
+        });
+    }
+
+    // Tell Tdlib the messages on screen have been seen, where they're newer than those
+    // it knows have been read
+    fn view_messages(&mut self, requests: &Requests) {
+        let read = self.last_read_inbox_message_id.max(self.viewed_up_to);
+        let history = self.history.lock().unwrap();
+        let ids: Vec<i64> = history
+            .messages()
+            .iter()
+            .skip(self.bottom_index)
+            .take(self.num_onscreen)
+            .map(|m| m.msg.id())
+            .filter(|&id| id > read)
+            .collect();
+        drop(history);
+        let newest = match ids.iter().max() {
+            Some(&id) => id,
+            None => return,
+        };
+        self.viewed_up_to = newest;
+        let req = ViewMessages::builder()
+            .chat_id(self.chat.id())
+            .message_ids(ids)
+            .force_read(false)
+            .build();
+        requests.send(&req);
+    }
+
+    // Show message `message_id` at the bottom of the screen, returning whether it's been
+    // retrieved
+    fn show_message(&mut self, message_id: i64) -> bool {
+        match self.history.lock().unwrap().show(message_id) {
+            Some(i) => {
+                self.bottom_index = i;
+                self.select_index = 0;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // The message `msg` replies to, if it's been retrieved
+    fn replied<'a>(&'a self, history: &'a History, msg: &TMessage) -> Option<&'a TMessage> {
+        let (chat_id, message_id) = msg.replied_to()?;
+        if chat_id == self.chat.id() {
+            if let Some(m) = history.all().find(|m| m.msg.id() == message_id) {
+                return Some(m);
+            }
+        }
+        self.replied.get(&message_id)?.as_ref()
+    }
+
+    // Retrieve the messages replied to by those on screen, where they're not in history
+    fn retrieve_replied(&mut self, requests: &Requests) {
+        let history = self.history.lock().unwrap();
+        let wanted: Vec<(i64, i64)> = history
+            .messages()
+            .iter()
+            .skip(self.bottom_index)
+            .take(self.num_onscreen)
+            .filter(|m| self.replied(&history, m).is_none())
+            .filter_map(|m| m.replied_to())
+            .filter(|(_, id)| !self.replied.contains_key(id))
+            .collect();
+        drop(history);
+
+        let this_chat = self.chat.id();
+        for (chat_id, message_id) in wanted {
+            self.replied.insert(message_id, None);
+            let req = GetMessage::builder()
+                .chat_id(chat_id)
+                .message_id(message_id)
+                .build();
+            requests.send_then(&req, move |app, reply| {
+                let mut m = match reply {
+                    Ok(m) => m,
+                    Err(e) => {
+                        return eprintln!("Couldn't get replied message {}: {}", message_id, e)
+                    }
+                };
+                match parse_msg(&mut m, chat_id) {
+                    Ok(m) => {
+                        app.chat_list
+                            .with_chat(this_chat, |c| c.replied.insert(message_id, Some(m)));
+                    }
+                    Err(e) => eprintln!("Couldn't get replied message {}: {}", message_id, e),
+                }
+            });
+        }
+    }
+
+    // ID of the message at the bottom of the screen, to keep there as history changes
+    fn bottom_message_id(&self) -> Option<i64> {
+        let history = self.history.lock().unwrap();
+        history
+            .messages()
+            .get(self.bottom_index)
+            .map(|m| m.msg.id())
+    }
+
+    // Create a TChat from a JSON string of a Tdlib Chat
+    fn from_json(j: String) -> rtdlib::errors::RTDResult<TChat> {
+        let mut t = TChat::new("Chat");
+        t.chat = Chat::from_json(j)?;
+        Ok(t)
+    }
+}
+
+// TUI block
+trait TBlock {
+    fn new(name: &'static str) -> Self;
+
+    fn scroll_down(&mut self) {}
+    fn scroll_up(&mut self) {}
+    fn page_down(&mut self) {}
+    fn page_up(&mut self) {}
+    fn go_to_bottom(&mut self) {}
+    fn go_to_top(&mut self) {}
+    fn get_len(&self) -> usize {
+        0
+    }
+    fn handle_input_insert(
+        &mut self,
+        _requests: &Requests,
+        _input: &termion::event::Key,
+        _cur_chat: &mut TChat,
+    ) {
+    }
+    fn handle_action_visual(&mut self, _requests: &Requests, _action: Action, _cur_chat_id: i64) {}
+
+    // Handle an action bound to a key in normal mode, by default moving through the block
+    fn handle_action_normal(&mut self, _requests: &Requests, action: Action) {
+        match action {
+            Action::ScrollDown => self.scroll_down(),
+            Action::ScrollUp => self.scroll_up(),
+            Action::Bottom => self.go_to_bottom(),
+            Action::Top => self.go_to_top(),
+            Action::PageDown => self.page_down(),
+            Action::PageUp => self.page_up(),
+            _ => {}
+        }
+    }
+}
+
+// List of chats
+impl TChats {
+    // Run `f` on the chat having given ID, in place, if it exists
+    fn with_chat<R>(&self, chat_id: i64, f: impl FnOnce(&mut TChat) -> R) -> Option<R> {
+        let mut cv = self.chat_vec.lock().unwrap();
+        cv.iter_mut().find(|c| c.chat.id() == chat_id).map(f)
+    }
+    // Run `f` on the selected chat, in place, if there is one
+    fn with_selected<R>(&self, f: impl FnOnce(&mut TChat) -> R) -> Option<R> {
+        let i = self.selected_index()?;
+        self.chat_vec.lock().unwrap().get_mut(i).map(f)
+    }
+    fn get_chat_id_by_index(&self, i: usize) -> Option<i64> {
+        self.chat_vec.lock().unwrap().get(i).map(|c| c.chat.id())
+    }
+    fn selected_chat_id(&self) -> Option<i64> {
+        self.get_chat_id_by_index(self.selected_index()?)
+    }
+    fn set_selected_index(&mut self, i: usize) {
+        *self.selected_index.lock().unwrap() = Some(i);
+
+        // Opening a chat tries again for history that couldn't be got before
+        if let Some(c) = self.chat_vec.lock().unwrap().get_mut(i) {
+            c.history_failed = None;
+        }
+    }
+
+    fn selected_index(&self) -> Option<usize> {
+        return *self.selected_index.lock().unwrap();
+    }
+
+    fn view(&self) -> View {
+        *self.view.lock().unwrap()
+    }
+
+    fn list(&self) -> ChatListId {
+        self.view().list
+    }
+
+    // Show a tab the selected chat is in, or else the archive, when it isn't in the chats
+    // shown, e.g. it's archived and was opened by name
+    fn reveal_selected(&mut self) {
+        let cv = self.chat_vec.lock().unwrap();
+        let chat = match self.selected_index().and_then(|i| cv.get(i)) {
+            Some(c) => c,
+            None => return,
+        };
+        if self.view().contains(chat) {
+            return;
+        }
+        let mut tabs = self.tabs.lock().unwrap();
+        let view = match tabs.tabs.iter().position(|t| t.view.contains(chat)) {
+            Some(tab) => {
+                tabs.index = tab;
+                tabs.view()
+            }
+            None if chat.positions.contains_key(&ChatListId::Archive) => {
+                View::all(ChatListId::Archive)
+            }
+            None => return,
+        };
+        let id = chat.chat.id();
+        drop((tabs, cv));
+        self.show(view);
+        self.select_chat(id);
+    }
+
+    // Get more chats in the list shown once close to the end of those got
+    fn load_more(&self, requests: &Requests) {
+        if self.selected_index().unwrap_or(0) + CHATS_AHEAD < self.get_len() {
+            return;
+        }
+        let list = self.list();
+        let mut paging = self.paging.lock().unwrap();
+        positions::load_page(requests, list, paging.entry(list).or_default());
+    }
+
+    // Whether more chats in the list shown are being got
+    fn loading(&self) -> bool {
+        let paging = self.paging.lock().unwrap();
+        paging.get(&self.list()).is_some_and(|p| p.loading)
+    }
+
+    fn select_chat(&mut self, chat_id: i64) {
+        let i = self
+            .chat_vec
+            .lock()
+            .unwrap()
+            .iter()
+            .position(|c| c.chat.id() == chat_id);
+        if let Some(i) = i {
+            self.set_selected_index(i);
+        }
+    }
+
+    // Show other chats, back at the one last selected among them, or else at the top.
+    // No chat is selected when there are none to show
+    fn show(&mut self, view: View) {
+        if let Some(id) = self.selected_chat_id() {
+            let shown = self.view();
+            self.selected_in.lock().unwrap().insert(shown, id);
+        }
+        *self.view.lock().unwrap() = view;
+        self.sort();
+        let len = self.get_len();
+        let selected = self.selected_in.lock().unwrap().get(&view).copied();
+        let i = selected.and_then(|id| {
+            let cv = self.chat_vec.lock().unwrap();
+            cv[..len].iter().position(|c| c.chat.id() == id)
+        });
+        *self.selected_index.lock().unwrap() = i.or(if len > 0 { Some(0) } else { None });
+    }
+
+    // Have the chats sorted once the updates being handled are done with
+    fn sort_later(&self) {
+        self.unsorted.store(true, Ordering::Relaxed);
+    }
+
+    // Sort the chats if updates have changed their order, returning whether they were
+    fn sort_if_unsorted(&mut self) -> bool {
+        let unsorted = self.unsorted.load(Ordering::Relaxed);
+        if unsorted {
+            self.sort();
+        }
+        unsorted
+    }
+
+    // Sort the chats shown to the start, pinned ones first, then in Tdlib's order. Chats it
+    // hasn't placed yet go by their last message
+    fn sort(&mut self) {
+        self.unsorted.store(false, Ordering::Relaxed);
+        let view = self.view();
+        let mut cv = self.chat_vec.lock().unwrap();
+        let id_of_selected = self
+            .selected_index()
+            .and_then(|i| cv.get(i))
+            .map(|c| c.chat.id());
+        cv.sort_by_cached_key(|c| {
+            let position = c.positions.get(&view.list);
+            Reverse((
+                view.contains(c),
+                position.is_some_and(|p| p.is_pinned),
+                position.map_or(0, |p| p.order),
+                *c.last_msg_date.lock().unwrap(),
+            ))
+        });
+        *self.shown.lock().unwrap() = cv.iter().filter(|c| view.contains(c)).count();
+
+        if let Some(id) = id_of_selected {
+            if let Some(i) = cv.iter().position(|c| c.chat.id() == id) {
+                *self.selected_index.lock().unwrap() = Some(i);
+            }
+        }
+    }
+}
+
+// Message input box
+impl InputBox {
+    // Creates message to be sent to chat, using passed in chat ID and the contents of
+    // the input string
+    fn send_message(&mut self, cur_chat: &mut TChat, requests: &Requests) {
+        let input = cur_chat.input.lock().unwrap().text().to_string();
+        let formatted = markup::parse(&input, cur_chat.parse_mode);
+        let text = match FormattedText::from_json(formatted.to_string()) {
+            Ok(t) => t,
+            Err(e) => {
+                eprintln!("Couldn't build message {}: {}", formatted, e);
+                return;
+            }
+        };
+        let msg = InputMessageContent::InputMessageText(
+            InputMessageText::builder().text(text.clone()).build(),
+        );
+        match cur_chat.msg_state {
+            MsgState::Edit(id) => edit_message(requests, cur_chat.chat.id(), id, text),
+            _ => {
+                let reply_to = match cur_chat.msg_state {
+                    MsgState::Reply(id) => id,
+                    _ => 0,
+                };
+                let req = SendMessage::builder()
+                    .chat_id(cur_chat.chat.id())
+                    .reply_to_message_id(reply_to)
+                    .input_message_content(msg)
+                    .build();
+                requests.send_then(&req, |app, reply| {
+                    if let Err(e) = reply {
+                        app.set_status(format!("Couldn't send message: {}", e));
+                    }
+                });
+            }
+        }
+        cur_chat.input.lock().unwrap().clear();
+    }
+}
+
+impl TBlock for InputBox {
+    fn new(name: &'static str) -> InputBox {
+        InputBox {
+            name,
+            vim: Arc::new(Mutex::new(Vim::new(true))),
+        }
+    }
+    fn handle_input_insert(
+        &mut self,
+        requests: &Requests,
+        input: &termion::event::Key,
+        cur_chat: &mut TChat,
+    ) {
+        match input {
+            // Newlines are entered with Alt-Enter
+            Key::Char('\n') => {
+                if cur_chat.input.lock().unwrap().is_empty() {
+                    return;
+                }
+                self.send_message(cur_chat, requests);
+                cur_chat.msg_state = MsgState::Normal;
+            }
+
+            // Switch how this chat's messages are formatted
+            Key::Ctrl('t') => cur_chat.parse_mode = cur_chat.parse_mode.next(),
+
+            _ => {
+                let mut editor = cur_chat.input.lock().unwrap();
+                self.vim.lock().unwrap().handle_key(&mut editor, input);
+            }
+        }
+    }
+}
+impl TBlock for TChats {
+    fn new(name: &'static str) -> TChats {
+        TChats {
+            chat_vec: Arc::new(Mutex::new(Vec::new())),
+            selected_index: Arc::new(Mutex::new(Some(0))),
+            name,
+            view: Arc::new(Mutex::new(View::all(ChatListId::Main))),
+            shown: Arc::new(Mutex::new(0)),
+            tabs: Arc::new(Mutex::new(TabsState::new(&[]))),
+            selected_in: Arc::new(Mutex::new(HashMap::new())),
+            paging: Arc::new(Mutex::new(HashMap::new())),
+            unsorted: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    // Get number of chats in the list shown
+    fn get_len(&self) -> usize {
+        return *self.shown.lock().unwrap();
+    }
+
+    fn scroll_up(&mut self) {
+        if self.get_len() == 0 {
+            return;
+        }
+        let selected_index = match self.selected_index() {
+            // Wrap around when going over top
+            Some(0) | None => self.get_len() - 1,
+            // Go up by one chat
+            Some(i) => (i - 1) % self.get_len(),
+        };
+
+        self.set_selected_index(selected_index);
+    }
+
+    fn scroll_down(&mut self) {
+        if self.get_len() == 0 {
+            return;
+        }
+        // Wrap around to top of list
+        let i = self
+            .selected_index()
+            .map_or(0, |i| (i + 1) % self.get_len());
+        self.set_selected_index(i);
+    }
+    fn page_down(&mut self) {}
+    fn page_up(&mut self) {}
+    fn go_to_top(&mut self) {
+        if self.get_len() > 0 {
+            self.set_selected_index(0);
+        }
+    }
+    fn go_to_bottom(&mut self) {
+        if self.get_len() > 0 {
+            self.set_selected_index(self.get_len() - 1);
+        }
+    }
+
+    fn handle_action_normal(&mut self, requests: &Requests, action: Action) {
+        match action {
+            Action::ScrollDown => self.scroll_down(),
+            Action::ScrollUp => self.scroll_up(),
+            Action::Bottom => self.go_to_bottom(),
+            Action::Top => self.go_to_top(),
+            _ => {}
+        }
+        self.load_more(requests);
+    }
+}
+
+impl TBlock for TChat {
+    fn new(_name: &'static str) -> TChat {
+        TChat {
+            chat: Chat::builder().build(),
+            history: Arc::new(Mutex::new(History::new())),
+            retrieving: None,
+            history_failed: None,
+            num_onscreen: 0,
+            bottom_index: 0,
+            jump_to: None,
+            replied: HashMap::new(),
+            select_index: 0,
+            marked: BTreeSet::new(),
+            range_start: None,
+            msg_state: MsgState::Normal,
+            last_msg_date: Arc::new(Mutex::new(-1)),
+            positions: HashMap::new(),
+            is_bot: false,
+            input: Arc::new(Mutex::new(Editor::new())),
+            parse_mode: ParseMode::Markdown,
+            notification_settings: Value::Null,
+            online_member_count: 0,
+            unread_count: 0,
+            unread_mention_count: 0,
+            last_read_inbox_message_id: 0,
+            last_read_outbox_message_id: 0,
+            viewed_up_to: 0,
+        }
+    }
+    // Go all the way to the bottom (e.g. newest message)
+    fn go_to_bottom(&mut self) {
+        self.history.lock().unwrap().show_latest();
+        self.bottom_index = 0;
+    }
+
+    // Go up to the oldest message retrieved so far
+    fn go_to_top(&mut self) {
+        self.bottom_index = self.get_len().saturating_sub(self.num_onscreen);
+    }
+
+    fn get_len(&self) -> usize {
+        return self.history.lock().unwrap().messages().len();
+    }
+
+    fn handle_action_visual(&mut self, _requests: &Requests, action: Action, _cur_chat_id: i64) {
+        match action {
+            Action::ScrollDown => self.select_down(),
+            Action::ScrollUp => self.select_up(),
+            Action::Reply => self.select_msg(MsgAction::Reply),
+            Action::Edit => self.select_msg(MsgAction::Edit),
+            Action::Mark => self.toggle_mark(),
+            Action::SelectRange => self.toggle_range(),
+            _ => {}
+        }
+    }
+
+    // Scroll up such that the topmost message becomes the bottom one
+    fn page_up(&mut self) {
+        self.history_failed = None;
+        self.bottom_index += self.num_onscreen;
+    }
+    //TODO: fiddle with scrolling off-by-one
+    fn page_down(&mut self) {
+        self.history_failed = None;
+
+        // If the bottom message is also the newest one, make sure it's flush
+        // with the bottom of the box, as to avoid blank gaps at bottom
+        if (self.bottom_index as i64 - self.num_onscreen as i64) <= 0 {
+            self.bottom_index = 0;
+            return;
+        }
+
+        // Reduce bottom index by page height
+        self.bottom_index -= self.num_onscreen;
+    }
+    fn scroll_up(&mut self) {
+        self.history_failed = None;
+        if self.bottom_index + self.num_onscreen < self.get_len() {
+            self.bottom_index += 1;
+        }
+    }
+    fn scroll_down(&mut self) {
+        self.history_failed = None;
+
+        // Use same safeguard as paging down to ensure bottom message is always flush with
+        // bottom of box
+        if self.bottom_index as i64 <= 0 {
+            self.bottom_index = 0;
+            return;
+        }
+        self.bottom_index -= 1;
+    }
+}
+
+// Run the client until it's quit
+pub fn run() {
+    let config = match Config::load(
+        get_arg(config::CONFIG_ARG).map(PathBuf::from),
+        get_arg(config::ACCOUNT_ARG),
+    ) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // Telegram API access object, or a stand-in replaying a script when running offline
+    let tdlib: Box<dyn Transport> = match get_arg(transport::REPLAY_ARG) {
+        Some(path) => match FakeTransport::from_file(&path) {
+            Ok(f) => Box::new(f),
+            Err(e) => {
+                eprintln!("Couldn't load replay {}: {}", path, e);
+                std::process::exit(1);
+            }
+        },
+        None => Box::new(Tdlib::new()),
+    };
+
+    // Set verbosity level to handle volume of debug output
+    let set_verbosity_level = SetLogVerbosityLevel::builder()
+        .new_verbosity_level(config.tdlib_verbosity)
+        .build();
+    tdlib.send(&set_verbosity_level.to_json().unwrap());
+
+    // Set up cross-thread communication
+    let (tx_to_td, rx_td) = channel::unbounded::<MsgCode>();
+    let (tx_to_ren, rx_ren) = channel::unbounded::<MsgCode>();
+
+    // Start parallel threads, one for UI, the other for managing requests with Tdlib
+    thread::scope(|s| {
+        let mut app = App::new(config);
+
+        // Create an Arc reference to pass into request (receiving) thread
+        let mut rec_app = app.clone();
+        let mut render_app = app.clone();
+        let _rec_thread = s.spawn(move |_| {
+            // Spawn thread for managing requests
+            td_thread(&*tdlib, &mut rec_app, &rx_td);
+        });
+        let _render_thread = s.spawn(move |_| {
+            // Spawn thread for managing requests
+            render_thread(&mut render_app, &rx_ren).unwrap();
+            std::process::exit(0);
+        });
+
+        // Spawn UI thread
+        ui_thread(&mut app, &tx_to_td, &tx_to_ren).unwrap();
+    })
+    .unwrap();
+}
+
+// Initialization parameters for Tdlib
+fn send_tdlib_parameters(requests: &Requests, config: &Config) {
+    let set_tdlib_parameters = SetTdlibParameters::builder()
+        .parameters(
+            TdlibParameters::builder()
+                // Don't use test data, communicate with actual Telegram
+                .use_test_dc(false)
+                .database_directory(&config.database_directory)
+                .files_directory(&config.files_directory)
+                // Keep chats, messages and files between runs
+                .use_file_database(true)
+                .use_chat_info_database(true)
+                .use_message_database(true)
+                .api_id(config.api_id)
+                .api_hash(&config.api_hash)
+                .system_language_code("en")
+                .device_model("computer")
+                .application_version("0.0.1")
+                .build(),
+        )
+        .build();
+
+    requests.send(&set_tdlib_parameters);
+}
+
+// Read a database encryption key from file, ignoring trailing newlines
+fn read_key_file(path: &str) -> io::Result<String> {
+    let key = std::fs::read_to_string(path)?;
+    Ok(key.trim_end_matches(&['\r', '\n'][..]).to_string())
+}
+
+// Get current user
+fn get_me(requests: &Requests) {
+    let req = GetMe::builder().build();
+    requests.send_then(&req, |app, reply| match reply {
+        Ok(me) => match User::from_json(me.to_string()) {
+            Ok(me) => app.me = me,
+            Err(e) => eprintln!("Couldn't parse current user: {}", e),
+        },
+        Err(e) => app.set_status(format!("Couldn't get current user: {}", e)),
+    });
+}
+
+// Command line flag to change the local database's passphrase after logging in
+const REKEY_ARG: &str = "--rekey";
+
+// How often to check for requests that have gone unanswered
+const EXPIRY_INTERVAL: Duration = Duration::from_secs(1);
+
+// Most messages Tdlib gives for one GetChatHistory
+const HISTORY_LIMIT: i64 = 100;
+
+// How close to the end of the chats got the chat list is scrolled before more are got
+const CHATS_AHEAD: usize = 50;
+
+// Driver for Tdlib communication. Woken by whichever comes first of an update from
+// Tdlib, a request to send, or an exit message
+pub fn td_thread(tdlib: &dyn Transport, app: &mut App, rx: &Receiver<MsgCode>) {
+    let config = app.config.clone();
+
+    // Ask for a new database passphrase once logged in
+    if std::env::args().any(|a| a == REKEY_ARG) {
+        app.login.lock().unwrap().request_rekey();
+    }
+
+    // Receiving blocks, so it's done on a thread of its own
+    let (tx_incoming, incoming) = channel::unbounded::<String>();
+    let stop = AtomicBool::new(false);
+    thread::scope(|s| {
+        s.spawn(|_| receive_thread(tdlib, config.receive_timeout, &tx_incoming, &stop));
+        let expiry = channel::tick(EXPIRY_INTERVAL);
+        loop {
+            select! {
+                recv(rx) -> _ => break,
+                recv(app.requests.woken()) -> _ => {}
+                recv(expiry) -> _ => requests::expire(app),
+                recv(incoming) -> res => match res {
+                    Ok(res) => {
+                        // Handle everything already received before sorting the chats
+                        // once for the lot
+                        handle_received(app, &res);
+                        for res in incoming.try_iter() {
+                            handle_received(app, &res);
+                        }
+                        if app.chat_list.sort_if_unsorted() {
+                            app.redraw.request();
+                        }
+                    }
+                    Err(_) => break,
+                },
+            }
+
+            // Send each request in queue, in order
+            for s in app.requests.take_outgoing() {
+                tdlib.send(&s);
+            }
+        }
+        stop.store(true, Ordering::Relaxed);
+    })
+    .unwrap();
+}
+
+// Pass everything received from Tdlib to `tx`, until `stop` is set
+fn receive_thread(tdlib: &dyn Transport, timeout: f64, tx: &Sender<String>, stop: &AtomicBool) {
+    while !stop.load(Ordering::Relaxed) {
+        if let Some(res) = tdlib.receive(timeout) {
+            if tx.send(res).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+// Handle a reply or update received from Tdlib
+fn handle_received(app: &mut App, res: &str) {
+    // Decode request string into an object
+    let mut obj: Value = match serde_json::from_str(res) {
+        Ok(o) => o,
+        Err(e) => {
+            eprintln!("Couldn't decode {}: {}", res, e);
+            return;
+        }
+    };
+    if app.config.debug {
+        eprintln!("Received: {:?}", obj);
+    }
+
+    // Replies to requests go to whoever sent the request, and may go by the chats' order
+    if requests::is_reply(&obj) {
+        app.chat_list.sort_if_unsorted();
+        requests::handle_reply(app, obj);
+        app.redraw.request();
+        return;
+    }
+
+    // A bad update shouldn't take down the client; log it and move on
+    if let Err(e) = updates::dispatch(app, &mut obj) {
+        eprintln!("Skipping {}: {}", obj["@type"], e);
+    }
+}
+
+fn render_thread(app: &mut App, rx: &Receiver<MsgCode>) -> Result<(), std::io::Error> {
+    let selected_style: Style = Style::default()
+        .fg(Color::Blue)
+        .add_modifier(Modifier::BOLD);
+    let unselected_style: Style = Style::default()
+        .fg(Color::White)
+        .remove_modifier(Modifier::BOLD);
+    let stdout = io::stdout().into_raw_mode()?;
+    let stdout = MouseTerminal::from(stdout);
+    let stdout = AlternateScreen::from(stdout);
+    let backend = TermionBackend::new(stdout);
+    let mut terminal = Terminal::new(backend)?;
+    let config = app.config.clone();
+
+    // Have pasted text marked, so that newlines in it don't send the message
+    write!(terminal.backend_mut(), "{}", event::ENABLE_BRACKETED_PASTE)?;
+    let margin = config.margin;
+
+    terminal.clear()?;
+    let mut chat_box_height: usize = 0;
+    let mut chat_box_width: usize = 0;
+    let mut last_draw = Instant::now();
+    app.redraw.request();
+    loop {
+        select! {
+            recv(rx) -> _ => break,
+            recv(app.redraw.woken()) -> _ => {}
+        }
+
+        // Hold off until a frame's time since the last draw, so that changes
+        // arriving in the meantime are drawn together
+        let wait = config.frame_time.checked_sub(last_draw.elapsed());
+        if let Some(wait) = wait {
+            if rx.recv_timeout(wait).is_ok() {
+                break;
+            }
+        }
+        if !app.redraw.take() {
+            continue;
+        }
+        last_draw = Instant::now();
+
+        terminal.draw(|f| {
+            // Until authorized, only the login screen is shown
+            let login = app.login.lock().unwrap();
+            if login.is_active() {
+                login::render_login(f, &login, &config.account);
+                return;
+            }
+            drop(login);
+
+            // The command line while typing a command, otherwise any status message,
+            // takes the bottom line of the screen
+            let mut size = f.size();
+            let bottom_line = Rect::new(size.x, size.y + size.height - 1, size.width, 1);
+            if let Some((command, cursor)) =
+                app.command_line.lock().unwrap().view(size.width as usize)
+            {
+                size.height = size.height.saturating_sub(1);
+                f.render_widget(Paragraph::new(command), bottom_line);
+                f.set_cursor(bottom_line.x + cursor as u16, bottom_line.y);
+            } else if let Some(status) = app.status.lock().unwrap().as_ref() {
+                size.height = size.height.saturating_sub(1);
+                let status_line =
+                    Paragraph::new(status.as_str()).style(Style::default().fg(Color::Red));
+                f.render_widget(status_line, bottom_line);
+            }
+
+            let chunks = Layout::default()
+                .direction(Direction::Vertical)
+                .margin(margin)
+                .constraints(
+                    [
+                        Constraint::Percentage(100 - config.input_height),
+                        Constraint::Percentage(config.input_height),
+                    ]
+                    .as_ref(),
+                )
+                .split(size);
+            let chat_chunks = Layout::default()
+                .direction(Direction::Horizontal)
+                .margin(margin)
+                .constraints(
+                    [
+                        Constraint::Percentage(config.chat_list_width),
+                        Constraint::Percentage(100 - config.chat_list_width),
+                    ]
+                    .as_ref(),
+                )
+                .split(chunks[0]);
+
+            // Tabs go above the chat list, when there's more than one
+            let tabs = app.chat_list.tabs.lock().unwrap();
+            let (tab_titles, tab_index) = (tabs.titles(), tabs.index);
+            drop(tabs);
+            let (tabs_area, chat_list_area) = if tab_titles.len() > 1 {
+                let areas = Layout::default()
+                    .constraints([Constraint::Length(1), Constraint::Min(0)].as_ref())
+                    .split(chat_chunks[0]);
+                (Some(areas[0]), areas[1])
+            } else {
+                (None, chat_chunks[0])
+            };
+            let mut chat_titles = vec::Vec::new();
+            let ui_users = app.users.lock().unwrap();
+            let ui_basic_groups = app.basic_groups.lock().unwrap();
+            let mut ui_supergroups = app.supergroups.lock().unwrap();
+            let ui_secret_chats = app.secret_chats.lock().unwrap();
+            chat_box_height = (chat_chunks[1].bottom() - chat_chunks[1].top() - 2 * margin).into();
+            chat_box_width = (chat_chunks[1].right() - chat_chunks[1].left() - 2 * margin).into();
+            let chat_list_width = chat_list_area.width.saturating_sub(2) as usize;
+            let mut chat_history = vec::Vec::new();
+            let mut senders = Senders {
+                users: &ui_users,
+                chats: HashMap::new(),
+                palette: &config.palette,
+            };
+            let (list, shown) = (app.chat_list.list(), app.chat_list.get_len());
+            for (i, chat) in (app.chat_list.chat_vec)
+                .lock()
+                .unwrap()
+                .iter_mut()
+                .enumerate()
+            {
+                senders
+                    .chats
+                    .insert(chat.chat.id(), chat.chat.title().to_string());
+                if i >= shown {
+                    continue;
+                }
+                let title_item = ListItem::new(chat_list_entry(chat, list, chat_list_width));
+                chat_titles.push(title_item.style(unselected_style));
+            }
+            if app.chat_list.loading() {
+                chat_titles.push(
+                    ListItem::new("Loading chats…").style(Style::default().fg(Color::DarkGray)),
+                );
+            }
+            let mut chats_block = List::new(chat_titles)
+                .block(
+                    Block::default()
+                        .title(match app.chat_list.list() {
+                            ChatListId::Archive => "Archive",
+                            _ => app.chat_list.name,
+                        })
+                        .borders(Borders::ALL),
+                )
+                .highlight_style(selected_style);
+            if app.selected_block() == TBlocks::ChatList {
+                chats_block = chats_block.style(selected_style);
+            }
+
+            // Scrolled to keep the selected chat in view, unless it's from outside the list
+            let selected = app.chat_list.selected_index().filter(|&i| i < shown);
+            app.chat_list_state.select(selected);
+            f.render_stateful_widget(chats_block, chat_list_area, &mut app.chat_list_state);
+            if let Some(area) = tabs_area {
+                let titles = tab_titles.into_iter().map(Spans::from).collect();
+                let tabs = Tabs::new(titles)
+                    .select(tab_index)
+                    .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+                f.render_widget(tabs, area);
+            }
+
+            // With no chat selected, e.g. in an empty tab, its boxes stay empty
+            let mut cv = app.chat_list.chat_vec.lock().unwrap();
+            let chat = match app.chat_list.selected_index().and_then(|i| cv.get_mut(i)) {
+                Some(c) => c,
+                None => {
+                    f.render_widget(Block::default().borders(Borders::ALL), chat_chunks[1]);
+                    f.render_widget(Block::default().borders(Borders::ALL), chunks[1]);
+                    render_help(f, app);
+                    render_popup(f, app);
+                    render_finder(f, app);
+                    render_search_results(f, app);
+                    return;
+                }
+            };
+
+            // Messages marked in visual mode are highlighted, beside the cursor
+            let visual = matches!(app.curr_mode(), InputMode::Visual)
+                && app.selected_block() == TBlocks::CurrChat;
+            let marked = if visual && chat.is_marking() {
+                chat.selected_ids(&chat.history.lock().unwrap())
+            } else {
+                Vec::new()
+            };
+            let search = app.search.lock().unwrap();
+            let highlights = Highlights {
+                query: search.as_ref().and_then(|s| s.query_for(chat.chat.id())),
+                marked,
+                revealed: chat.cursor_message_id().filter(|_| visual),
+            };
+            let (displayed_msgs, history_height) = build_msg_list(
+                chat,
+                &senders,
+                &highlights,
+                chat_box_width,
+                chat_box_height,
+                &mut chat_history,
+            );
+            drop(search);
+            chat.num_onscreen = displayed_msgs;
+            // Retrieve older messages when nearing the top of those retrieved, and newer
+            // ones nearing the bottom when the chat was opened further back
+            let limit = ((chat_box_height * 2) as i64).min(HISTORY_LIMIT);
+            let history = chat.history.lock().unwrap();
+            let (oldest_id, newest_id) = (history.oldest_id(), history.newest_id());
+            let (shows_first, shows_latest) = (history.shows_first(), history.shows_latest());
+            let len = history.messages().len();
+            drop(history);
+            if (history_height < chat_box_height
+                || chat.bottom_index + 2 * chat.num_onscreen >= len)
+                && !shows_first
+            {
+                chat.retrieve_history(&app.requests, oldest_id, 0, limit);
+            } else if chat.bottom_index < chat.num_onscreen && !shows_latest {
+                chat.retrieve_history(&app.requests, newest_id, 1 - limit, limit);
+            }
+            chat.retrieve_replied(&app.requests);
+            chat.view_messages(&app.requests);
+            view_chat(app, chat.chat.id());
+            if let Some(sg) = chat
+                .chat
+                .type_()
+                .as_supergroup()
+                .and_then(|t| ui_supergroups.get_mut(&t.supergroup_id()))
+            {
+                sg.retrieve_full_info(&app.requests);
+            }
+            let extra_info = chat_info(
+                chat,
+                &ui_users,
+                &ui_basic_groups,
+                &ui_supergroups,
+                &ui_secret_chats,
+            );
+            match app.curr_mode() {
+                InputMode::Visual if app.selected_block() == TBlocks::CurrChat => {
+                    app.chat_history_state.select(Some(chat.select_index))
+                }
+                _ => app.chat_history_state.select(None),
+            }
+
+            let chat_title = format!("{}: {}", *chat.chat.title(), extra_info);
+            let mut chat_block = List::new(chat_history)
+                .block(Block::default().title(chat_title).borders(Borders::ALL))
+                .highlight_style(Style::default().bg(Color::Yellow))
+                .start_corner(Corner::BottomLeft);
+
+            let mut input_title = format!("{} ({})", app.input_box.name, chat.parse_mode.name());
+            let writing = app.selected_block() == TBlocks::Input
+                && matches!(app.curr_mode(), InputMode::Insert);
+            if let Some(vim) = app.input_box.vim.lock().unwrap().indicator() {
+                if writing {
+                    input_title = format!("{} {}", input_title, vim);
+                }
+            }
+            let mut input_block = Block::default()
+                .title(input_title.as_str())
+                .borders(Borders::ALL);
+            let mut editor = chat.input.lock().unwrap();
+            let input_str = editor.text().to_string();
+            let input_inner = input_block.inner(chunks[1]);
+            let (input_lines, (cursor_x, cursor_y)) =
+                editor.view(input_inner.width as usize, input_inner.height as usize);
+            drop(editor);
+            let input_lines: Vec<Spans> = input_lines.into_iter().map(Spans::from).collect();
+            let input = Paragraph::new(input_lines).style(unselected_style).block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(input_title.as_str()),
+            );
+
+            // Show where typing goes while writing a message
+            if writing {
+                f.set_cursor(
+                    input_inner.x + cursor_x as u16,
+                    input_inner.y + cursor_y as u16,
+                );
+            }
+
+            match app.selected_block() {
+                TBlocks::ChatList => {}
+                TBlocks::CurrChat => chat_block = chat_block.style(selected_style),
+                TBlocks::Input => input_block = input_block.style(selected_style),
+            }
+
+            f.render_widget(input_block, chunks[1]);
+            f.render_stateful_widget(chat_block, chat_chunks[1], &mut app.chat_history_state);
+            f.render_widget(input, chunks[1]);
+
+            // Boxes shown above the input box, stacking upwards
+            let mut above_input = chunks[1].y;
+
+            // Preview of how the message will look, once it has any formatting
+            let formatted = markup::parse(&input_str, chat.parse_mode);
+            let has_markup = formatted["entities"]
+                .as_array()
+                .is_some_and(|e| !e.is_empty())
+                || formatted["text"] != input_str.as_str();
+            if has_markup {
+                let spans = format::styled(
+                    formatted["text"].as_str().unwrap_or(""),
+                    &formatted["entities"],
+                    unselected_style,
+                    false,
+                );
+                let lines = format::wrap(&spans, chunks[1].width.saturating_sub(2) as usize);
+                let height = (lines.len() as u16 + 2).min(above_input);
+                let preview_rect =
+                    Rect::new(chunks[1].x, above_input - height, chunks[1].width, height);
+                above_input = preview_rect.y;
+                let preview = Paragraph::new(lines)
+                    .block(Block::default().title("Preview").borders(Borders::ALL));
+                f.render_widget(Clear, preview_rect);
+                f.render_widget(preview, preview_rect);
+            }
+            // The message being replied to or edited, shown above the input box
+            let secondary = match chat.msg_state {
+                MsgState::Normal => None,
+                MsgState::Reply(id) => Some(("Replying to...", id)),
+                MsgState::Edit(id) => Some(("Editing...", id)),
+            };
+            let history = chat.history.lock().unwrap();
+            let secondary = secondary
+                .and_then(|(title, id)| Some((title, history.all().find(|m| m.msg.id() == id)?)));
+            if let Some((secondary_title, secondary_msg)) = secondary {
+                let mut secondary_msg_rect = Rect::new(chunks[1].x, 0, chunks[1].width, 0);
+                let secondary_msg_text = content::describe(secondary_msg, &ui_users);
+                let lines = textwrap::fill(&secondary_msg_text, secondary_msg_rect.width as usize);
+                // Add two for margin and one for first line
+                secondary_msg_rect.height = (lines.matches('\n').count() + 3) as u16;
+                secondary_msg_rect.height = secondary_msg_rect.height.min(above_input);
+                secondary_msg_rect.y = above_input - secondary_msg_rect.height;
+                let secondary_msg_block = Paragraph::new(lines).block(
+                    Block::default()
+                        .title(secondary_title)
+                        .borders(Borders::ALL),
+                );
+                f.render_widget(Clear, secondary_msg_rect);
+                f.render_widget(secondary_msg_block, secondary_msg_rect);
+            }
+            drop(history);
+            render_help(f, app);
+            render_popup(f, app);
+            render_finder(f, app);
+            render_search_results(f, app);
+        })?;
+
+        // Copying goes through the terminal, which passes it on to the system clipboard
+        if let Some(text) = app.clipboard.lock().unwrap().take() {
+            write!(
+                terminal.backend_mut(),
+                "\x1b]52;c;{}\x07",
+                base64::encode(text.as_bytes())
+            )?;
+            io::Write::flush(terminal.backend_mut())?;
+        }
+    }
+    write!(terminal.backend_mut(), "{}", event::DISABLE_BRACKETED_PASTE)?;
+    Ok(())
+}
+// Draw the results of searching every chat over everything else, while they're listed
+fn render_search_results<B: Backend>(f: &mut Frame<B>, app: &App) {
+    if let Some(results) = app.search_results.lock().unwrap().as_ref() {
+        search::render_results(f, results);
+    }
+}
+
+// Draw the popup for the selected messages over the chat, if there is one
+fn render_popup<B: Backend>(f: &mut Frame<B>, app: &App) {
+    if let Some(popup) = app.popup.lock().unwrap().as_ref() {
+        selected::render_popup(f, popup);
+    }
+}
+
+// Draw the chat switcher over everything else, if it's open
+fn render_finder<B: Backend>(f: &mut Frame<B>, app: &App) {
+    if let Some(finder) = app.finder.lock().unwrap().as_mut() {
+        finder::render_finder(f, finder);
+    }
+}
+
+// Draw the key bindings over everything else, if they've been asked for
+fn render_help<B: Backend>(f: &mut Frame<B>, app: &App) {
+    if *app.help.lock().unwrap() {
+        keymap::render_help(f, &app.config.keymap, app.curr_mode(), app.selected_block());
+    }
+}
+
+// Who messages are shown as sent by: users, or chats sending on their own behalf
+struct Senders<'a> {
+    users: &'a HashMap<i64, TUser>,
+
+    // Chat titles by ID
+    chats: HashMap<i64, String>,
+
+    // Colours for chats, and anyone unknown
+    palette: &'a [Color],
+}
+
+impl Senders<'_> {
+    // Name and colour to show as sending `msg`. A channel's posts are signed by their
+    // author when the channel asks for it, and otherwise go by the channel's title
+    fn name_and_color(&self, msg: &TMessage) -> (String, Color) {
+        if msg.sender_chat_id != 0 {
+            let color =
+                self.palette[msg.sender_chat_id.unsigned_abs() as usize % self.palette.len()];
+            let name = match self.chats.get(&msg.sender_chat_id) {
+                _ if !msg.msg.author_signature().is_empty() => msg.msg.author_signature().clone(),
+                Some(title) => title.clone(),
+                None => "Unknown Chat".to_string(),
+            };
+            return (name, color);
+        }
+        match self.users.get(&msg.msg.sender_user_id()) {
+            Some(u) => (u.u.first_name().to_string(), u.color),
+            None => ("Unknown User".to_string(), self.palette[0]),
+        }
+    }
+}
+
+// What stands out among the messages shown: words being searched for, messages marked
+// in visual mode, and the one under the cursor, whose spoilers are shown
+struct Highlights<'a> {
+    query: Option<&'a str>,
+    marked: Vec<i64>,
+    revealed: Option<i64>,
+}
+
+/*
+ * Build the message list to be displayed, based on size parameters of chat box
+ */
+
+fn build_msg_list(
+    chat: &TChat,
+    senders: &Senders,
+    highlights: &Highlights,
+    chat_box_width: usize,
+    chat_box_height: usize,
+    chat_history: &mut Vec<ListItem>,
+) -> (usize, usize) {
+    // Track total number of messages displayed, for tracking scroll
+
+    let history = chat.history.lock().unwrap();
+    let h = history.messages();
+
+    let mut history_height = 0;
+    let text_style = Style::default()
+        .remove_modifier(Modifier::BOLD)
+        .fg(Color::White);
+
+    // Iterate through the chat hsitory, starting at the bottommost message that is to be displayed
+    for msg in h.iter().skip(chat.bottom_index) {
+        let (sender_name, sender_color) = senders.name_and_color(msg);
+        let mut spans = vec![
+            Span::styled(
+                sender_name,
+                Style::default()
+                    .remove_modifier(Modifier::BOLD)
+                    .fg(sender_color),
+            ),
+            Span::styled(": ", text_style),
+        ];
+        let reveal = highlights.revealed == Some(msg.msg.id());
+        let mut text = content::describe_styled(msg, senders.users, text_style, reveal);
+        if let Some(q) = highlights.query {
+            text = format::highlight(text, q, Style::default().fg(Color::Black).bg(Color::Yellow));
+        }
+        spans.extend(text);
+        if msg.msg.is_outgoing() {
+            spans.push(delivery_mark(msg, chat.last_read_outbox_message_id));
+        }
+        let mut lis = format::wrap(&spans, chat_box_width);
+        if msg.replied_to().is_some() {
+            let header = reply_header(chat.replied(&history, msg), senders, chat_box_width);
+            lis.insert(0, header);
+        }
+        history_height += lis.len();
+
+        // Peel off lines from the start of the topmost message to display partial message
+        // when cut off
+        while history_height > chat_box_height {
+            lis.remove(0);
+            history_height -= 1;
+        }
+        let mut item = ListItem::new(Text::from(lis));
+        if highlights.marked.contains(&msg.msg.id()) {
+            item = item.style(Style::default().bg(Color::Blue));
+        }
+        chat_history.push(item);
+
+        if history_height >= chat_box_height {
+            break;
+        }
+    }
+    (chat_history.len(), history_height)
+}
+// A chat's title in the chat list, followed by whether it's pinned in `list`, how many
+// messages are unread and how many of them mention us, all cut to fit `width`
+fn chat_list_entry(chat: &TChat, list: ChatListId, width: usize) -> Spans<'static> {
+    let muted = chat.notification_settings["mute_for"].as_i64().unwrap_or(0) > 0;
+    let unread_style = if muted {
+        Style::default().fg(Color::DarkGray)
+    } else {
+        Style::default().fg(Color::Green)
+    };
+    let mut badges = Vec::new();
+    if chat.positions.get(&list).is_some_and(|p| p.is_pinned) {
+        badges.push(Span::styled(" ⚑", Style::default().fg(Color::DarkGray)));
+    }
+    if chat.unread_count > 0 {
+        badges.push(Span::styled(
+            format!(" {}", chat.unread_count),
+            unread_style,
+        ));
+    }
+    if chat.unread_mention_count > 0 {
+        badges.push(Span::styled(
+            format!(" @{}", chat.unread_mention_count),
+            Style::default()
+                .fg(Color::Yellow)
+                .add_modifier(Modifier::BOLD),
+        ));
+    }
+    let badges_width: usize = badges.iter().map(|b| b.content.width()).sum();
+    let title = format::truncate(chat.chat.title(), width.saturating_sub(badges_width));
+    let mut spans = vec![Span::raw(title)];
+    spans.extend(badges);
+    Spans::from(spans)
+}
+
+// What's shown beside a chat's title: when the other user was last seen, or how many are
+// in a group or channel and how many of them are online
+fn chat_info(
+    chat: &TChat,
+    users: &HashMap<i64, TUser>,
+    basic_groups: &HashMap<i64, TBasicGroup>,
+    supergroups: &HashMap<i64, TSupergroup>,
+    secret_chats: &HashMap<i64, TSecretChat>,
+) -> String {
+    let type_ = chat.chat.type_();
+    if let Some(private) = type_.as_private() {
+        match users.get(&private.user_id()) {
+            Some(u) => user_status(&u.status),
+            None => "unknown".to_string(),
+        }
+    } else if let Some(secret) = type_.as_secret() {
+        let user = users.get(&secret.user_id());
+        let state = secret_chats.get(&secret.secret_chat_id()).map(|s| &s.state);
+        match (state, user) {
+            (Some(s), _) if s.is_closed() => "secret chat, closed".to_string(),
+            (Some(s), Some(u)) if s.is_pending() => {
+                let outbound = secret_chats
+                    .get(&secret.secret_chat_id())
+                    .is_some_and(|s| s.is_outbound);
+                if outbound {
+                    format!(
+                        "secret chat, waiting for {} to come online",
+                        u.u.first_name()
+                    )
+                } else {
+                    "secret chat, being set up".to_string()
+                }
+            }
+            (_, Some(u)) => format!("secret chat, {}", user_status(&u.status)),
+            (_, None) => "secret chat".to_string(),
+        }
+    } else if let Some(group) = type_
+        .as_basic_group()
+        .and_then(|t| basic_groups.get(&t.basic_group_id()))
+    {
+        // Count up how many members in chat are online
+        let members_online = group
+            .full_info
+            .members()
+            .iter()
+            .filter_map(|m| users.get(&m.user_id()))
+            .filter(|u| u.status.is_online() && u.u.type_().is_regular())
+            .count();
+        format!(
+            "{} members, {} online",
+            group.g.member_count(),
+            members_online
+        )
+    } else if let Some(sg) = type_
+        .as_supergroup()
+        .and_then(|t| supergroups.get(&t.supergroup_id()))
+    {
+        // The full info's count is known for sure, where the supergroup's may not be
+        let count = sg.full_info["member_count"]
+            .as_i64()
+            .unwrap_or_else(|| sg.g.member_count());
+        match (sg.g.is_channel(), count) {
+            (true, 0) => "channel".to_string(),
+            (true, n) => format!("{} subscribers", n),
+            (false, 0) => "group".to_string(),
+            (false, n) if chat.online_member_count > 0 => {
+                format!("{} members, {} online", n, chat.online_member_count)
+            }
+            (false, n) => format!("{} members", n),
+        }
+    } else {
+        "unknown".to_string()
+    }
+}
+
+// Whether a user is online, or when they were last seen
+fn user_status(status: &UserStatus) -> String {
+    if status.is_online() {
+        "online".to_string()
+    } else if let Some(offline) = status.as_offline() {
+        let ts: u64 = offline.was_online() as u64;
+        let d = std::time::UNIX_EPOCH + std::time::Duration::from_secs(ts);
+        let date_time = DateTime::<Local>::from(d);
+        format!("last seen {}", date_time.format("%H:%M on %m/%d"))
+    } else {
+        "unknown".to_string()
+    }
+}
+
+// Tell Tdlib which chat is open, closing the one open before, so it sends what's only
+// sent about open chats
+fn view_chat(app: &App, chat_id: i64) {
+    let mut viewing = app.viewing.lock().unwrap();
+    if *viewing == Some(chat_id) {
+        return;
+    }
+    if let Some(old) = viewing.replace(chat_id) {
+        app.requests
+            .send(&CloseChat::builder().chat_id(old).build());
+    }
+    app.requests
+        .send(&OpenChat::builder().chat_id(chat_id).build());
+}
+
+// Mark after a message we sent, showing whether it's being sent, couldn't be, has been
+// sent, or has been read
+fn delivery_mark(msg: &TMessage, last_read_outbox_message_id: i64) -> Span<'static> {
+    let (mark, color) = match msg.msg.sending_state() {
+        Some(state) if state.is_pending() => (" …", Color::DarkGray),
+        Some(_) => (" ✗ not sent", Color::Red),
+        None if msg.msg.id() <= last_read_outbox_message_id => (" ✓✓", Color::Green),
+        None => (" ✓", Color::DarkGray),
+    };
+    Span::styled(mark, Style::default().fg(color))
+}
+
+// Line above a reply quoting who it replies to and the start of what they said
+fn reply_header(original: Option<&TMessage>, senders: &Senders, width: usize) -> Spans<'static> {
+    let quote_style = Style::default().fg(Color::DarkGray);
+    let mut spans = vec![Span::styled("> ", quote_style)];
+    let original = match original {
+        Some(m) => m,
+        None => {
+            spans.push(Span::styled("…", quote_style));
+            return Spans::from(spans);
+        }
+    };
+    let (name, color) = senders.name_and_color(original);
+    let snippet = content::describe(original, senders.users).replace('\n', " ");
+    let used = 2 + name.width() + 2;
+    spans.push(Span::styled(name, Style::default().fg(color)));
+    spans.push(Span::styled(": ", quote_style));
+    spans.push(Span::styled(
+        format::truncate(&snippet, width.saturating_sub(used)),
+        quote_style,
+    ));
+    Spans::from(spans)
+}
+
+/*
+ *   Get specified command line argument
+ */
+
+fn get_arg(arg_name: &str) -> Option<String> {
+    std::env::args().find_map(|arg| arg.strip_prefix(arg_name).map(|a| a.trim().to_string()))
+}
+// Parse a message from Tdlib's JSON. When rtdlib can't handle it, only the fields
+// needed to show the message are kept
+fn parse_msg(cur_msg: &mut Value, chat_id: i64) -> Result<TMessage, UpdateError> {
+    // ANOTHER WEIRD STOPGAP
+    cur_msg["sender_user_id"] = cur_msg["sender"]["user_id"].to_owned();
+    cur_msg["views"] = json!(1);
+    let content = cur_msg["content"].clone();
+    let reply_in_chat_id = cur_msg["reply_in_chat_id"].as_i64().unwrap_or(0);
+    let sender_chat_id = cur_msg["sender"]["chat_id"].as_i64().unwrap_or(0);
+    let is_pinned = cur_msg["is_pinned"].as_bool().unwrap_or(false);
+    let forward_info = cur_msg["forward_info"].clone();
+    if !content["@type"].is_string() {
+        return Err(UpdateError::Malformed("content"));
+    }
+    if let Ok(msg) = Message::from_json(cur_msg.to_string()) {
+        return Ok(TMessage {
+            msg,
+            content,
+            reply_in_chat_id,
+            sender_chat_id,
+            is_pinned,
+            forward_info,
+        });
+    }
+    let get = |field: &'static str| cur_msg[field].as_i64().ok_or(UpdateError::Malformed(field));
+    let flag = |field: &str| cur_msg[field].as_bool().unwrap_or(false);
+    let mut builder = Message::builder();
+    builder
+        .chat_id(chat_id)
+        .id(get("id")?)
+        .date(get("date")?)
+        .edit_date(cur_msg["edit_date"].as_i64().unwrap_or(0))
+        // Messages sent on behalf of a chat have no sending user
+        .sender_user_id(cur_msg["sender_user_id"].as_i64().unwrap_or(0))
+        .reply_to_message_id(cur_msg["reply_to_message_id"].as_i64().unwrap_or(0))
+        .is_outgoing(flag("is_outgoing"))
+        .is_channel_post(flag("is_channel_post"))
+        .author_signature(cur_msg["author_signature"].as_str().unwrap_or(""))
+        .can_be_edited(flag("can_be_edited"))
+        .can_be_forwarded(flag("can_be_forwarded"))
+        .can_be_deleted_only_for_self(flag("can_be_deleted_only_for_self"))
+        .can_be_deleted_for_all_users(flag("can_be_deleted_for_all_users"));
+    if let Ok(state) = MessageSendingState::from_json(cur_msg["sending_state"].to_string()) {
+        builder.sending_state(state);
+    }
+    let msg = builder.build();
+    Ok(TMessage {
+        msg,
+        content,
+        reply_in_chat_id,
+        sender_chat_id,
+        is_pinned,
+        forward_info,
+    })
+}
+
+fn edit_message(requests: &Requests, chat_id: i64, msg_id: i64, msg: FormattedText) {
+    let msg_text =
+        InputMessageContent::InputMessageText(InputMessageText::builder().text(msg).build());
+    let req = EditMessageText::builder()
+        .chat_id(chat_id)
+        .message_id(msg_id)
+        .input_message_content(msg_text)
+        .build();
+    requests.send_then(&req, |app, reply| {
+        if let Err(e) = reply {
+            app.set_status(format!("Couldn't edit message: {}", e));
+        }
+    });
+}
+
+// Insert pasted text where it's being typed
+fn paste(app: &mut App, text: &str) {
+    // Terminals may send Windows line endings, or carriage returns alone
+    let text = text.replace("\r\n", "\n").replace('\r', "\n");
+
+    // Login prompts are a single line
+    let mut login = app.login.lock().unwrap();
+    if login.is_active() {
+        for c in text.chars().filter(|c| !c.is_control()) {
+            login.handle_input(&app.requests, &Key::Char(c));
+        }
+        return;
+    }
+    drop(login);
+
+    if app.selected_block() != TBlocks::Input {
+        return;
+    }
+    if let InputMode::Insert = app.curr_mode() {
+        app.chat_list.with_selected(|chat| {
+            let mut editor = chat.input.lock().unwrap();
+            editor.checkpoint();
+            editor.insert_str(&text);
+        });
+    }
+}
+
+fn ui_thread(
+    app: &mut App,
+    tx_to_td: &Sender<MsgCode>,
+    tx_to_ren: &Sender<MsgCode>,
+) -> Result<(), std::io::Error> {
+    let exit_key = app.config.exit_key;
+    let events = Events::with_config(event::Config {
+        tick_rate: app.config.tick_rate,
+    });
+
+    // Keys typed towards a binding in normal or visual mode
+    let mut pending = keymap::Pending::default();
+    loop {
+        let enext = match events.next() {
+            Ok(eve) => eve,
+            Err(_e) => return Err(Error::other("oh no!")),
+        };
+        if let Event::Paste(text) = &enext {
+            paste(app, text);
+            app.redraw.request();
+            continue;
+        }
+        if let Event::Input(input) = enext {
+            // Status messages last until the next keypress
+            *app.status.lock().unwrap() = None;
+            app.redraw.request();
+
+            // While logging in, all input goes to the login screen
+            let mut login = app.login.lock().unwrap();
+            if login.is_active() {
+                if input == exit_key {
+                    tx_to_ren.send(MsgCode::Exit).unwrap();
+                    tx_to_td.send(MsgCode::Exit).unwrap();
+                    return Ok(());
+                }
+                login.handle_input(&app.requests, &input);
+                continue;
+            }
+            drop(login);
+
+            if app.command_line.lock().unwrap().is_open() {
+                if let Some(Action::Quit) = command::handle_key(app, input) {
+                    tx_to_ren.send(MsgCode::Exit).unwrap();
+                    tx_to_td.send(MsgCode::Exit).unwrap();
+                    return Ok(());
+                }
+                continue;
+            }
+            if app.finder.lock().unwrap().is_some() {
+                finder::handle_key(app, input);
+                continue;
+            }
+            if app.search_results.lock().unwrap().is_some() {
+                search::handle_results_key(app, input);
+                continue;
+            }
+            if app.popup.lock().unwrap().is_some() {
+                selected::handle_popup_key(app, input);
+                continue;
+            }
+
+            // Any key closes the help
+            let mut help = app.help.lock().unwrap();
+            if *help {
+                *help = false;
+                continue;
+            }
+            drop(help);
+
+            if input == Key::Esc {
+                pending = keymap::Pending::default();
+
+                // Writing a message, Esc first switches to vim's normal mode, then leaves
+                // the input box
+                if app.selected_block() == TBlocks::Input {
+                    if let InputMode::Insert = app.curr_mode() {
+                        let escaped = app.chat_list.with_selected(|chat| {
+                            let mut editor = chat.input.lock().unwrap();
+                            app.input_box.vim.lock().unwrap().escape(&mut editor)
+                        });
+                        if escaped == Some(true) {
+                            continue;
+                        }
+                    }
+                }
+                app.set_curr_mode(InputMode::Normal);
+                continue;
+            }
+
+            let (mode, block) = (app.curr_mode(), app.selected_block());
+            if let InputMode::Insert = mode {
+                if block == TBlocks::Input {
+                    let (input_box, requests) = (&mut app.input_box, &app.requests);
+                    app.chat_list.with_selected(|chat| {
+                        input_box.handle_input_insert(requests, &input, chat)
+                    });
+                }
+                continue;
+            }
+
+            let (action, count) = match app.config.keymap.feed(&mut pending, mode, block, input) {
+                Some(a) => a,
+                None => continue,
+            };
+            match action {
+                Action::Quit => {
+                    tx_to_ren.send(MsgCode::Exit).unwrap();
+                    tx_to_td.send(MsgCode::Exit).unwrap();
+                    return Ok(());
+                }
+                Action::Help => *app.help.lock().unwrap() = true,
+                Action::Command => app.command_line.lock().unwrap().open(Prompt::Command),
+                Action::Search => app.command_line.lock().unwrap().open(Prompt::Search),
+                Action::SearchAll => app.command_line.lock().unwrap().open(Prompt::SearchAll),
+                Action::NextMatch | Action::PrevMatch => {
+                    for _ in 0..count {
+                        search::next_match(app, action == Action::NextMatch);
+                    }
+                }
+                Action::PinChat => positions::toggle_pinned(app),
+                Action::ArchiveChat => positions::toggle_archived(app),
+                Action::ShowArchive => positions::toggle_archive(app),
+                Action::NextTab => tabs::switch(app, true),
+                Action::PrevTab => tabs::switch(app, false),
+                Action::FindChat => {
+                    let finder = Finder::new(app);
+                    *app.finder.lock().unwrap() = Some(finder);
+                }
+                Action::NextBlock => app.set_selected_block(match block {
+                    TBlocks::ChatList => TBlocks::CurrChat,
+                    TBlocks::CurrChat => TBlocks::Input,
+                    TBlocks::Input => TBlocks::ChatList,
+                }),
+                Action::Insert => {
+                    app.set_curr_mode(InputMode::Insert);
+                    app.chat_list.with_selected(|chat| {
+                        let mut editor = chat.input.lock().unwrap();
+                        app.input_box.vim.lock().unwrap().insert(&mut editor);
+                    });
+                }
+                Action::Visual => {
+                    app.set_curr_mode(InputMode::Visual);
+                    app.chat_list.with_selected(|c| {
+                        c.select_index = 0;
+                        c.clear_selection();
+                    });
+                }
+
+                Action::Delete => selected::delete(app),
+                Action::Forward => selected::forward(app),
+                Action::Copy => selected::copy(app),
+                Action::TogglePin => selected::toggle_pin(app),
+                Action::Details => selected::details(app),
+                Action::GoToReplied => {
+                    let replied = app.chat_list.with_selected(|c| {
+                        let history = c.history.lock().unwrap();
+                        let selected = history.messages().get(c.bottom_index + c.select_index)?;
+                        Some((c.chat.id(), selected.replied_to()))
+                    });
+                    match replied.flatten() {
+                        Some((this_chat, Some((chat_id, message_id)))) if this_chat == chat_id => {
+                            search::jump_to_message(app, chat_id, message_id)
+                        }
+                        Some((_, Some((chat_id, message_id)))) => {
+                            app.open_message(chat_id, message_id)
+                        }
+                        Some((_, None)) => app.set_status("Not a reply".to_string()),
+                        None => {}
+                    }
+                }
+
+                // Everything else moves or acts within the selected block, `count` times
+                _ => {
+                    let i = app.chat_list.selected_index();
+                    let cur_chat_id = app.chat_list.selected_chat_id();
+                    for _ in 0..count {
+                        match (mode, block) {
+                            (InputMode::Normal, TBlocks::ChatList) => {
+                                app.chat_list.handle_action_normal(&app.requests, action)
+                            }
+                            (InputMode::Normal, TBlocks::CurrChat) => {
+                                let mut cv = app.chat_list.chat_vec.lock().unwrap();
+                                if let Some(c) = i.and_then(|i| cv.get_mut(i)) {
+                                    c.handle_action_normal(&app.requests, action);
+                                }
+                            }
+                            (InputMode::Visual, TBlocks::CurrChat) => {
+                                let mut cv = app.chat_list.chat_vec.lock().unwrap();
+                                let c = i.and_then(|i| cv.get_mut(i));
+                                if let (Some(c), Some(id)) = (c, cur_chat_id) {
+                                    c.handle_action_visual(&app.requests, action, id);
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,402 @@
+use crate::requests::{Reply, Requests};
+use crate::App;
+use rtdlib::types::*;
+use termion::event::Key;
+use tui::{
+    backend::Backend,
+    layout::Rect,
+    style::{Color, Modifier, Style},
+    text::{Span, Spans},
+    widgets::{Block, Borders, Clear, Paragraph, Wrap},
+    Frame,
+};
+
+// Width and height of the login box
+const LOGIN_WIDTH: u16 = 60;
+const LOGIN_HEIGHT: u16 = 9;
+
+// Piece of information the login screen is currently asking for
+#[derive(PartialEq, Clone, Copy)]
+pub enum LoginStep {
+    PhoneNumber,
+    Code,
+    Password,
+    FirstName,
+    LastName,
+
+    // Passphrase unlocking the local database
+    EncryptionKey,
+
+    // New passphrase for the local database, and the same again to confirm it
+    NewEncryptionKey,
+    ConfirmEncryptionKey,
+}
+
+impl LoginStep {
+    fn prompt(&self) -> &'static str {
+        match self {
+            LoginStep::PhoneNumber => "Phone number (international format, e.g. +15558675309)",
+            LoginStep::Code => "Login code",
+            LoginStep::Password => "Two-step verification password",
+            LoginStep::FirstName => "First name",
+            LoginStep::LastName => "Last name",
+            LoginStep::EncryptionKey => "Passphrase for local data",
+            LoginStep::NewEncryptionKey => "New passphrase for local data",
+            LoginStep::ConfirmEncryptionKey => "Confirm new passphrase",
+        }
+    }
+
+    // Whether what's typed for this step should be hidden
+    fn is_secret(&self) -> bool {
+        matches!(
+            self,
+            LoginStep::Password
+                | LoginStep::EncryptionKey
+                | LoginStep::NewEncryptionKey
+                | LoginStep::ConfirmEncryptionKey
+        )
+    }
+
+    // Whether submitting nothing is meaningful for this step
+    fn allows_empty(&self) -> bool {
+        matches!(
+            self,
+            LoginStep::LastName | LoginStep::NewEncryptionKey | LoginStep::ConfirmEncryptionKey
+        )
+    }
+}
+
+// State of the login screen, shared between the Tdlib, UI and render threads
+pub struct LoginState {
+    // What is currently being prompted for. `None` when no login input is needed
+    pub step: Option<LoginStep>,
+
+    // What the user has typed so far for the current step
+    pub input: String,
+
+    // Extra information for the current step (code delivery method, password hint, etc.)
+    pub hint: String,
+
+    // Error returned by Tdlib for the last submission, shown until the next one
+    pub error: Option<String>,
+
+    // Whether a submission was sent and we're waiting on Tdlib's reply
+    pub waiting: bool,
+
+    // First name entered during registration, held until the last name is entered
+    first_name: String,
+
+    // New database passphrase, held until it's been confirmed
+    new_key: String,
+
+    // Confirmed new database passphrase, to be set once Tdlib is ready
+    pending_key: Option<String>,
+
+    // Whether the new passphrase replaces the key of an already unlocked database,
+    // rather than being chosen while the database is being opened
+    rekeying: bool,
+
+    // Whether to ask for a new passphrase once logged in
+    rekey_requested: bool,
+}
+
+impl LoginState {
+    pub fn new() -> LoginState {
+        LoginState {
+            step: None,
+            input: String::new(),
+            hint: String::new(),
+            error: None,
+            waiting: false,
+            first_name: String::new(),
+            new_key: String::new(),
+            pending_key: None,
+            rekeying: false,
+            rekey_requested: false,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.step.is_some()
+    }
+
+    // Move on to prompting for `step`, with the input initially set to `prefill`
+    pub fn prompt(&mut self, step: LoginStep, hint: String, prefill: &str) {
+        self.step = Some(step);
+        self.hint = hint;
+        self.input = prefill.to_string();
+        self.waiting = false;
+    }
+
+    // Ask for a new database passphrase once logged in
+    pub fn request_rekey(&mut self) {
+        self.rekey_requested = true;
+    }
+
+    // Whether a new passphrase was requested and not yet asked for
+    pub fn take_rekey_request(&mut self) -> bool {
+        std::mem::take(&mut self.rekey_requested)
+    }
+
+    // Ask for a new passphrase for the already unlocked database
+    pub fn prompt_rekey(&mut self) {
+        self.prompt(
+            LoginStep::NewEncryptionKey,
+            "Leave empty to store local data unencrypted".to_string(),
+            "",
+        );
+        self.rekeying = true;
+    }
+
+    // Login finished (or not needed at all)
+    pub fn finish(&mut self) {
+        self.step = None;
+        self.input.clear();
+        self.new_key.clear();
+        self.error = None;
+        self.waiting = false;
+        self.rekeying = false;
+    }
+
+    // Set a new database passphrase once Tdlib is ready
+    pub fn set_pending_key(&mut self, key: String) {
+        self.pending_key = Some(key);
+    }
+
+    // New database passphrase chosen before login completed, if any
+    pub fn take_pending_key(&mut self) -> Option<String> {
+        self.pending_key.take()
+    }
+
+    // Tdlib rejected the last submission. Show the error and let the user try again
+    pub fn fail(&mut self, msg: &str) {
+        self.error = Some(describe_error(msg).to_string());
+        self.waiting = false;
+        if self.step != Some(LoginStep::PhoneNumber) {
+            self.input.clear();
+        }
+    }
+
+    // Handle a keypress on the login screen, sending any resulting request
+    pub fn handle_input(&mut self, requests: &Requests, input: &Key) {
+        let step = match self.step {
+            Some(s) => s,
+            None => return,
+        };
+        match input {
+            Key::Char('\n') => {
+                let value = self.input.trim().to_string();
+                if value.is_empty() && !step.allows_empty() {
+                    self.error = Some("Please enter a value".to_string());
+                    return;
+                }
+                self.error = None;
+                match step {
+                    LoginStep::PhoneNumber => send_phone_parameters(requests, &value),
+                    LoginStep::Code => send_auth_code(requests, &value),
+                    LoginStep::Password => send_auth_password(requests, &self.input),
+                    LoginStep::FirstName => {
+                        // Nothing to send yet, the last name is needed as well
+                        self.first_name = value;
+                        self.step = Some(LoginStep::LastName);
+                        self.input.clear();
+                        return;
+                    }
+                    LoginStep::LastName => send_registration(requests, &self.first_name, &value),
+                    LoginStep::EncryptionKey => send_check_encryption_key(requests, &self.input),
+                    LoginStep::NewEncryptionKey => {
+                        self.new_key = self.input.clone();
+                        self.step = Some(LoginStep::ConfirmEncryptionKey);
+                        self.input.clear();
+                        return;
+                    }
+                    LoginStep::ConfirmEncryptionKey => {
+                        if self.input != self.new_key {
+                            self.error = Some("Passphrases don't match. Please try again.".into());
+                            self.step = Some(LoginStep::NewEncryptionKey);
+                            self.input.clear();
+                            return;
+                        }
+                        let key = std::mem::take(&mut self.new_key);
+                        if self.rekeying {
+                            send_set_encryption_key(requests, &key);
+                            self.finish();
+                            return;
+                        }
+                        // The database must be opened with its current (empty) key first.
+                        // The new one is set when Tdlib is ready
+                        self.pending_key = Some(key);
+                        send_check_encryption_key(requests, "");
+                    }
+                }
+                self.waiting = true;
+            }
+            Key::Char(c) => self.input.push(*c),
+            Key::Backspace => {
+                self.input.pop();
+            }
+            _ => {}
+        }
+    }
+}
+
+// Show errors in reply to login requests on the login screen, which then prompts again.
+// On success Tdlib moves on to the next authorization state, so there's nothing to do
+fn login_reply(app: &mut App, reply: Reply) {
+    if let Err(e) = reply {
+        let mut login = app.login.lock().unwrap();
+        if login.is_active() {
+            login.fail(&e);
+        } else {
+            // e.g. changing the passphrase once logged in
+            drop(login);
+            app.set_status(describe_error(&e).to_string());
+        }
+    }
+}
+
+// Turn Tdlib's error codes into something a user can act on
+fn describe_error(msg: &str) -> &str {
+    match msg {
+        "PHONE_NUMBER_INVALID" => "Invalid phone number. Please try again.",
+        "PHONE_NUMBER_BANNED" => "This phone number is banned from Telegram.",
+        "PHONE_CODE_INVALID" => "Incorrect code. Please try again.",
+        "PHONE_CODE_EXPIRED" => "Code expired. Please restart to request a new one.",
+        "PASSWORD_HASH_INVALID" => "Incorrect password. Please try again.",
+        "FIRSTNAME_INVALID" => "Invalid first name. Please try again.",
+        "LASTNAME_INVALID" => "Invalid last name. Please try again.",
+        "Wrong database encryption key" => "Wrong passphrase. Please try again.",
+        m => m,
+    }
+}
+
+// Describe how the login code was delivered, from the `code_info` of a WaitCode state
+pub fn describe_code_type(code_type: &str) -> String {
+    match code_type {
+        "authenticationCodeTypeTelegramMessage" => "Code was sent to your Telegram app",
+        "authenticationCodeTypeSms" => "Code was sent by SMS",
+        "authenticationCodeTypeCall" => "You will receive a call with the code",
+        "authenticationCodeTypeFlashCall" => "You will receive a flash call",
+        _ => "",
+    }
+    .to_string()
+}
+
+// Send phone number to Tdlib to connect app with account
+fn send_phone_parameters(requests: &Requests, phone_number: &str) {
+    let phone_parameters = SetAuthenticationPhoneNumber::builder()
+        .phone_number(phone_number)
+        .settings(PhoneNumberAuthenticationSettings::builder().build())
+        .build();
+
+    requests.send_then(&phone_parameters, login_reply);
+}
+
+// Check provided auth code against Tdlib's expectation
+fn send_auth_code(requests: &Requests, code: &str) {
+    let check_auth_code = CheckAuthenticationCode::builder().code(code).build();
+    requests.send_then(&check_auth_code, login_reply);
+}
+
+// Check two-step verification password
+fn send_auth_password(requests: &Requests, password: &str) {
+    let check_password = CheckAuthenticationPassword::builder()
+        .password(password)
+        .build();
+    requests.send_then(&check_password, login_reply);
+}
+
+// Encode a passphrase as the bytes Tdlib expects for a database encryption key
+fn encode_key(key: &str) -> String {
+    base64::encode(key.as_bytes())
+}
+
+// Check database encryption key with Tdlib. An empty key opens an unencrypted database
+pub fn send_check_encryption_key(requests: &Requests, key: &str) {
+    let check_enc_key = CheckDatabaseEncryptionKey::builder()
+        .encryption_key(encode_key(key))
+        .build();
+    requests.send_then(&check_enc_key, login_reply);
+}
+
+// Change the database encryption key. An empty key removes encryption
+pub fn send_set_encryption_key(requests: &Requests, key: &str) {
+    let set_enc_key = SetDatabaseEncryptionKey::builder()
+        .new_encryption_key(encode_key(key))
+        .build();
+    requests.send_then(&set_enc_key, login_reply);
+}
+
+// Send agreement to terms of service
+fn send_registration(requests: &Requests, first_name: &str, last_name: &str) {
+    let reg = RegisterUser::builder()
+        .first_name(first_name)
+        .last_name(last_name)
+        .build();
+
+    requests.send_then(&reg, login_reply);
+}
+
+// Draw the login screen for `account` in the center of the terminal
+pub fn render_login<B: Backend>(f: &mut Frame<B>, login: &LoginState, account: &str) {
+    let size = f.size();
+    let width = LOGIN_WIDTH.min(size.width);
+    let height = LOGIN_HEIGHT.min(size.height);
+    let area = Rect::new(
+        size.x + (size.width - width) / 2,
+        size.y + (size.height - height) / 2,
+        width,
+        height,
+    );
+    let step = match login.step {
+        Some(s) => s,
+        None => return,
+    };
+
+    // Don't display passwords as they're typed
+    let shown_input = if step.is_secret() {
+        "*".repeat(login.input.chars().count())
+    } else {
+        login.input.clone()
+    };
+    let mut lines = vec![
+        Spans::from(Span::styled(
+            step.prompt(),
+            Style::default().add_modifier(Modifier::BOLD),
+        )),
+        Spans::from(Span::styled(
+            login.hint.clone(),
+            Style::default().fg(Color::Gray),
+        )),
+        Spans::from(format!("> {}", shown_input)),
+        Spans::from(""),
+    ];
+    if let Some(e) = &login.error {
+        lines.push(Spans::from(Span::styled(
+            e.clone(),
+            Style::default().fg(Color::Red),
+        )));
+    } else if login.waiting {
+        lines.push(Spans::from(Span::styled(
+            "Checking...",
+            Style::default().fg(Color::Yellow),
+        )));
+    }
+
+    let login_block = Paragraph::new(lines)
+        .block(
+            Block::default()
+                .title(format!("Log in ({})", account))
+                .borders(Borders::ALL),
+        )
+        .wrap(Wrap { trim: false });
+    f.render_widget(Clear, area);
+    f.render_widget(login_block, area);
+
+    // Place cursor at the end of the input line
+    // Placed only if it fits, as the box may have been squashed to nothing
+    let cursor_x = area.x as usize + 3 + shown_input.chars().count();
+    if cursor_x < area.right().saturating_sub(1) as usize {
+        f.set_cursor(cursor_x as u16, area.y + 3);
+    }
+}
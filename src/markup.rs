@@ -0,0 +1,754 @@
+use serde_json::{json, Value};
+
+// How text typed in the input box is turned into a formatted message
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ParseMode {
+    // Telegram-flavoured Markdown: *bold*, _italic_, __underline__, ~strikethrough~,
+    // `code`, ```pre```, [text](url), with \ escaping any of these
+    Markdown,
+
+    // The HTML subset Telegram supports: <b>, <i>, <u>, <s>, <code>, <pre>, <a href>
+    Html,
+
+    // Sent exactly as typed
+    Plain,
+}
+
+impl ParseMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ParseMode::Markdown => "Markdown",
+            ParseMode::Html => "HTML",
+            ParseMode::Plain => "Plain",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ParseMode> {
+        match name.to_lowercase().as_str() {
+            "markdown" => Some(ParseMode::Markdown),
+            "html" => Some(ParseMode::Html),
+            "plain" => Some(ParseMode::Plain),
+            _ => None,
+        }
+    }
+
+    // The mode after this one, when cycling through them
+    pub fn next(&self) -> ParseMode {
+        match self {
+            ParseMode::Markdown => ParseMode::Html,
+            ParseMode::Html => ParseMode::Plain,
+            ParseMode::Plain => ParseMode::Markdown,
+        }
+    }
+}
+
+// Turn typed text into a Tdlib formattedText. Markup that isn't closed is kept as typed
+pub fn parse(input: &str, mode: ParseMode) -> Value {
+    let mut out = Builder::new();
+    let chars: Vec<char> = input.chars().collect();
+    match mode {
+        ParseMode::Markdown => markdown(&chars, &mut out),
+        ParseMode::Html => html(&chars, &mut out),
+        ParseMode::Plain => chars.iter().for_each(|c| out.push(*c)),
+    }
+    out.finish()
+}
+
+// Write a Tdlib formattedText as markup that parses back to it, e.g. to edit a message
+pub fn unparse(formatted: &Value, mode: ParseMode) -> String {
+    let text = formatted["text"].as_str().unwrap_or("");
+    if mode == ParseMode::Plain {
+        return text.to_string();
+    }
+
+    // Markup to open and close each entity, at UTF-16 offsets
+    let mut opens: Vec<(usize, usize, String)> = Vec::new();
+    let mut closes: Vec<(usize, usize, String)> = Vec::new();
+    let mut verbatim: Vec<(usize, usize)> = Vec::new();
+    for entity in formatted["entities"].as_array().into_iter().flatten() {
+        let start = entity["offset"].as_u64().unwrap_or(0) as usize;
+        let end = start + entity["length"].as_u64().unwrap_or(0) as usize;
+        let markup = match mode {
+            ParseMode::Markdown => markdown_markup(&entity["type"]),
+            _ => html_markup(&entity["type"]),
+        };
+        if let Some((open, close)) = markup {
+            if is_verbatim(&entity["type"]) {
+                verbatim.push((start, end));
+            }
+            opens.push((start, end, open));
+            closes.push((end, start, close));
+        }
+    }
+    // Outer entities open first and close last
+    opens.sort_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)));
+    closes.sort_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)));
+
+    let mut out = String::new();
+    let (mut opens, mut closes) = (opens.into_iter().peekable(), closes.into_iter().peekable());
+    let mut offset = 0;
+    for c in text.chars().chain(std::iter::once('\0')) {
+        while let Some((_, _, close)) = closes.next_if(|(end, _, _)| *end <= offset) {
+            out.push_str(&close);
+        }
+        if c == '\0' {
+            break;
+        }
+        while let Some((_, _, open)) = opens.next_if(|(start, _, _)| *start <= offset) {
+            out.push_str(&open);
+        }
+        let in_code = verbatim.iter().any(|(s, e)| (*s..*e).contains(&offset));
+        match mode {
+            ParseMode::Markdown if in_code && (c == '`' || c == '\\') => out.push('\\'),
+            ParseMode::Markdown if !in_code && MARKDOWN_SPECIAL.contains(&c) => out.push('\\'),
+            _ => {}
+        }
+        match (mode, c) {
+            (ParseMode::Html, '<') => out.push_str("&lt;"),
+            (ParseMode::Html, '>') => out.push_str("&gt;"),
+            (ParseMode::Html, '&') => out.push_str("&amp;"),
+            _ => out.push(c),
+        }
+        offset += c.len_utf16();
+    }
+    out
+}
+
+// Characters that have to be escaped to be sent as they are in Markdown
+const MARKDOWN_SPECIAL: &[char] = &['\\', '*', '_', '~', '`', '[', ']'];
+
+// Text being built up along with the entities over it. Tdlib measures entities in
+// UTF-16 code units
+struct Builder {
+    text: String,
+    len: usize,
+    entities: Vec<Value>,
+}
+
+impl Builder {
+    fn new() -> Builder {
+        Builder {
+            text: String::new(),
+            len: 0,
+            entities: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, c: char) {
+        self.text.push(c);
+        self.len += c.len_utf16();
+    }
+
+    // Add an entity of `entity_type` from `start` to the end of the text so far
+    fn entity(&mut self, start: usize, entity_type: Value) {
+        if self.len > start {
+            self.entities.push(json!({
+                "@type": "textEntity",
+                "offset": start,
+                "length": self.len - start,
+                "type": entity_type,
+            }));
+        }
+    }
+
+    fn finish(mut self) -> Value {
+        self.entities
+            .sort_by_key(|e| e["offset"].as_u64().unwrap_or(0));
+        json!({
+            "@type": "formattedText",
+            "text": self.text,
+            "entities": self.entities,
+        })
+    }
+}
+
+fn entity_type(td_type: &str) -> Value {
+    json!({ "@type": td_type })
+}
+
+// Entity for a link to `url`, or a mention when it links to a user
+fn link_type(url: &str) -> Value {
+    match url.strip_prefix("tg://user?id=").map(str::parse::<i64>) {
+        Some(Ok(user_id)) => json!({"@type": "textEntityTypeMentionName", "user_id": user_id}),
+        _ => json!({"@type": "textEntityTypeTextUrl", "url": url}),
+    }
+}
+
+// Markdown delimiters and the entities they make, longest first so `__` isn't taken as `_`
+const MARKDOWN_DELIMITERS: &[(&str, &str)] = &[
+    ("__", "textEntityTypeUnderline"),
+    ("*", "textEntityTypeBold"),
+    ("_", "textEntityTypeItalic"),
+    ("~", "textEntityTypeStrikethrough"),
+];
+
+fn markdown(chars: &[char], out: &mut Builder) {
+    let mut i = 0;
+    while i < chars.len() {
+        let rest = &chars[i..];
+
+        if rest[0] == '\\' && rest.len() > 1 {
+            out.push(rest[1]);
+            i += 2;
+            continue;
+        }
+
+        // Pre block, optionally naming its language on the opening line
+        if starts_with(rest, "```") {
+            if let Some(end) = find_closing(rest, 3, "```", true) {
+                let mut body = &rest[3..end];
+                let mut language = String::new();
+                if let Some(newline) = body.iter().position(|c| *c == '\n') {
+                    let first: String = body[..newline].iter().collect();
+                    if !first.is_empty() && !first.contains(char::is_whitespace) {
+                        language = first;
+                        body = &body[newline + 1..];
+                    }
+                }
+                let start = out.len;
+                push_verbatim(body, out);
+                if language.is_empty() {
+                    out.entity(start, entity_type("textEntityTypePre"));
+                } else {
+                    out.entity(
+                        start,
+                        json!({"@type": "textEntityTypePreCode", "language": language}),
+                    );
+                }
+                i += end + 3;
+                continue;
+            }
+            // Left unclosed, rather than taken as code between the second and third `
+            "```".chars().for_each(|c| out.push(c));
+            i += 3;
+            continue;
+        }
+
+        if rest[0] == '`' {
+            if let Some(end) = find_closing(rest, 1, "`", true) {
+                let start = out.len;
+                push_verbatim(&rest[1..end], out);
+                out.entity(start, entity_type("textEntityTypeCode"));
+                i += end + 1;
+                continue;
+            }
+        }
+
+        // [text](url)
+        if rest[0] == '[' {
+            if let Some(link) = find_link(rest) {
+                let (text_end, url_end) = link;
+                let url: String = rest[text_end + 2..url_end].iter().collect();
+                let start = out.len;
+                markdown(&rest[1..text_end], out);
+                out.entity(start, link_type(url.trim()));
+                i += url_end + 1;
+                continue;
+            }
+        }
+
+        let delimiter = MARKDOWN_DELIMITERS
+            .iter()
+            .find(|(d, _)| starts_with(rest, d));
+        if let Some((d, td_type)) = delimiter {
+            let len = d.chars().count();
+            // Underscores within words, as in snake_case, are left alone
+            let in_word = |c: Option<&char>| c.is_some_and(|c| c.is_alphanumeric());
+            let intraword = |end: usize| {
+                d.starts_with('_')
+                    && ((i > 0 && in_word(chars.get(i - 1))) || in_word(rest.get(end + len)))
+            };
+            let closing = find_closing(rest, len, d, false).filter(|end| !intraword(*end));
+            if let Some(end) = closing {
+                let start = out.len;
+                markdown(&rest[len..end], out);
+                out.entity(start, entity_type(td_type));
+                i += end + len;
+                continue;
+            }
+        }
+
+        out.push(rest[0]);
+        i += 1;
+    }
+}
+
+// Contents of code, where only ` and \ can be escaped
+fn push_verbatim(chars: &[char], out: &mut Builder) {
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i] == '\\' && i + 1 < chars.len() && (chars[i + 1] == '`' || chars[i + 1] == '\\')
+        {
+            i += 1;
+        }
+        out.push(chars[i]);
+        i += 1;
+    }
+}
+
+fn starts_with(chars: &[char], s: &str) -> bool {
+    s.chars().enumerate().all(|(i, c)| chars.get(i) == Some(&c))
+}
+
+// Index of the delimiter closing one opened at the start of `chars`, searching from
+// `from`. Escaped characters and code are skipped over, unless looking for the end of code
+fn find_closing(chars: &[char], from: usize, delimiter: &str, in_code: bool) -> Option<usize> {
+    let mut i = from;
+    while i < chars.len() {
+        if chars[i] == '\\' {
+            i += 2;
+            continue;
+        }
+        if starts_with(&chars[i..], delimiter) {
+            // `_` can't close on the first half of `__`, nor be empty
+            let doubled = delimiter == "_" && chars.get(i + 1) == Some(&'_');
+            if !doubled && i > from {
+                return Some(i);
+            }
+            if doubled {
+                i += 2;
+                continue;
+            }
+        }
+        if !in_code && chars[i] == '`' {
+            let code_end = if starts_with(&chars[i..], "```") {
+                find_closing(&chars[i..], 3, "```", true).map(|e| e + 3)
+            } else {
+                find_closing(&chars[i..], 1, "`", true).map(|e| e + 1)
+            };
+            if let Some(e) = code_end {
+                i += e;
+                continue;
+            }
+        }
+        i += 1;
+    }
+    None
+}
+
+// For `[text](url)` at the start of `chars`, the indices of `]` and `)`
+fn find_link(chars: &[char]) -> Option<(usize, usize)> {
+    let text_end = find_closing(chars, 1, "]", false)?;
+    if chars.get(text_end + 1) != Some(&'(') {
+        return None;
+    }
+    let url_end = chars[text_end + 2..].iter().position(|c| *c == ')')? + text_end + 2;
+    Some((text_end, url_end))
+}
+
+// An HTML tag that's been opened and not yet closed
+struct OpenTag {
+    name: String,
+    start: usize,
+    entity_type: Option<Value>,
+}
+
+fn html(chars: &[char], out: &mut Builder) {
+    let mut open: Vec<OpenTag> = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '&' {
+            if let Some((decoded, len)) = html_entity(&chars[i..]) {
+                out.push(decoded);
+                i += len;
+                continue;
+            }
+        }
+        if c == '<' {
+            if let Some(end) = chars[i..].iter().position(|c| *c == '>') {
+                let tag: String = chars[i + 1..i + end].iter().collect();
+                if html_tag(&tag, &mut open, out) {
+                    i += end + 1;
+                    continue;
+                }
+            }
+        }
+        out.push(c);
+        i += 1;
+    }
+
+    // Close anything left open at the end of the text
+    while let Some(tag) = open.pop() {
+        if let Some(t) = tag.entity_type {
+            out.entity(tag.start, t);
+        }
+    }
+}
+
+// Handle the tag between `<` and `>`, returning false if it isn't one Telegram supports
+fn html_tag(tag: &str, open: &mut Vec<OpenTag>, out: &mut Builder) -> bool {
+    let tag = tag.trim();
+    if let Some(name) = tag.strip_prefix('/') {
+        let name = name.trim().to_lowercase();
+        let position = match open.iter().rposition(|t| t.name == name) {
+            Some(p) => p,
+            None => return false,
+        };
+        let tag = open.remove(position);
+        if let Some(t) = tag.entity_type {
+            out.entity(tag.start, t);
+        }
+        return true;
+    }
+
+    let (name, attrs) = match tag.find(char::is_whitespace) {
+        Some(split) => (&tag[..split], &tag[split..]),
+        None => (tag, ""),
+    };
+    let name = name.to_lowercase();
+    let entity_type = match name.as_str() {
+        "b" | "strong" => entity_type("textEntityTypeBold"),
+        "i" | "em" => entity_type("textEntityTypeItalic"),
+        "u" | "ins" => entity_type("textEntityTypeUnderline"),
+        "s" | "strike" | "del" => entity_type("textEntityTypeStrikethrough"),
+        "pre" => entity_type("textEntityTypePre"),
+        "a" => match html_attr(attrs, "href") {
+            Some(href) => link_type(&href),
+            None => return false,
+        },
+        "code" => {
+            // <pre><code class="language-x"> makes the pre block x code
+            let language = html_attr(attrs, "class")
+                .and_then(|c| c.strip_prefix("language-").map(str::to_string));
+            let in_pre = open.last().filter(|t| t.name == "pre");
+            if let (Some(language), Some(pre)) = (language, in_pre) {
+                let pre_start = pre.start;
+                open.last_mut().unwrap().entity_type =
+                    Some(json!({"@type": "textEntityTypePreCode", "language": language}));
+                open.push(OpenTag {
+                    name,
+                    start: pre_start,
+                    entity_type: None,
+                });
+                return true;
+            }
+            if in_pre.is_some() {
+                // Already code
+                open.push(OpenTag {
+                    name,
+                    start: out.len,
+                    entity_type: None,
+                });
+                return true;
+            }
+            entity_type("textEntityTypeCode")
+        }
+        _ => return false,
+    };
+    open.push(OpenTag {
+        name,
+        start: out.len,
+        entity_type: Some(entity_type),
+    });
+    true
+}
+
+// Value of attribute `name` in e.g. ` href="https://..."`, matched in any case
+fn html_attr(attrs: &str, name: &str) -> Option<String> {
+    let value = attrs.char_indices().find_map(|(at, _)| {
+        let rest = &attrs[at..];
+        let named = rest
+            .get(..name.len())
+            .is_some_and(|n| n.eq_ignore_ascii_case(name));
+        // Not the end of another attribute's name, as in data-href
+        let boundary = at == 0 || attrs[..at].ends_with(char::is_whitespace);
+        if named && boundary {
+            rest[name.len()..].trim_start().strip_prefix('=')
+        } else {
+            None
+        }
+    })?;
+    let value = value.trim_start();
+    let quote = value.chars().next()?;
+    if quote == '"' || quote == '\'' {
+        let end = value[1..].find(quote)?;
+        Some(decode_html(&value[1..end + 1]))
+    } else {
+        let end = value.find(char::is_whitespace).unwrap_or(value.len());
+        Some(decode_html(&value[..end]))
+    }
+}
+
+fn decode_html(s: &str) -> String {
+    let chars: Vec<char> = s.chars().collect();
+    let mut out = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        match html_entity(&chars[i..]) {
+            Some((c, len)) => {
+                out.push(c);
+                i += len;
+            }
+            None => {
+                out.push(chars[i]);
+                i += 1;
+            }
+        }
+    }
+    out
+}
+
+// The character named by an HTML entity like `&amp;` or `&#33;` at the start of
+// `chars`, and the entity's length
+fn html_entity(chars: &[char]) -> Option<(char, usize)> {
+    if chars.first() != Some(&'&') {
+        return None;
+    }
+    let end = chars.iter().take(10).position(|c| *c == ';')?;
+    let name: String = chars[1..end].iter().collect();
+    let c = match name.as_str() {
+        "lt" => '<',
+        "gt" => '>',
+        "amp" => '&',
+        "quot" => '"',
+        _ => {
+            let code = match name.strip_prefix("#x").or_else(|| name.strip_prefix("#X")) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => name.strip_prefix('#')?.parse().ok()?,
+            };
+            std::char::from_u32(code)?
+        }
+    };
+    Some((c, end + 1))
+}
+
+// Code and pre blocks, whose contents aren't parsed
+fn is_verbatim(entity_type: &Value) -> bool {
+    matches!(
+        entity_type["@type"].as_str(),
+        Some("textEntityTypeCode") | Some("textEntityTypePre") | Some("textEntityTypePreCode")
+    )
+}
+
+fn markdown_markup(entity_type: &Value) -> Option<(String, String)> {
+    let pair = |s: &str| Some((s.to_string(), s.to_string()));
+    match entity_type["@type"].as_str()? {
+        "textEntityTypeBold" => pair("*"),
+        "textEntityTypeItalic" => pair("_"),
+        "textEntityTypeUnderline" => pair("__"),
+        "textEntityTypeStrikethrough" => pair("~"),
+        "textEntityTypeCode" => pair("`"),
+        "textEntityTypePre" => pair("```"),
+        "textEntityTypePreCode" => Some((
+            format!("```{}\n", entity_type["language"].as_str().unwrap_or("")),
+            "```".to_string(),
+        )),
+        "textEntityTypeTextUrl" => Some((
+            "[".to_string(),
+            format!("]({})", entity_type["url"].as_str().unwrap_or("")),
+        )),
+        "textEntityTypeMentionName" => Some((
+            "[".to_string(),
+            format!(
+                "](tg://user?id={})",
+                entity_type["user_id"].as_i64().unwrap_or(0)
+            ),
+        )),
+        _ => None,
+    }
+}
+
+fn html_markup(entity_type: &Value) -> Option<(String, String)> {
+    let tag = |t: &str| Some((format!("<{}>", t), format!("</{}>", t)));
+    let link = |href: String| Some((format!("<a href=\"{}\">", href), "</a>".to_string()));
+    match entity_type["@type"].as_str()? {
+        "textEntityTypeBold" => tag("b"),
+        "textEntityTypeItalic" => tag("i"),
+        "textEntityTypeUnderline" => tag("u"),
+        "textEntityTypeStrikethrough" => tag("s"),
+        "textEntityTypeCode" => tag("code"),
+        "textEntityTypePre" => tag("pre"),
+        "textEntityTypePreCode" => Some((
+            format!(
+                "<pre><code class=\"language-{}\">",
+                entity_type["language"].as_str().unwrap_or("")
+            ),
+            "</code></pre>".to_string(),
+        )),
+        "textEntityTypeTextUrl" => link(
+            entity_type["url"]
+                .as_str()
+                .unwrap_or("")
+                .replace('&', "&amp;")
+                .replace('"', "&quot;"),
+        ),
+        "textEntityTypeMentionName" => link(format!(
+            "tg://user?id={}",
+            entity_type["user_id"].as_i64().unwrap_or(0)
+        )),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The text of `formatted`, and the offset, length and type of each of its entities
+    fn split(formatted: &Value) -> (&str, Vec<(u64, u64, &str)>) {
+        let entities = formatted["entities"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| {
+                let td_type = e["type"]["@type"].as_str().unwrap();
+                (
+                    e["offset"].as_u64().unwrap(),
+                    e["length"].as_u64().unwrap(),
+                    td_type,
+                )
+            })
+            .collect();
+        (formatted["text"].as_str().unwrap(), entities)
+    }
+
+    #[test]
+    fn markdown_nesting() {
+        let formatted = parse("*bold _both_* __under ~strike~__", ParseMode::Markdown);
+        let (text, entities) = split(&formatted);
+        assert_eq!(text, "bold both under strike");
+        assert_eq!(
+            entities,
+            vec![
+                (0, 9, "textEntityTypeBold"),
+                (5, 4, "textEntityTypeItalic"),
+                (10, 12, "textEntityTypeUnderline"),
+                (16, 6, "textEntityTypeStrikethrough"),
+            ]
+        );
+    }
+
+    #[test]
+    fn markdown_unclosed() {
+        for input in &["*bold", "_italic", "`code", "```pre", "[text](url", "a * b"] {
+            let formatted = parse(input, ParseMode::Markdown);
+            let (text, entities) = split(&formatted);
+            assert_eq!(&text, input);
+            assert!(entities.is_empty(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn markdown_escapes() {
+        let formatted = parse(r"\*not bold\* snake_case_name", ParseMode::Markdown);
+        let (text, entities) = split(&formatted);
+        assert_eq!(text, "*not bold* snake_case_name");
+        assert!(entities.is_empty());
+
+        // Only ` and \ are escaped in code, and markers in it are kept
+        let formatted = parse(r"`a\`*b*\n`", ParseMode::Markdown);
+        let (text, entities) = split(&formatted);
+        assert_eq!(text, r"a`*b*\n");
+        assert_eq!(entities, vec![(0, 7, "textEntityTypeCode")]);
+    }
+
+    #[test]
+    fn markdown_code_and_links() {
+        let formatted = parse(
+            "```rust\nfn f() {}``` [me](tg://user?id=5)",
+            ParseMode::Markdown,
+        );
+        let (text, entities) = split(&formatted);
+        assert_eq!(text, "fn f() {} me");
+        assert_eq!(
+            entities,
+            vec![
+                (0, 9, "textEntityTypePreCode"),
+                (10, 2, "textEntityTypeMentionName"),
+            ]
+        );
+        assert_eq!(formatted["entities"][0]["type"]["language"], "rust");
+        assert_eq!(formatted["entities"][1]["type"]["user_id"], 5);
+    }
+
+    #[test]
+    fn offsets_are_utf16() {
+        // 😀 takes two UTF-16 code units, é one
+        let formatted = parse("😀é *b😀* <b>", ParseMode::Markdown);
+        let (text, entities) = split(&formatted);
+        assert_eq!(text, "😀é b😀 <b>");
+        assert_eq!(entities, vec![(4, 3, "textEntityTypeBold")]);
+
+        let formatted = parse("😀<i>😀</i>", ParseMode::Html);
+        let (_, entities) = split(&formatted);
+        assert_eq!(entities, vec![(2, 2, "textEntityTypeItalic")]);
+    }
+
+    #[test]
+    fn html_nesting_and_unclosed() {
+        let formatted = parse("<b>bold <I>both</I></b> <s>struck", ParseMode::Html);
+        let (text, entities) = split(&formatted);
+        assert_eq!(text, "bold both struck");
+        assert_eq!(
+            entities,
+            vec![
+                (0, 9, "textEntityTypeBold"),
+                (5, 4, "textEntityTypeItalic"),
+                (10, 6, "textEntityTypeStrikethrough"),
+            ]
+        );
+
+        // Tags Telegram doesn't support, and closing ones never opened, are kept
+        let formatted = parse("a <p>b</p> </b> 1<2", ParseMode::Html);
+        let (text, entities) = split(&formatted);
+        assert_eq!(text, "a <p>b</p> </b> 1<2");
+        assert!(entities.is_empty());
+    }
+
+    #[test]
+    fn html_entities() {
+        let formatted = parse(
+            "&lt;b&gt; &amp; &quot; &#33; &#x41; &#X1F600;",
+            ParseMode::Html,
+        );
+        let (text, _) = split(&formatted);
+        assert_eq!(text, "<b> & \" ! A 😀");
+
+        // Anything not an entity is kept as typed
+        let formatted = parse("a & b; &nope; &#xZZ; &;", ParseMode::Html);
+        let (text, _) = split(&formatted);
+        assert_eq!(text, "a & b; &nope; &#xZZ; &;");
+
+        assert_eq!(decode_html(";x&;&amp;;"), ";x&;&;");
+    }
+
+    #[test]
+    fn html_attributes() {
+        let url = |input: &str| {
+            let formatted = parse(input, ParseMode::Html);
+            formatted["entities"][0]["type"]["url"]
+                .as_str()
+                .map(str::to_string)
+        };
+        assert_eq!(
+            url("<a href=\"https://a.b/?x=1&amp;y=2\">t</a>").unwrap(),
+            "https://a.b/?x=1&y=2"
+        );
+        assert_eq!(url("<a HREF = 'https://a.b'>t</a>").unwrap(), "https://a.b");
+        assert_eq!(
+            url("<a href=https://a.b target=x>t</a>").unwrap(),
+            "https://a.b"
+        );
+        assert_eq!(url("<a href=\"x;y\">t</a>").unwrap(), "x;y");
+        assert_eq!(url("<a data-href=\"x\" href=\"y\">t</a>").unwrap(), "y");
+        // Letters changing length when lowercased don't throw the value off
+        assert_eq!(
+            url("<a title=\"İİ\" href=\"https://a.b\">t</a>").unwrap(),
+            "https://a.b"
+        );
+
+        // Without an href it's no link
+        let formatted = parse("<a data-href=\"x\">t</a>", ParseMode::Html);
+        let (text, entities) = split(&formatted);
+        assert_eq!(text, "<a data-href=\"x\">t</a>");
+        assert!(entities.is_empty());
+
+        let formatted = parse(
+            "<pre><code class=\"language-rust\">x</code></pre>",
+            ParseMode::Html,
+        );
+        assert_eq!(split(&formatted).1, vec![(0, 1, "textEntityTypePreCode")]);
+        assert_eq!(formatted["entities"][0]["type"]["language"], "rust");
+    }
+}
@@ -0,0 +1,196 @@
+use crate::command::report_failure;
+use crate::tabs::View;
+use crate::{App, Requests, TChat};
+use serde_json::{json, Value};
+
+// How many chats are asked for at once
+const PAGE_SIZE: i64 = 100;
+
+// Where chats are in Telegram's chat lists, and moving them: pinning chats at the top of
+// a list, and archiving them
+
+// A list of chats: the main one, archived chats, or a chat folder by its ID
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChatListId {
+    Main,
+    Archive,
+    Folder(i64),
+}
+
+impl ChatListId {
+    pub fn from_json(list: &Value) -> Option<ChatListId> {
+        match list["@type"].as_str()? {
+            "chatListMain" => Some(ChatListId::Main),
+            "chatListArchive" => Some(ChatListId::Archive),
+            "chatListFilter" => Some(ChatListId::Folder(list["chat_filter_id"].as_i64()?)),
+            _ => None,
+        }
+    }
+
+    pub fn to_json(self) -> Value {
+        match self {
+            ChatListId::Main => json!({"@type": "chatListMain"}),
+            ChatListId::Archive => json!({"@type": "chatListArchive"}),
+            ChatListId::Folder(id) => json!({"@type": "chatListFilter", "chat_filter_id": id}),
+        }
+    }
+}
+
+// Where a chat is in a list. Pinned chats come first, then higher orders
+#[derive(Clone, Copy)]
+pub struct Position {
+    pub order: i64,
+    pub is_pinned: bool,
+}
+
+// How far through a list chats have been got
+pub struct Paging {
+    // Order and ID of the last chat got, which the next page starts after
+    offset: (i64, i64),
+    pub loading: bool,
+
+    // Whether every chat in the list has been got
+    pub done: bool,
+}
+
+impl Default for Paging {
+    fn default() -> Paging {
+        Paging {
+            offset: (i64::MAX, 0),
+            loading: false,
+            done: false,
+        }
+    }
+}
+
+// Apply a chatPosition to a chat. An order of 0 means it's left the list
+pub fn set_position(chat: &mut TChat, position: &Value) {
+    let list = match ChatListId::from_json(&position["list"]) {
+        Some(l) => l,
+        None => return,
+    };
+
+    // Orders are sent as strings, being too big for JSON numbers
+    let order = match &position["order"] {
+        Value::String(s) => s.parse().unwrap_or(0),
+        o => o.as_i64().unwrap_or(0),
+    };
+    if order == 0 {
+        chat.positions.remove(&list);
+        return;
+    }
+    let is_pinned = position["is_pinned"].as_bool().unwrap_or(false);
+    chat.positions.insert(list, Position { order, is_pinned });
+}
+
+// Get the next page of chats in a list. Tdlib sends the chats themselves as updates
+// before replying with their IDs, in order
+pub fn load_page(requests: &Requests, list: ChatListId, paging: &mut Paging) {
+    if paging.loading || paging.done {
+        return;
+    }
+    paging.loading = true;
+    let (offset_order, offset_chat_id) = paging.offset;
+    requests.send_json(
+        json!({
+            "@type": "getChats",
+            "chat_list": list.to_json(),
+            "offset_order": offset_order.to_string(),
+            "offset_chat_id": offset_chat_id,
+            "limit": PAGE_SIZE,
+        }),
+        move |app, reply| {
+            let ids: Vec<i64> = match &reply {
+                Ok(chats) => chats["chat_ids"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|id| id.as_i64())
+                    .collect(),
+                Err(_) => Vec::new(),
+            };
+
+            // An empty page means there are no more, while one whose last chat isn't
+            // known to be in the list can't be carried on from
+            let last = ids.last().and_then(|&id| {
+                app.chat_list
+                    .with_chat(id, |c| c.positions.get(&list).map(|p| (p.order, id)))
+                    .flatten()
+            });
+            let mut all_paging = app.chat_list.paging.lock().unwrap();
+            let paging = all_paging.entry(list).or_default();
+            paging.loading = false;
+            match last {
+                Some(offset) => paging.offset = offset,
+                None => paging.done = reply.is_ok(),
+            }
+            drop(all_paging);
+            if reply.is_err() {
+                return report_failure(app, reply, "get chats");
+            }
+
+            // Carry on while the chats got don't reach far enough past those on screen
+            app.chat_list.load_more(&app.requests);
+        },
+    );
+}
+
+// Show archived chats, or go back to the tab shown before
+pub fn toggle_archive(app: &mut App) {
+    if app.chat_list.list() == ChatListId::Archive {
+        let view = app.chat_list.tabs.lock().unwrap().view();
+        return app.chat_list.show(view);
+    }
+
+    app.chat_list.show(View::all(ChatListId::Archive));
+    app.chat_list.load_more(&app.requests);
+}
+
+// Pin the selected chat at the top of the list shown, or unpin it
+pub fn toggle_pinned(app: &mut App) {
+    let list = app.chat_list.list();
+    // Chats opened from outside the list can't be pinned in it
+    let chat = app.chat_list.with_selected(|c| {
+        let position = c.positions.get(&list)?;
+        Some((c.chat.id(), position.is_pinned))
+    });
+    let (chat_id, pinned) = match chat.flatten() {
+        Some(c) => c,
+        None => return,
+    };
+    let what = if pinned { "unpin chat" } else { "pin chat" };
+    app.requests.send_json(
+        json!({
+            "@type": "toggleChatIsPinned",
+            "chat_list": list.to_json(),
+            "chat_id": chat_id,
+            "is_pinned": !pinned,
+        }),
+        move |app, reply| report_failure(app, reply, what),
+    );
+}
+
+// Archive the selected chat, or move it back to the main list if it's archived
+pub fn toggle_archived(app: &mut App) {
+    let chat = app.chat_list.with_selected(|c| {
+        let archived = c.positions.contains_key(&ChatListId::Archive);
+        (c.chat.id(), archived)
+    });
+    let (chat_id, archived) = match chat {
+        Some(c) => c,
+        None => return,
+    };
+    let (list, what) = if archived {
+        (ChatListId::Main, "unarchive chat")
+    } else {
+        (ChatListId::Archive, "archive chat")
+    };
+    app.requests.send_json(
+        json!({
+            "@type": "addChatToList",
+            "chat_id": chat_id,
+            "chat_list": list.to_json(),
+        }),
+        move |app, reply| report_failure(app, reply, what),
+    );
+}
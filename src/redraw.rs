@@ -0,0 +1,41 @@
+use crossbeam::channel::{self, Receiver, Sender};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+// Tracks whether the screen is out of date, waking the render thread when it becomes so.
+// However many changes are made before the next frame, they're drawn together
+#[derive(Clone)]
+pub struct Redraw {
+    dirty: Arc<AtomicBool>,
+
+    // Holds at most one wakeup, so the render thread never falls behind
+    wake: Sender<()>,
+    woken: Receiver<()>,
+}
+
+impl Redraw {
+    pub fn new() -> Redraw {
+        let (wake, woken) = channel::bounded(1);
+        Redraw {
+            dirty: Arc::new(AtomicBool::new(false)),
+            wake,
+            woken,
+        }
+    }
+
+    // Note that something shown on screen has changed
+    pub fn request(&self) {
+        self.dirty.store(true, Ordering::SeqCst);
+        let _ = self.wake.try_send(());
+    }
+
+    // Receives once a redraw has been requested
+    pub fn woken(&self) -> &Receiver<()> {
+        &self.woken
+    }
+
+    // Whether a redraw has been requested since the last call
+    pub fn take(&self) -> bool {
+        self.dirty.swap(false, Ordering::SeqCst)
+    }
+}
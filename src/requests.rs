@@ -0,0 +1,164 @@
+use crate::App;
+use crossbeam::channel::{self, Receiver, Sender};
+use rtdlib::types::RObject;
+use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// How long to wait for Tdlib to reply to a request before giving up on it
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Tdlib's reply to a request: the response object, or the message of the error it returned
+pub type Reply = Result<Value, String>;
+
+// Called on the Tdlib thread with the reply to a request
+pub type Callback = Box<dyn FnOnce(&mut App, Reply) + Send>;
+
+// A request that's been sent and is waiting for its reply
+struct Pending {
+    // Type of request, for reporting errors
+    td_type: String,
+    sent_at: Instant,
+    callback: Callback,
+}
+
+// Requests outgoing to Tdlib, and those waiting on a reply. Every request is tagged
+// with a unique "@extra", which Tdlib copies into its reply
+#[derive(Clone)]
+pub struct Requests {
+    // Tagged requests, in order, not yet handed to Tdlib
+    outgoing: Arc<Mutex<VecDeque<String>>>,
+
+    // Requests waiting on a reply, by their "@extra"
+    pending: Arc<Mutex<HashMap<u64, Pending>>>,
+    next_id: Arc<AtomicU64>,
+
+    // Wakes the Tdlib thread as soon as there's something to send. Holds at most one
+    // wakeup, as the Tdlib thread sends everything outgoing each time it's woken
+    wake: Sender<()>,
+    woken: Receiver<()>,
+}
+
+impl Requests {
+    pub fn new() -> Requests {
+        let (wake, woken) = channel::bounded(1);
+        Requests {
+            outgoing: Arc::new(Mutex::new(VecDeque::new())),
+            pending: Arc::new(Mutex::new(HashMap::new())),
+            next_id: Arc::new(AtomicU64::new(1)),
+            wake,
+            woken,
+        }
+    }
+
+    // Send `req` without any particular handling of the reply. If it fails,
+    // the error is shown in the status line
+    pub fn send<R: RObject>(&self, req: &R) {
+        let td_type = req.td_name();
+        self.send_json(to_value(req), move |app, reply| {
+            if let Err(e) = reply {
+                app.set_status(format!("{} failed: {}", td_type, e));
+            }
+        });
+    }
+
+    // Send `req`, calling `callback` with the reply once it arrives
+    pub fn send_then<R, F>(&self, req: &R, callback: F)
+    where
+        R: RObject,
+        F: FnOnce(&mut App, Reply) + Send + 'static,
+    {
+        self.send_json(to_value(req), callback);
+    }
+
+    // Send a request built as JSON, for requests rtdlib doesn't know about
+    pub fn send_json<F>(&self, mut req: Value, callback: F)
+    where
+        F: FnOnce(&mut App, Reply) + Send + 'static,
+    {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        req["@extra"] = json!(id);
+        let td_type = req["@type"].as_str().unwrap_or("request").to_string();
+        self.pending.lock().unwrap().insert(
+            id,
+            Pending {
+                td_type,
+                sent_at: Instant::now(),
+                callback: Box::new(callback),
+            },
+        );
+        self.outgoing.lock().unwrap().push_back(req.to_string());
+        let _ = self.wake.try_send(());
+    }
+
+    // Receives once there are requests waiting to be sent
+    pub fn woken(&self) -> &Receiver<()> {
+        &self.woken
+    }
+
+    // Take all requests waiting to be sent, in order
+    pub fn take_outgoing(&self) -> Vec<String> {
+        self.outgoing.lock().unwrap().drain(..).collect()
+    }
+
+    // Take the callback of the request `obj` is a reply to, if it's still waiting
+    fn take_callback(&self, obj: &Value) -> Option<Callback> {
+        let id = obj["@extra"].as_u64()?;
+        self.pending.lock().unwrap().remove(&id).map(|p| p.callback)
+    }
+
+    // Take the callbacks of requests that have waited too long for a reply,
+    // along with the type of each request
+    fn take_expired(&self) -> Vec<(String, Callback)> {
+        let mut pending = self.pending.lock().unwrap();
+        let expired: Vec<u64> = pending
+            .iter()
+            .filter(|(_, p)| p.sent_at.elapsed() > REQUEST_TIMEOUT)
+            .map(|(id, _)| *id)
+            .collect();
+        expired
+            .into_iter()
+            .filter_map(|id| pending.remove(&id))
+            .map(|p| (p.td_type, p.callback))
+            .collect()
+    }
+}
+
+// Whether `obj` is a reply to a request, rather than an update
+pub fn is_reply(obj: &Value) -> bool {
+    !obj["@extra"].is_null()
+}
+
+// Hand a reply to the callback of the request it answers
+pub fn handle_reply(app: &mut App, obj: Value) {
+    let callback = match app.requests.take_callback(&obj) {
+        Some(c) => c,
+        None => {
+            // Most likely the request already timed out
+            eprintln!("Reply to unknown request: {}", obj);
+            return;
+        }
+    };
+    let reply = match obj["@type"].as_str() {
+        Some("error") => Err(obj["message"]
+            .as_str()
+            .unwrap_or("Unknown error")
+            .to_string()),
+        _ => Ok(obj),
+    };
+    callback(app, reply);
+}
+
+// Fail any requests that have gone unanswered for too long
+pub fn expire(app: &mut App) {
+    for (td_type, callback) in app.requests.take_expired() {
+        eprintln!("{} timed out", td_type);
+        callback(app, Err(format!("{} timed out", td_type)));
+    }
+}
+
+fn to_value<R: RObject>(req: &R) -> Value {
+    serde_json::from_str(&req.to_json().unwrap()).unwrap()
+}
@@ -0,0 +1,430 @@
+use crate::content;
+use crate::requests::Reply;
+use crate::{parse_msg, App, TBlocks};
+use chrono::prelude::*;
+use serde_json::json;
+use termion::event::Key;
+use tui::{
+    backend::Backend,
+    layout::Rect,
+    style::{Color, Modifier, Style},
+    text::{Span, Spans},
+    widgets::{Block, Borders, Clear, List, ListItem, ListState, Paragraph},
+    Frame,
+};
+
+// Most results asked for at once
+const SEARCH_LIMIT: i64 = 50;
+
+// Messages retrieved either side of one jumped to
+const JUMP_AROUND: i64 = 25;
+
+// A search through the open chat's messages, with `n` and `N` going from hit to hit
+pub struct ChatSearch {
+    chat_id: i64,
+    query: String,
+
+    // IDs of messages found so far, newest first
+    hits: Vec<i64>,
+
+    // Index in `hits` of the message last jumped to
+    current: Option<usize>,
+
+    // Whether Tdlib may have older hits than the last in `hits`
+    more: bool,
+    total: i64,
+}
+
+impl ChatSearch {
+    // What's highlighted in the chat with `chat_id`, if it's being searched
+    pub fn query_for(&self, chat_id: i64) -> Option<&str> {
+        if self.chat_id == chat_id {
+            Some(&self.query)
+        } else {
+            None
+        }
+    }
+}
+
+// Messages found in every chat, listed over the chats until one is opened
+pub struct SearchResults {
+    query: String,
+    found: Vec<Found>,
+    selected: usize,
+    total: i64,
+
+    // Whether more results have been asked for and not yet arrived
+    loading: bool,
+}
+
+struct Found {
+    chat_id: i64,
+    message_id: i64,
+    date: i64,
+    chat_title: String,
+    line: String,
+}
+
+// Search the open chat's history, jumping to the newest hit
+pub fn search_chat(app: &mut App, query: &str) {
+    let query = query.trim();
+    if query.is_empty() {
+        return;
+    }
+    let chat_id = match app.chat_list.selected_chat_id() {
+        Some(id) => id,
+        None => return app.set_status("No chat open".to_string()),
+    };
+    app.set_selected_block(TBlocks::CurrChat);
+    *app.search.lock().unwrap() = Some(ChatSearch {
+        chat_id,
+        query: query.to_string(),
+        hits: Vec::new(),
+        current: None,
+        more: true,
+        total: 0,
+    });
+    search_more(app, chat_id, query.to_string(), 0);
+}
+
+// Ask for hits older than `from_message_id`, or the newest when 0, then go to the first
+// of them
+fn search_more(app: &mut App, chat_id: i64, query: String, from_message_id: i64) {
+    let req = json!({
+        "@type": "searchChatMessages",
+        "chat_id": chat_id,
+        "query": query,
+        "sender": null,
+        "from_message_id": from_message_id,
+        "offset": 0,
+        "limit": SEARCH_LIMIT,
+        "filter": null,
+        "message_thread_id": 0,
+    });
+    app.requests.send_json(req, move |app, reply| {
+        let reply = match reply {
+            Ok(r) => r,
+            Err(e) => return app.set_status(format!("Couldn't search: {}", e)),
+        };
+        let ids: Vec<i64> = reply["messages"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|m| m["id"].as_i64())
+            .collect();
+        let mut guard = app.search.lock().unwrap();
+        let search = match guard.as_mut() {
+            // Another search was started meanwhile
+            Some(s) if s.chat_id == chat_id && s.query == query => s,
+            _ => return,
+        };
+        let before = search.hits.len();
+        for id in ids {
+            if !search.hits.contains(&id) {
+                search.hits.push(id);
+            }
+        }
+        search.total = reply["total_count"].as_i64().unwrap_or(0);
+        search.more = search.hits.len() > before && (search.hits.len() as i64) < search.total;
+        if search.hits.is_empty() {
+            *guard = None;
+            drop(guard);
+            return app.set_status(format!("No messages contain \"{}\"", query));
+        }
+        if search.hits.len() == before {
+            // Nothing older, so start again from the newest
+            search.current = Some(0);
+        } else {
+            search.current = Some(before);
+        }
+        drop(guard);
+        go_to_current(app);
+    });
+}
+
+// Go to the next older hit, or the next newer when `older` is false, wrapping around at
+// either end
+pub fn next_match(app: &mut App, older: bool) {
+    let mut guard = app.search.lock().unwrap();
+    let search = match guard.as_mut() {
+        Some(s) => s,
+        None => {
+            drop(guard);
+            return app.set_status("No search".to_string());
+        }
+    };
+    let len = search.hits.len();
+    if len == 0 {
+        return;
+    }
+    let next = match (search.current, older) {
+        (None, _) => 0,
+        (Some(i), true) if i + 1 < len => i + 1,
+        (Some(_), true) if search.more => {
+            let (chat_id, query, from) =
+                (search.chat_id, search.query.clone(), search.hits[len - 1]);
+            drop(guard);
+            return search_more(app, chat_id, query, from);
+        }
+        (Some(_), true) => 0,
+        (Some(0), false) => len - 1,
+        (Some(i), false) => i - 1,
+    };
+    search.current = Some(next);
+    drop(guard);
+    go_to_current(app);
+}
+
+// Jump to the hit last gone to, showing how far through the hits it is
+fn go_to_current(app: &mut App) {
+    let search = app.search.lock().unwrap();
+    let (chat_id, message_id, status) = match search.as_ref() {
+        Some(ChatSearch {
+            chat_id,
+            hits,
+            current: Some(i),
+            total,
+            ..
+        }) => (
+            *chat_id,
+            hits[*i],
+            format!("Match {} of {}", i + 1, (*total).max(hits.len() as i64)),
+        ),
+        _ => return,
+    };
+    drop(search);
+    jump_to_message(app, chat_id, message_id);
+    app.set_status(status);
+}
+
+// Scroll the chat with `chat_id` so that message `message_id` is at the bottom of the
+// screen. Messages not retrieved yet are gone to once the history around them has arrived
+pub fn jump_to_message(app: &mut App, chat_id: i64, message_id: i64) {
+    let requests = &app.requests;
+    let found = app.chat_list.with_chat(chat_id, |c| {
+        if c.show_message(message_id) {
+            c.jump_to = None;
+            return true;
+        }
+
+        // Already retrieved around it, so it's gone
+        if c.jump_to == Some(message_id) {
+            c.jump_to = None;
+            return false;
+        }
+        c.jump_to = Some(message_id);
+        c.retrieve_history(requests, message_id, -JUMP_AROUND, 2 * JUMP_AROUND);
+        true
+    });
+    if found == Some(false) {
+        app.set_status("Couldn't find that message in the chat's history".to_string());
+    }
+    app.redraw.request();
+}
+
+// Search every chat, listing the results
+pub fn search_all(app: &mut App, query: &str) {
+    let query = query.trim();
+    if query.is_empty() {
+        return;
+    }
+    *app.search_results.lock().unwrap() = Some(SearchResults {
+        query: query.to_string(),
+        found: Vec::new(),
+        selected: 0,
+        total: 0,
+        loading: true,
+    });
+    search_all_more(app, query.to_string(), (0, 0, 0));
+}
+
+// Ask for results older than `offset`, given as the date, chat and message of the
+// oldest result so far
+fn search_all_more(app: &mut App, query: String, offset: (i64, i64, i64)) {
+    let req = json!({
+        "@type": "searchMessages",
+        "chat_list": null,
+        "query": query,
+        "offset_date": offset.0,
+        "offset_chat_id": offset.1,
+        "offset_message_id": offset.2,
+        "limit": SEARCH_LIMIT,
+        "filter": null,
+        "min_date": 0,
+        "max_date": 0,
+    });
+    app.requests
+        .send_json(req, move |app, reply| add_results(app, &query, reply));
+}
+
+fn add_results(app: &mut App, query: &str, reply: Reply) {
+    let mut reply = match reply {
+        Ok(r) => r,
+        Err(e) => {
+            *app.search_results.lock().unwrap() = None;
+            return app.set_status(format!("Couldn't search: {}", e));
+        }
+    };
+
+    // Described here rather than while drawing, to look up chats and users once
+    let mut found = Vec::new();
+    {
+        let users = app.users.lock().unwrap();
+        let cv = app.chat_list.chat_vec.lock().unwrap();
+        for m in reply["messages"].as_array_mut().into_iter().flatten() {
+            let chat_id = m["chat_id"].as_i64().unwrap_or(0);
+            let msg = match parse_msg(m, chat_id) {
+                Ok(msg) => msg,
+                Err(e) => {
+                    eprintln!("Skipping search result: {}", e);
+                    continue;
+                }
+            };
+            let chat_title = cv
+                .iter()
+                .find(|c| c.chat.id() == chat_id)
+                .map_or_else(String::new, |c| c.chat.title().to_string());
+            let sender = users
+                .get(&msg.msg.sender_user_id())
+                .map_or_else(|| chat_title.clone(), |u| u.u.first_name().to_string());
+            let text = content::describe(&msg, &users).replace('\n', " ");
+            found.push(Found {
+                chat_id,
+                message_id: msg.msg.id(),
+                date: msg.msg.date(),
+                chat_title,
+                line: format!("{}: {}", sender, text),
+            });
+        }
+    }
+
+    let mut results = app.search_results.lock().unwrap();
+    let results = match results.as_mut() {
+        Some(r) if r.query == query => r,
+        _ => return,
+    };
+    results.loading = false;
+    results.total = reply["total_count"].as_i64().unwrap_or(0);
+    if found.is_empty() {
+        // Tdlib's count is only an estimate
+        results.total = results.found.len() as i64;
+    }
+    results.found.extend(found);
+    app.redraw.request();
+}
+
+// Handle a key while search results are shown
+pub fn handle_results_key(app: &mut App, key: Key) {
+    let mut results = app.search_results.lock().unwrap();
+    let state = match results.as_mut() {
+        Some(r) => r,
+        None => return,
+    };
+    match key {
+        Key::Esc | Key::Char('q') => *results = None,
+        Key::Char('\n') => {
+            let found = match state.found.get(state.selected) {
+                Some(f) => f,
+                None => return,
+            };
+            let (chat_id, message_id) = (found.chat_id, found.message_id);
+            let query = state.query.clone();
+            *results = None;
+            drop(results);
+            open_result(app, chat_id, message_id, query);
+        }
+        Key::Up | Key::Char('k') | Key::Ctrl('p') => {
+            state.selected = state.selected.saturating_sub(1);
+        }
+        Key::Down | Key::Char('j') | Key::Ctrl('n') => {
+            if state.selected + 1 < state.found.len() {
+                state.selected += 1;
+            }
+
+            // Near the end of what's been found, so ask for more
+            let more = (state.found.len() as i64) < state.total;
+            if more && !state.loading && state.selected + 5 >= state.found.len() {
+                state.loading = true;
+                let last = state
+                    .found
+                    .last()
+                    .map_or((0, 0, 0), |f| (f.date, f.chat_id, f.message_id));
+                let query = state.query.clone();
+                drop(results);
+                search_all_more(app, query, last);
+            }
+        }
+        _ => {}
+    }
+}
+
+// Open the chat a result is in, at the message found. Searching that chat carries on from
+// there with `n` and `N`
+fn open_result(app: &mut App, chat_id: i64, message_id: i64, query: String) {
+    *app.search.lock().unwrap() = Some(ChatSearch {
+        chat_id,
+        query,
+        hits: vec![message_id],
+        current: Some(0),
+        more: true,
+        total: 0,
+    });
+    app.open_message(chat_id, message_id);
+}
+
+// Draw search results over the middle of the screen
+pub fn render_results<B: Backend>(f: &mut Frame<B>, results: &SearchResults) {
+    let size = f.size();
+    let width = (size.width * 4 / 5).max(30).min(size.width);
+    let height = (size.height * 4 / 5).max(6).min(size.height);
+    let area = Rect::new(
+        size.x + (size.width - width) / 2,
+        size.y + (size.height - height) / 2,
+        width,
+        height,
+    );
+    let title = if results.loading && results.found.is_empty() {
+        format!("Searching for \"{}\"...", results.query)
+    } else {
+        format!(
+            "{} of {} messages containing \"{}\"",
+            results.found.len(),
+            results.total.max(results.found.len() as i64),
+            results.query
+        )
+    };
+    f.render_widget(Clear, area);
+    let block = Block::default().title(title).borders(Borders::ALL);
+    if results.found.is_empty() {
+        let empty = if results.loading { "" } else { "Nothing found" };
+        f.render_widget(Paragraph::new(empty).block(block), area);
+        return;
+    }
+
+    let items: Vec<ListItem> = results
+        .found
+        .iter()
+        .map(|found| {
+            let date = match Local.timestamp_opt(found.date, 0).single() {
+                Some(d) => d.format("%Y-%m-%d %H:%M").to_string(),
+                None => found.date.to_string(),
+            };
+            ListItem::new(Spans::from(vec![
+                Span::styled(format!("{} ", date), Style::default().fg(Color::DarkGray)),
+                Span::styled(
+                    format!("{} ", found.chat_title),
+                    Style::default().add_modifier(Modifier::BOLD),
+                ),
+                Span::raw(found.line.clone()),
+            ]))
+        })
+        .collect();
+    let list = List::new(items).block(block).highlight_style(
+        Style::default()
+            .fg(Color::Blue)
+            .add_modifier(Modifier::BOLD | Modifier::REVERSED),
+    );
+    let mut state = ListState::default();
+    state.select(Some(results.selected));
+    f.render_stateful_widget(list, area, &mut state);
+}
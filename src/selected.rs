@@ -0,0 +1,372 @@
+use crate::command::report_failure;
+use crate::content;
+use crate::finder::Finder;
+use crate::{App, InputMode, TBlocks, TMessage};
+use chrono::prelude::*;
+use rtdlib::types::*;
+use serde_json::json;
+use std::cmp::Reverse;
+use termion::event::Key;
+use tui::{
+    backend::Backend,
+    layout::{Constraint, Rect},
+    style::{Color, Modifier, Style},
+    text::Span,
+    widgets::{Block, Borders, Cell, Clear, Paragraph, Row, Table, Wrap},
+    Frame,
+};
+
+// Acting on messages selected in visual mode: deleting, forwarding, copying, pinning and
+// showing their details
+
+// Popup over the chat while acting on messages
+pub enum Popup {
+    // Asking whether to delete messages, and whether they can be deleted for everyone
+    ConfirmDelete {
+        chat_id: i64,
+        message_ids: Vec<i64>,
+        for_everyone: bool,
+    },
+
+    // A message's details, as (label, value)
+    Details(Vec<(String, String)>),
+}
+
+// The open chat and the messages selected in it, newest first
+pub fn selection(app: &App) -> Result<(i64, Vec<TMessage>), String> {
+    let selecting =
+        matches!(app.curr_mode(), InputMode::Visual) && app.selected_block() == TBlocks::CurrChat;
+    if !selecting {
+        return Err("Select a message in visual mode first".to_string());
+    }
+    let cv = app.chat_list.chat_vec.lock().unwrap();
+    let chat = app
+        .chat_list
+        .selected_index()
+        .and_then(|i| cv.get(i))
+        .ok_or_else(|| "No chat open".to_string())?;
+    let history = chat.history.lock().unwrap();
+
+    // Messages marked may be further back than those shown, after jumping elsewhere
+    let ids = chat.selected_ids(&history);
+    let mut msgs: Vec<TMessage> = history
+        .all()
+        .filter(|m| ids.contains(&m.msg.id()))
+        .cloned()
+        .collect();
+    if msgs.is_empty() {
+        return Err("No message selected".to_string());
+    }
+    msgs.sort_by_key(|m| Reverse(m.msg.id()));
+    Ok((chat.chat.id(), msgs))
+}
+
+// IDs of the messages marked in the open chat, if any are, rather than only the one under
+// the cursor being selected
+pub fn marked(app: &App) -> Option<Vec<i64>> {
+    let (chat_id, msgs) = selection(app).ok()?;
+    let marking = app.chat_list.with_chat(chat_id, |c| c.is_marking())?;
+    if !marking {
+        return None;
+    }
+    Some(msgs.iter().map(|m| m.msg.id()).collect())
+}
+
+// Unmark the messages in a chat once they've been acted on
+pub fn clear(app: &App, chat_id: i64) {
+    app.chat_list.with_chat(chat_id, |c| c.clear_selection());
+}
+
+// Run `f` on the selection, showing why if there's none
+fn with_selection(app: &mut App, f: impl FnOnce(&mut App, i64, Vec<TMessage>)) {
+    match selection(app) {
+        Ok((chat_id, msgs)) => f(app, chat_id, msgs),
+        Err(e) => app.set_status(e),
+    }
+}
+
+// Ask whether to delete the selected messages
+pub fn delete(app: &mut App) {
+    with_selection(app, |app, chat_id, msgs| {
+        let deletable = msgs
+            .iter()
+            .all(|m| m.msg.can_be_deleted_only_for_self() || m.msg.can_be_deleted_for_all_users());
+        if !deletable {
+            return app.set_status("Can't delete that".to_string());
+        }
+        *app.popup.lock().unwrap() = Some(Popup::ConfirmDelete {
+            chat_id,
+            message_ids: msgs.iter().map(|m| m.msg.id()).collect(),
+            for_everyone: msgs.iter().all(|m| m.msg.can_be_deleted_for_all_users()),
+        });
+    });
+}
+
+// Choose a chat to forward the selected messages to
+pub fn forward(app: &mut App) {
+    with_selection(app, |app, chat_id, msgs| {
+        if !msgs.iter().all(|m| m.msg.can_be_forwarded()) {
+            return app.set_status("Can't forward that".to_string());
+        }
+        let message_ids = msgs.iter().map(|m| m.msg.id()).collect();
+        let finder = Finder::forwarding(app, chat_id, message_ids);
+        *app.finder.lock().unwrap() = Some(finder);
+    });
+}
+
+pub fn forward_to(app: &mut App, from_chat_id: i64, message_ids: Vec<i64>, chat_id: i64) {
+    app.requests.send_json(
+        json!({
+            "@type": "forwardMessages",
+            "chat_id": chat_id,
+            "from_chat_id": from_chat_id,
+            "message_ids": message_ids,
+            "send_copy": false,
+            "remove_caption": false,
+        }),
+        |app, reply| report_failure(app, reply, "forward message"),
+    );
+}
+
+// Copy the text of the selected messages to the clipboard, oldest first. Several are
+// copied each with its sender's name, as they'd be read in the chat
+pub fn copy(app: &mut App) {
+    with_selection(app, |app, chat_id, msgs| {
+        let users = app.users.lock().unwrap();
+        let texts: Vec<String> = msgs
+            .iter()
+            .rev()
+            .map(|m| {
+                let text = match m.text() {
+                    Some(t) => t.to_string(),
+                    None => content::describe(m, &users),
+                };
+                if msgs.len() == 1 {
+                    return text;
+                }
+                match users.get(&m.msg.sender_user_id()) {
+                    Some(u) => format!("{}: {}", u.u.first_name(), text),
+                    None => text,
+                }
+            })
+            .collect();
+        drop(users);
+        *app.clipboard.lock().unwrap() = Some(texts.join("\n"));
+        clear(app, chat_id);
+        app.set_status(match msgs.len() {
+            1 => "Copied".to_string(),
+            n => format!("Copied {} messages", n),
+        });
+    });
+}
+
+// Pin the selected messages, or unpin them if they're all pinned
+pub fn toggle_pin(app: &mut App) {
+    with_selection(app, |app, chat_id, msgs| {
+        let pinning = !msgs.iter().all(|m| m.is_pinned);
+        for m in msgs.iter() {
+            pin(app, chat_id, m.msg.id(), pinning);
+        }
+        clear(app, chat_id);
+    });
+}
+
+pub fn pin(app: &mut App, chat_id: i64, message_id: i64, pin: bool) {
+    let req = if pin {
+        json!({
+            "@type": "pinChatMessage",
+            "chat_id": chat_id,
+            "message_id": message_id,
+            "disable_notification": false,
+            "only_for_self": false,
+        })
+    } else {
+        json!({
+            "@type": "unpinChatMessage",
+            "chat_id": chat_id,
+            "message_id": message_id,
+        })
+    };
+    let what = if pin { "pin message" } else { "unpin message" };
+    app.requests
+        .send_json(req, move |app, reply| report_failure(app, reply, what));
+}
+
+// Show the selected message's details
+pub fn details(app: &mut App) {
+    with_selection(app, |app, _, msgs| {
+        let msg = match msgs.first() {
+            Some(m) => m,
+            None => return,
+        };
+        let rows = describe_details(app, msg);
+        *app.popup.lock().unwrap() = Some(Popup::Details(rows));
+    });
+}
+
+fn describe_details(app: &App, msg: &TMessage) -> Vec<(String, String)> {
+    let users = app.users.lock().unwrap();
+    let user_name = |id: i64| match users.get(&id) {
+        Some(u) => format!(
+            "{} ({})",
+            content::full_name(u.u.first_name(), u.u.last_name()),
+            id
+        ),
+        None => id.to_string(),
+    };
+    let chat_title = |id: i64| {
+        let title = app.chat_list.with_chat(id, |c| c.chat.title().to_string());
+        match title {
+            Some(t) => format!("{} ({})", t, id),
+            None => id.to_string(),
+        }
+    };
+    let date = |ts: i64| match Local.timestamp_opt(ts, 0).single() {
+        Some(d) => d.format("%Y-%m-%d %H:%M:%S").to_string(),
+        None => ts.to_string(),
+    };
+
+    let m = &msg.msg;
+    let mut rows = vec![
+        ("Message ID".to_string(), m.id().to_string()),
+        ("Chat".to_string(), chat_title(m.chat_id())),
+        ("Sender".to_string(), user_name(m.sender_user_id())),
+        ("Sent".to_string(), date(m.date())),
+    ];
+    if m.edit_date() > 0 {
+        rows.push(("Edited".to_string(), date(m.edit_date())));
+    }
+    if let Some((chat_id, message_id)) = msg.replied_to() {
+        rows.push((
+            "Reply to".to_string(),
+            format!("{} in {}", message_id, chat_title(chat_id)),
+        ));
+    }
+    let forward = &msg.forward_info;
+    if forward.is_object() {
+        let origin = &forward["origin"];
+        let from = match origin["@type"].as_str() {
+            Some("messageForwardOriginUser") => {
+                user_name(origin["sender_user_id"].as_i64().unwrap_or(0))
+            }
+            Some("messageForwardOriginChat") => {
+                chat_title(origin["sender_chat_id"].as_i64().unwrap_or(0))
+            }
+            Some("messageForwardOriginChannel") => {
+                chat_title(origin["chat_id"].as_i64().unwrap_or(0))
+            }
+            _ => origin["sender_name"]
+                .as_str()
+                .unwrap_or("Hidden user")
+                .to_string(),
+        };
+        rows.push(("Forwarded from".to_string(), from));
+        rows.push((
+            "Originally sent".to_string(),
+            date(forward["date"].as_i64().unwrap_or(0)),
+        ));
+    }
+    if msg.is_pinned {
+        rows.push(("Pinned".to_string(), "yes".to_string()));
+    }
+    rows.push((
+        "Type".to_string(),
+        msg.content["@type"]
+            .as_str()
+            .unwrap_or("unknown")
+            .to_string(),
+    ));
+    rows
+}
+
+// Handle a key while a popup is shown
+pub fn handle_popup_key(app: &mut App, key: Key) {
+    let popup = match app.popup.lock().unwrap().take() {
+        Some(p) => p,
+        None => return,
+    };
+
+    // Anything but a choice cancels
+    if let Popup::ConfirmDelete {
+        chat_id,
+        message_ids,
+        for_everyone,
+    } = popup
+    {
+        let revoke = match key {
+            Key::Char('m') => false,
+            Key::Char('e') if for_everyone => true,
+            _ => return,
+        };
+        let req = DeleteMessages::builder()
+            .chat_id(chat_id)
+            .message_ids(message_ids)
+            .revoke(revoke)
+            .build();
+        app.requests.send_then(&req, |app, reply| {
+            report_failure(app, reply, "delete message")
+        });
+        clear(app, chat_id);
+    }
+}
+
+// Draw a popup over the middle of the screen
+pub fn render_popup<B: Backend>(f: &mut Frame<B>, popup: &Popup) {
+    let size = f.size();
+    match popup {
+        Popup::ConfirmDelete {
+            message_ids,
+            for_everyone,
+            ..
+        } => {
+            let what = match message_ids.len() {
+                1 => "this message".to_string(),
+                n => format!("{} messages", n),
+            };
+            let mut text = format!("Delete {}?\n\n[m] for me", what);
+            if *for_everyone {
+                text.push_str("  [e] for everyone");
+            }
+            text.push_str("  [Esc] cancel");
+            let area = centered(size, 50, 5);
+            f.render_widget(Clear, area);
+            let block = Block::default()
+                .title(Span::styled(
+                    "Delete",
+                    Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+                ))
+                .borders(Borders::ALL);
+            f.render_widget(
+                Paragraph::new(text).block(block).wrap(Wrap { trim: false }),
+                area,
+            );
+        }
+        Popup::Details(rows) => {
+            let label_width = rows.iter().map(|(l, _)| l.len()).max().unwrap_or(0) as u16;
+            let widths = [Constraint::Length(label_width), Constraint::Percentage(100)];
+            let area = centered(size, 70, rows.len() as u16 + 2);
+            f.render_widget(Clear, area);
+            let table = Table::new(rows.iter().map(|(label, value)| {
+                Row::new(vec![
+                    Cell::from(label.as_str()).style(Style::default().fg(Color::DarkGray)),
+                    Cell::from(value.as_str()),
+                ])
+            }))
+            .block(Block::default().title("Message").borders(Borders::ALL))
+            .widths(&widths)
+            .column_spacing(2);
+            f.render_widget(table, area);
+        }
+    }
+}
+
+// A box of `width` by `height`, or as much of it as fits, in the middle of `size`
+fn centered(size: Rect, width: u16, height: u16) -> Rect {
+    let (width, height) = (width.min(size.width), height.min(size.height));
+    Rect::new(
+        size.x + (size.width - width) / 2,
+        size.y + (size.height - height) / 2,
+        width,
+        height,
+    )
+}
@@ -0,0 +1,176 @@
+use crate::positions::ChatListId;
+use crate::{App, TChat};
+use serde_json::Value;
+
+// Tabs above the chat list: all chats, then Telegram's chat folders, then filters set up
+// in the config file, which only exist here
+
+// Chats picked out by a filter from the config file, among all chats
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LocalFilter {
+    Unread,
+    Private,
+    Groups,
+    Channels,
+    Bots,
+    Muted,
+}
+
+impl LocalFilter {
+    pub fn from_name(name: &str) -> Option<LocalFilter> {
+        match name.to_lowercase().as_str() {
+            "unread" => Some(LocalFilter::Unread),
+            "private" => Some(LocalFilter::Private),
+            "groups" => Some(LocalFilter::Groups),
+            "channels" => Some(LocalFilter::Channels),
+            "bots" => Some(LocalFilter::Bots),
+            "muted" => Some(LocalFilter::Muted),
+            _ => None,
+        }
+    }
+
+    fn title(self) -> &'static str {
+        match self {
+            LocalFilter::Unread => "Unread",
+            LocalFilter::Private => "Private",
+            LocalFilter::Groups => "Groups",
+            LocalFilter::Channels => "Channels",
+            LocalFilter::Bots => "Bots",
+            LocalFilter::Muted => "Muted",
+        }
+    }
+
+    pub(crate) fn matches(self, chat: &TChat) -> bool {
+        let type_ = chat.chat.type_();
+        let is_channel = type_.as_supergroup().is_some_and(|sg| sg.is_channel());
+        match self {
+            LocalFilter::Unread => chat.unread_count > 0 || chat.unread_mention_count > 0,
+            LocalFilter::Private => (type_.is_private() || type_.is_secret()) && !chat.is_bot,
+            LocalFilter::Groups => type_.is_basic_group() || (type_.is_supergroup() && !is_channel),
+            LocalFilter::Channels => is_channel,
+            LocalFilter::Bots => chat.is_bot,
+            LocalFilter::Muted => chat.notification_settings["mute_for"].as_i64().unwrap_or(0) > 0,
+        }
+    }
+}
+
+// Which chats a tab shows: those in a list, and of them those a filter matches, if any
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct View {
+    pub list: ChatListId,
+    pub filter: Option<LocalFilter>,
+}
+
+impl View {
+    // Every chat in a list
+    pub fn all(list: ChatListId) -> View {
+        View { list, filter: None }
+    }
+
+    pub fn contains(self, chat: &TChat) -> bool {
+        chat.positions.contains_key(&self.list) && self.filter.is_none_or(|f| f.matches(chat))
+    }
+}
+
+#[derive(Clone)]
+pub struct Tab {
+    pub title: String,
+    pub view: View,
+}
+
+// The tabs, and which is shown
+pub struct TabsState {
+    pub tabs: Vec<Tab>,
+    pub index: usize,
+}
+
+impl TabsState {
+    pub fn new(filters: &[LocalFilter]) -> TabsState {
+        let mut state = TabsState {
+            tabs: Vec::new(),
+            index: 0,
+        };
+        state.set_folders(filters, Vec::new());
+        state
+    }
+
+    // Replace the tabs with all chats, `folders` and then `filters`, keeping the one shown
+    // if it's still there
+    fn set_folders(&mut self, filters: &[LocalFilter], folders: Vec<Tab>) {
+        let shown = self.tabs.get(self.index).map(|t| t.view);
+        self.tabs = vec![Tab {
+            title: "All".to_string(),
+            view: View::all(ChatListId::Main),
+        }];
+        self.tabs.extend(folders);
+        self.tabs.extend(filters.iter().map(|&f| Tab {
+            title: f.title().to_string(),
+            view: View {
+                list: ChatListId::Main,
+                filter: Some(f),
+            },
+        }));
+        self.index = shown
+            .and_then(|v| self.tabs.iter().position(|t| t.view == v))
+            .unwrap_or(0);
+    }
+
+    pub fn view(&self) -> View {
+        self.tabs[self.index].view
+    }
+
+    pub fn titles(&self) -> Vec<String> {
+        self.tabs.iter().map(|t| t.title.clone()).collect()
+    }
+
+    pub fn next(&mut self) {
+        self.index = (self.index + 1) % self.tabs.len();
+    }
+
+    pub fn previous(&mut self) {
+        if self.index > 0 {
+            self.index -= 1;
+        } else {
+            self.index = self.tabs.len() - 1;
+        }
+    }
+}
+
+// Telegram's chat folders changed, as chatFilterInfos
+pub fn set_folders(app: &mut App, folders: &Value) {
+    let folders = folders
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|f| {
+            Some(Tab {
+                title: f["title"].as_str()?.to_string(),
+                view: View::all(ChatListId::Folder(f["id"].as_i64()?)),
+            })
+        })
+        .collect();
+    let mut tabs = app.chat_list.tabs.lock().unwrap();
+    let shown = tabs.view();
+    tabs.set_folders(&app.config.chat_filters, folders);
+    let view = tabs.view();
+    drop(tabs);
+
+    // The folder shown was deleted
+    if view != shown && app.chat_list.view() == shown {
+        app.chat_list.show(view);
+    }
+}
+
+// Switch to the next tab, or the previous one
+pub fn switch(app: &mut App, forward: bool) {
+    let mut tabs = app.chat_list.tabs.lock().unwrap();
+    if forward {
+        tabs.next();
+    } else {
+        tabs.previous();
+    }
+    let view = tabs.view();
+    drop(tabs);
+    app.chat_list.show(view);
+    app.chat_list.load_more(&app.requests);
+}
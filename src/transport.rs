@@ -0,0 +1,163 @@
+use rtdlib::Tdlib;
+use serde_json::Value;
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::io;
+use std::sync::Mutex;
+use std::time::Duration;
+
+// Command line argument to run against a replay file instead of Telegram
+pub const REPLAY_ARG: &str = "--replay=";
+
+// Anything requests can be sent to and updates received from, in Tdlib's JSON format
+pub trait Transport: Send + Sync {
+    fn send(&self, request: &str);
+
+    // Wait up to `timeout` seconds for the next update or response
+    fn receive(&self, timeout: f64) -> Option<String>;
+}
+
+impl Transport for Tdlib {
+    fn send(&self, request: &str) {
+        Tdlib::send(self, request)
+    }
+
+    fn receive(&self, timeout: f64) -> Option<String> {
+        Tdlib::receive(self, timeout)
+    }
+}
+
+// Stands in for Tdlib without a connection to Telegram. Hands out canned updates,
+// replies to requests by type, and records every request sent to it
+#[derive(Default)]
+pub struct FakeTransport {
+    // Updates waiting to be received, in order
+    incoming: Mutex<VecDeque<String>>,
+
+    // Responses to hand out once a request of the given type (e.g. "getChats") is sent.
+    // Each request gets one response, with the updates before and after it
+    replies: Mutex<HashMap<String, VecDeque<String>>>,
+
+    // Every request sent, in order
+    sent: Mutex<Vec<String>>,
+}
+
+impl FakeTransport {
+    pub fn new() -> FakeTransport {
+        FakeTransport::default()
+    }
+
+    // Load a replay script. Each line is either
+    //   {"@type": ...}              an update, received in file order
+    //   requestType {"@type": ...}  a response, received once a `requestType` request is sent.
+    //                               Further responses wait for further requests of the type
+    // Blank lines and lines starting with `#` are ignored
+    pub fn from_file(path: &str) -> io::Result<FakeTransport> {
+        let fake = FakeTransport::new();
+        for (i, line) in fs::read_to_string(path)?.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (request_type, json) = if line.starts_with('{') {
+                (None, line)
+            } else {
+                match line.find(char::is_whitespace) {
+                    Some(split) => (Some(&line[..split]), line[split..].trim()),
+                    None => (Some(line), ""),
+                }
+            };
+            if serde_json::from_str::<Value>(json).is_err() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}:{}: not valid JSON", path, i + 1),
+                ));
+            }
+            match request_type {
+                Some(t) => fake.reply_to(t, json),
+                None => fake.push(json),
+            }
+        }
+        Ok(fake)
+    }
+
+    // Queue an update to be received
+    pub fn push(&self, update: &str) {
+        self.incoming.lock().unwrap().push_back(update.to_string());
+    }
+
+    // Queue a response to be received after the next request of type `request_type` is sent
+    pub fn reply_to(&self, request_type: &str, response: &str) {
+        self.replies
+            .lock()
+            .unwrap()
+            .entry(request_type.to_string())
+            .or_default()
+            .push_back(response.to_string());
+    }
+
+    // All requests sent so far
+    pub fn sent(&self) -> Vec<String> {
+        self.sent.lock().unwrap().clone()
+    }
+
+    // Types of all requests sent so far, e.g. ["setTdlibParameters", "getChats"]
+    pub fn sent_types(&self) -> Vec<String> {
+        self.sent()
+            .iter()
+            .filter_map(|r| serde_json::from_str::<Value>(r).ok())
+            .map(|r| r["@type"].as_str().unwrap_or("").to_string())
+            .collect()
+    }
+}
+
+impl Transport for FakeTransport {
+    fn send(&self, request: &str) {
+        self.sent.lock().unwrap().push(request.to_string());
+        let request: Value = match serde_json::from_str(request) {
+            Ok(r) => r,
+            Err(_) => return,
+        };
+        let request_type = request["@type"].as_str().unwrap_or("");
+
+        // Deliver what's waiting on this type of request, up to the first response that
+        // isn't an update and the updates straight after it. Like Tdlib, responses (but
+        // not updates) carry the request's "@extra"
+        let mut replies = self.replies.lock().unwrap();
+        let waiting = match replies.get_mut(request_type) {
+            Some(r) => r,
+            None => return,
+        };
+        let mut incoming = self.incoming.lock().unwrap();
+        let mut answered = false;
+        while let Some(response) = waiting.front() {
+            let mut response: Value = serde_json::from_str(response).unwrap_or_default();
+            let is_update = response["@type"]
+                .as_str()
+                .is_some_and(|t| t.starts_with("update"));
+            if answered && !is_update {
+                break;
+            }
+            if !is_update {
+                answered = true;
+                if !request["@extra"].is_null() {
+                    response["@extra"] = request["@extra"].clone();
+                }
+            }
+            incoming.push_back(response.to_string());
+            waiting.pop_front();
+        }
+        if waiting.is_empty() {
+            replies.remove(request_type);
+        }
+    }
+
+    fn receive(&self, timeout: f64) -> Option<String> {
+        let next = self.incoming.lock().unwrap().pop_front();
+        if next.is_none() {
+            // Behave like Tdlib and wait out the timeout when there's nothing to receive
+            std::thread::sleep(Duration::from_secs_f64(timeout));
+        }
+        next
+    }
+}
@@ -0,0 +1,836 @@
+use crate::content;
+use crate::login::{self, LoginStep};
+use crate::positions;
+use crate::tabs;
+use crate::{get_me, parse_msg, read_key_file, send_tdlib_parameters};
+use crate::{App, MsgState, TBasicGroup, TChat, TSecretChat, TSupergroup, TUser};
+use rtdlib::types::*;
+use serde_json::{json, Value};
+use std::fmt;
+
+// Why an object received from Tdlib couldn't be handled
+pub enum UpdateError {
+    // A field was missing or had the wrong type
+    Malformed(&'static str),
+
+    // Couldn't be converted to the named rtdlib type
+    Parse(&'static str, String),
+
+    // Refers to a chat we haven't been told about
+    UnknownChat(i64),
+}
+
+impl fmt::Display for UpdateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UpdateError::Malformed(field) => write!(f, "missing or invalid field `{}`", field),
+            UpdateError::Parse(t, e) => write!(f, "couldn't parse {}: {}", t, e),
+            UpdateError::UnknownChat(id) => write!(f, "unknown chat {}", id),
+        }
+    }
+}
+
+// Handles an object, returning whether it changed anything shown
+type Handler = fn(&mut App, &mut Value) -> Result<bool, UpdateError>;
+
+// Handler for each type of object received from Tdlib, by its "@type"
+const HANDLERS: &[(&str, Handler)] = &[
+    ("updateAuthorizationState", authorization_state),
+    ("updateUser", user),
+    ("updateUserStatus", user_status),
+    ("updateBasicGroup", basic_group),
+    ("updateBasicGroupFullInfo", basic_group_full_info),
+    ("updateSupergroup", supergroup),
+    ("updateSupergroupFullInfo", supergroup_full_info),
+    ("updateSecretChat", secret_chat),
+    ("updateNewChat", new_chat),
+    ("updateChatLastMessage", chat_last_message),
+    ("updateChatPosition", chat_position),
+    ("updateChatDraftMessage", chat_draft_message),
+    ("updateChatFilters", chat_filters),
+    ("updateChatNotificationSettings", chat_notification_settings),
+    ("updateChatOnlineMemberCount", chat_online_member_count),
+    ("updateChatReadInbox", chat_read_inbox),
+    ("updateChatReadOutbox", chat_read_outbox),
+    ("updateChatUnreadMentionCount", chat_unread_mention_count),
+    ("updateMessageMentionRead", chat_unread_mention_count),
+    ("updateNewMessage", new_message),
+    ("updateMessageSendSucceeded", message_sent),
+    ("updateMessageSendFailed", message_sent),
+    ("updateMessageIsPinned", message_is_pinned),
+    ("updateDeleteMessages", delete_messages),
+    ("error", error),
+];
+
+// Pass an object received from Tdlib to the handler for its type, redrawing if it changed
+// anything shown. Objects of unknown types are logged and otherwise ignored
+pub fn dispatch(app: &mut App, obj: &mut Value) -> Result<(), UpdateError> {
+    let td_type = obj["@type"]
+        .as_str()
+        .ok_or(UpdateError::Malformed("@type"))?;
+    match HANDLERS.iter().find(|(t, _)| *t == td_type) {
+        Some((_, handler)) => {
+            if handler(app, obj)? {
+                app.redraw.request();
+            }
+            Ok(())
+        }
+        None => {
+            eprintln!("Unhandled message: {}", obj);
+            Ok(())
+        }
+    }
+}
+
+// Get an integer field of `obj`
+fn get_i64(obj: &Value, field: &'static str) -> Result<i64, UpdateError> {
+    obj[field].as_i64().ok_or(UpdateError::Malformed(field))
+}
+
+// Parse a field of `obj` with the given rtdlib parser, naming `td_type` on failure
+fn parse<T, E: fmt::Display>(
+    obj: &Value,
+    td_type: &'static str,
+    parser: fn(String) -> Result<T, E>,
+) -> Result<T, UpdateError> {
+    parser(obj.to_string()).map_err(|e| UpdateError::Parse(td_type, e.to_string()))
+}
+
+// Whether the chat `chat_id` is the one open
+fn is_open(app: &App, chat_id: i64) -> bool {
+    app.chat_list.selected_chat_id() == Some(chat_id)
+}
+
+// The type of the chat open, if any, which says what's shown beside its title
+fn open_chat_type(app: &App) -> Option<ChatType> {
+    app.chat_list.with_selected(|c| c.chat.type_().clone())
+}
+
+fn is_open_basic_group(app: &App, group_id: i64) -> bool {
+    let open = open_chat_type(app);
+    matches!(open, Some(ChatType::BasicGroup(g)) if g.basic_group_id() == group_id)
+}
+
+fn is_open_supergroup(app: &App, supergroup_id: i64) -> bool {
+    let open = open_chat_type(app);
+    matches!(open, Some(ChatType::Supergroup(g)) if g.supergroup_id() == supergroup_id)
+}
+
+// Received any of a number of auth state changes
+fn authorization_state(app: &mut App, obj: &mut Value) -> Result<bool, UpdateError> {
+    let config = app.config.clone();
+    let state = &obj["authorization_state"];
+    let astate = parse(state, "AuthorizationState", AuthorizationState::from_json)?;
+    let mut login = app.login.lock().unwrap();
+    match astate {
+        // Authorization complete, get list of chats
+        AuthorizationState::Ready(_) => {
+            login.finish();
+
+            // Encrypt the database with the passphrase chosen while opening it
+            if let Some(key) = login.take_pending_key() {
+                login::send_set_encryption_key(&app.requests, &key);
+            }
+            if login.take_rekey_request() {
+                login.prompt_rekey();
+            }
+            app.chat_list.load_more(&app.requests);
+            get_me(&app.requests);
+        }
+        // Initial setup request
+        AuthorizationState::WaitTdlibParameters(_) => {
+            send_tdlib_parameters(&app.requests, &config);
+        }
+
+        // Unlock the local database, with the key from the configured key file,
+        // or else a passphrase asked for on the login screen
+        AuthorizationState::WaitEncryptionKey(_) => {
+            let is_encrypted = state["is_encrypted"].as_bool().unwrap_or(false);
+            let key_file = config.encryption_key_file.as_deref().map(read_key_file);
+            match key_file {
+                // If the key is rejected, the login screen asks for it instead
+                Some(Ok(key)) if is_encrypted => {
+                    login.prompt(LoginStep::EncryptionKey, String::new(), "");
+                    login.waiting = true;
+                    login::send_check_encryption_key(&app.requests, &key);
+                }
+                // Open unencrypted, then encrypt with the key once ready
+                Some(Ok(key)) => {
+                    login.set_pending_key(key);
+                    login::send_check_encryption_key(&app.requests, "");
+                }
+                Some(Err(e)) => {
+                    login.prompt(LoginStep::EncryptionKey, String::new(), "");
+                    login.error = Some(format!("Couldn't read key file: {}", e));
+                }
+                None if is_encrypted => login.prompt(LoginStep::EncryptionKey, String::new(), ""),
+                None if config.encrypt => login.prompt(
+                    LoginStep::NewEncryptionKey,
+                    "Local data is not encrypted yet. Choose a passphrase, \
+                     or leave empty for none"
+                        .to_string(),
+                    "",
+                ),
+                None => login::send_check_encryption_key(&app.requests, ""),
+            }
+        }
+
+        // Tdlib is waiting for phone number. Prompt for it, pre-filled
+        // with the one from the config file if there is one
+        AuthorizationState::WaitPhoneNumber(_) => {
+            login.prompt(LoginStep::PhoneNumber, String::new(), &config.phone_number);
+        }
+
+        // Tdlib is awaiting authorization code that was sent
+        // to user via Telegram, SMS, or otherwise
+        AuthorizationState::WaitCode(_) => {
+            let code_type = state["code_info"]["type"]["@type"].as_str().unwrap_or("");
+            login.prompt(LoginStep::Code, login::describe_code_type(code_type), "");
+        }
+
+        // Account has two-step verification enabled
+        AuthorizationState::WaitPassword(_) => {
+            let hint = match state["password_hint"].as_str() {
+                Some(h) if !h.is_empty() => format!("Hint: {}", h),
+                _ => String::new(),
+            };
+            login.prompt(LoginStep::Password, hint, "");
+        }
+
+        // Phone number isn't associated with an account yet
+        AuthorizationState::WaitRegistration(_) => {
+            login.prompt(
+                LoginStep::FirstName,
+                "This number has no Telegram account yet. Registering...".to_string(),
+                "",
+            );
+        }
+        _ => {
+            eprintln!("unhandled auth case!: {}", state);
+        }
+    }
+    Ok(true)
+}
+
+// Received user information. Can be new or an update to an existing
+fn user(app: &mut App, obj: &mut Value) -> Result<bool, UpdateError> {
+    let u = parse(&obj["user"], "User", User::from_json)?;
+    let is_bot = obj["user"]["type"]["@type"] == "userTypeBot";
+    let status = parse(&obj["user"]["status"], "UserStatus", UserStatus::from_json)?;
+    let palette = &app.config.palette;
+    let mut users = app.users.lock().unwrap();
+    let num_users = users.len();
+
+    // Create TUser, determining name color, or update if already exists
+    users
+        .entry(u.id())
+        .and_modify(|tu| tu.u = u.clone())
+        .or_insert(TUser {
+            u,
+            // Calculate the next color to use, maintaining maximum variety
+            color: palette[num_users % palette.len()],
+            full_info: UserFullInfo::builder().build(),
+            status,
+        });
+    drop(users);
+
+    // Private chats have the user's ID
+    app.chat_list
+        .with_chat(obj["user"]["id"].as_i64().unwrap_or(0), |c| {
+            c.is_bot = is_bot
+        });
+    Ok(true)
+}
+
+// Received an update to users status (online/offline/etc.)
+fn user_status(app: &mut App, obj: &mut Value) -> Result<bool, UpdateError> {
+    let uid = get_i64(obj, "user_id")?;
+    let status = parse(&obj["status"], "UserStatus", UserStatus::from_json)?;
+    if let Some(tu) = app.users.lock().unwrap().get_mut(&uid) {
+        tu.status = status;
+    }
+
+    // Only shown beside the title of a chat with the user, or of a group they're in
+    Ok(match open_chat_type(app) {
+        Some(ChatType::Private(p)) => p.user_id() == uid,
+        Some(ChatType::Secret(s)) => s.user_id() == uid,
+        Some(ChatType::BasicGroup(g)) => app
+            .basic_groups
+            .lock()
+            .unwrap()
+            .get(&g.basic_group_id())
+            .is_some_and(|bg| bg.full_info.members().iter().any(|m| m.user_id() == uid)),
+        _ => false,
+    })
+}
+
+// Received information about a basic group
+fn basic_group(app: &mut App, obj: &mut Value) -> Result<bool, UpdateError> {
+    let g = parse(&obj["basic_group"], "BasicGroup", BasicGroup::from_json)?;
+    let id = g.id();
+
+    // Insert, or update keeping any full info already received
+    app.basic_groups
+        .lock()
+        .unwrap()
+        .entry(g.id())
+        .and_modify(|bg| bg.g = g.clone())
+        .or_insert(TBasicGroup {
+            g,
+            full_info: BasicGroupFullInfo::default(),
+        });
+    Ok(is_open_basic_group(app, id))
+}
+
+// Received full information about a basic group
+fn basic_group_full_info(app: &mut App, obj: &mut Value) -> Result<bool, UpdateError> {
+    let group_id = get_i64(obj, "basic_group_id")?;
+    let full_info = parse(
+        &obj["basic_group_full_info"],
+        "BasicGroupFullInfo",
+        BasicGroupFullInfo::from_json,
+    )?;
+    if let Some(bg) = app.basic_groups.lock().unwrap().get_mut(&group_id) {
+        bg.full_info = full_info;
+    }
+    Ok(is_open_basic_group(app, group_id))
+}
+
+// Received information about a supergroup or channel
+fn supergroup(app: &mut App, obj: &mut Value) -> Result<bool, UpdateError> {
+    let g = parse(&obj["supergroup"], "Supergroup", Supergroup::from_json)?;
+    let id = g.id();
+    app.supergroups
+        .lock()
+        .unwrap()
+        .entry(g.id())
+        .and_modify(|sg| sg.g = g.clone())
+        .or_insert(TSupergroup {
+            g,
+            full_info: Value::Null,
+            retrieving_full_info: false,
+        });
+    Ok(is_open_supergroup(app, id))
+}
+
+fn supergroup_full_info(app: &mut App, obj: &mut Value) -> Result<bool, UpdateError> {
+    let supergroup_id = get_i64(obj, "supergroup_id")?;
+    let full_info = obj["supergroup_full_info"].take();
+    if let Some(sg) = app.supergroups.lock().unwrap().get_mut(&supergroup_id) {
+        sg.full_info = full_info;
+    }
+    Ok(is_open_supergroup(app, supergroup_id))
+}
+
+// Received a new secret chat, or a change in its state
+fn secret_chat(app: &mut App, obj: &mut Value) -> Result<bool, UpdateError> {
+    let chat = &obj["secret_chat"];
+    let id = get_i64(chat, "id")?;
+    let secret = TSecretChat {
+        state: parse(
+            &chat["state"],
+            "SecretChatState",
+            SecretChatState::from_json,
+        )?,
+        is_outbound: chat["is_outbound"].as_bool().unwrap_or(false),
+    };
+    app.secret_chats.lock().unwrap().insert(id, secret);
+    let open = open_chat_type(app);
+    Ok(matches!(open, Some(ChatType::Secret(s)) if s.secret_chat_id() == id))
+}
+
+// Received information about a chat of which we've not heard before
+fn new_chat(app: &mut App, obj: &mut Value) -> Result<bool, UpdateError> {
+    let new_chat = &mut obj["chat"];
+    if !new_chat.is_object() {
+        return Err(UpdateError::Malformed("chat"));
+    }
+
+    let notification_settings = new_chat["notification_settings"].clone();
+    let count = |field: &str| new_chat[field].as_i64().unwrap_or(0);
+    let (unread_count, unread_mention_count) =
+        (count("unread_count"), count("unread_mention_count"));
+    let last_read_inbox_message_id = count("last_read_inbox_message_id");
+    let last_read_outbox_message_id = count("last_read_outbox_message_id");
+
+    // Add attributes to new_chat that are expected by rtdlib,
+    // but not provided by the API
+    new_chat["order"] = json!(1);
+    new_chat["is_pinned"] = json!(false);
+    new_chat["is_sponsored"] = json!(false);
+    new_chat["pinned_message_id"] = json!(0);
+
+    // Add TChat to chat list
+    let mut tchat = TChat::from_json(new_chat.to_string())
+        .map_err(|e| UpdateError::Parse("Chat", e.to_string()))?;
+    tchat.parse_mode = app.config.parse_mode;
+    tchat.notification_settings = notification_settings;
+    if let Some(private) = tchat.chat.type_().as_private() {
+        let users = app.users.lock().unwrap();
+        tchat.is_bot = users
+            .get(&private.user_id())
+            .is_some_and(|u| u.u.type_().is_bot());
+    }
+    tchat.unread_count = unread_count;
+    tchat.unread_mention_count = unread_mention_count;
+    tchat.last_read_inbox_message_id = last_read_inbox_message_id;
+    tchat.last_read_outbox_message_id = last_read_outbox_message_id;
+    set_positions(&mut tchat, &new_chat["positions"]);
+    app.chat_list.chat_vec.lock().unwrap().push(tchat);
+    app.chat_list.sort_later();
+    Ok(true)
+}
+
+// Apply each of an array of chatPositions to a chat
+fn set_positions(chat: &mut TChat, positions: &Value) {
+    for position in positions.as_array().into_iter().flatten() {
+        positions::set_position(chat, position);
+    }
+}
+
+fn chat_last_message(app: &mut App, obj: &mut Value) -> Result<bool, UpdateError> {
+    let chat_id = get_i64(obj, "chat_id")?;
+
+    // Last message is absent when e.g. the chat's history is cleared
+    let date = obj["last_message"]["date"].as_i64().unwrap_or(0);
+    app.chat_list
+        .with_chat(chat_id, |c| {
+            c.set_last_msg_date(date);
+            set_positions(c, &obj["positions"]);
+        })
+        .ok_or(UpdateError::UnknownChat(chat_id))?;
+    app.chat_list.sort_later();
+    Ok(true)
+}
+
+fn chat_position(app: &mut App, obj: &mut Value) -> Result<bool, UpdateError> {
+    let chat_id = get_i64(obj, "chat_id")?;
+    app.chat_list
+        .with_chat(chat_id, |c| positions::set_position(c, &obj["position"]))
+        .ok_or(UpdateError::UnknownChat(chat_id))?;
+    app.chat_list.sort_later();
+    Ok(true)
+}
+
+// Telegram's chat folders, shown as tabs
+fn chat_filters(app: &mut App, obj: &mut Value) -> Result<bool, UpdateError> {
+    tabs::set_folders(app, &obj["chat_filters"]);
+    Ok(true)
+}
+
+// Drafts aren't kept, but move chats within their lists
+fn chat_draft_message(app: &mut App, obj: &mut Value) -> Result<bool, UpdateError> {
+    let chat_id = get_i64(obj, "chat_id")?;
+    app.chat_list
+        .with_chat(chat_id, |c| set_positions(c, &obj["positions"]))
+        .ok_or(UpdateError::UnknownChat(chat_id))?;
+    app.chat_list.sort_later();
+    Ok(true)
+}
+
+fn chat_notification_settings(app: &mut App, obj: &mut Value) -> Result<bool, UpdateError> {
+    let chat_id = get_i64(obj, "chat_id")?;
+    let settings = obj["notification_settings"].take();
+    app.chat_list
+        .with_chat(chat_id, |c| c.notification_settings = settings)
+        .ok_or(UpdateError::UnknownChat(chat_id))?;
+
+    // Chats may have come into or gone out of a tab of muted chats
+    app.chat_list.sort_later();
+    Ok(true)
+}
+
+fn chat_online_member_count(app: &mut App, obj: &mut Value) -> Result<bool, UpdateError> {
+    let chat_id = get_i64(obj, "chat_id")?;
+    let count = get_i64(obj, "online_member_count")?;
+    app.chat_list
+        .with_chat(chat_id, |c| c.online_member_count = count)
+        .ok_or(UpdateError::UnknownChat(chat_id))?;
+    Ok(is_open(app, chat_id))
+}
+
+// We've read messages in a chat, here or elsewhere
+fn chat_read_inbox(app: &mut App, obj: &mut Value) -> Result<bool, UpdateError> {
+    let chat_id = get_i64(obj, "chat_id")?;
+    let last_read = get_i64(obj, "last_read_inbox_message_id")?;
+    let unread_count = get_i64(obj, "unread_count")?;
+    app.chat_list
+        .with_chat(chat_id, |c| {
+            c.last_read_inbox_message_id = last_read;
+            c.unread_count = unread_count;
+        })
+        .ok_or(UpdateError::UnknownChat(chat_id))?;
+    app.chat_list.sort_later();
+    Ok(true)
+}
+
+// Whoever we're writing to has read our messages
+fn chat_read_outbox(app: &mut App, obj: &mut Value) -> Result<bool, UpdateError> {
+    let chat_id = get_i64(obj, "chat_id")?;
+    let last_read = get_i64(obj, "last_read_outbox_message_id")?;
+    app.chat_list
+        .with_chat(chat_id, |c| c.last_read_outbox_message_id = last_read)
+        .ok_or(UpdateError::UnknownChat(chat_id))?;
+    Ok(is_open(app, chat_id))
+}
+
+// Mentions of us were read, or new ones arrived. Both updates carry the new count
+fn chat_unread_mention_count(app: &mut App, obj: &mut Value) -> Result<bool, UpdateError> {
+    let chat_id = get_i64(obj, "chat_id")?;
+    let count = get_i64(obj, "unread_mention_count")?;
+    app.chat_list
+        .with_chat(chat_id, |c| c.unread_mention_count = count)
+        .ok_or(UpdateError::UnknownChat(chat_id))?;
+    app.chat_list.sort_later();
+    Ok(true)
+}
+
+// Received information about a message of which we've not heard before
+fn new_message(app: &mut App, obj: &mut Value) -> Result<bool, UpdateError> {
+    let msg = &mut obj["message"];
+    let chat_id = get_i64(msg, "chat_id")?;
+
+    // Parse message into rtdlib::Message type
+    let cur_msg = parse_msg(msg, chat_id)?;
+    let sender_id = cur_msg.msg.sender_user_id();
+    let muted = app
+        .chat_list
+        .with_chat(chat_id, |c| {
+            c.notification_settings["mute_for"].as_i64().unwrap_or(0) > 0
+        })
+        .unwrap_or(false);
+    if app.me.id() > 0 && app.me.id() != sender_id && !muted {
+        let users = app.users.lock().unwrap();
+        let sender_name = match users.get(&sender_id) {
+            Some(u) => format!("{} {}", u.u.first_name(), u.u.last_name()),
+            None => "Unknown User".to_string(),
+        };
+        let body = content::describe(&cur_msg, &users);
+
+        // Showing it can block on D-Bus, which other threads shouldn't wait on users for
+        drop(users);
+        let shown = notify_rust::Notification::new()
+            .summary(sender_name.as_str())
+            .body(&body)
+            .show();
+        if let Err(e) = shown {
+            eprintln!("Couldn't show notification: {}", e);
+        }
+    }
+
+    // Determine the chat to which message belongs, and place at start, rather than push to end
+    app.chat_list
+        .with_chat(chat_id, |c| c.history.lock().unwrap().add_newest(cur_msg))
+        .ok_or(UpdateError::UnknownChat(chat_id))?;
+    Ok(is_open(app, chat_id))
+}
+
+fn message_is_pinned(app: &mut App, obj: &mut Value) -> Result<bool, UpdateError> {
+    let chat_id = get_i64(obj, "chat_id")?;
+    let message_id = get_i64(obj, "message_id")?;
+    let is_pinned = obj["is_pinned"].as_bool().unwrap_or(false);
+    app.chat_list
+        .with_chat(chat_id, |c| {
+            if let Some(m) = c.history.lock().unwrap().get_mut(message_id) {
+                m.is_pinned = is_pinned;
+            }
+        })
+        .ok_or(UpdateError::UnknownChat(chat_id))?;
+    Ok(is_open(app, chat_id))
+}
+
+// Messages were deleted, or only dropped from Tdlib's cache, which isn't permanent and
+// doesn't matter here
+fn delete_messages(app: &mut App, obj: &mut Value) -> Result<bool, UpdateError> {
+    if !obj["is_permanent"].as_bool().unwrap_or(false) {
+        return Ok(false);
+    }
+    let chat_id = get_i64(obj, "chat_id")?;
+    let message_ids: Vec<i64> = obj["message_ids"]
+        .as_array()
+        .ok_or(UpdateError::Malformed("message_ids"))?
+        .iter()
+        .filter_map(|id| id.as_i64())
+        .collect();
+    app.chat_list
+        .with_chat(chat_id, |c| {
+            // Messages on screen stay where they are, unless they're what was deleted
+            let bottom = c.bottom_message_id();
+            let mut history = c.history.lock().unwrap();
+            history.remove(&message_ids);
+            let len = history.messages().len();
+            c.bottom_index = match bottom.and_then(|id| history.index_of(id)) {
+                Some(i) => i,
+                None => c.bottom_index.min(len.saturating_sub(1)),
+            };
+            c.select_index = c.select_index.min(len.saturating_sub(c.bottom_index + 1));
+            drop(history);
+
+            // Replies to them no longer quote them, and they can't be selected
+            for id in message_ids.iter() {
+                c.marked.remove(id);
+                if let Some(replied) = c.replied.get_mut(id) {
+                    *replied = None;
+                }
+            }
+
+            // Nor replied to or edited
+            if let MsgState::Reply(id) | MsgState::Edit(id) = c.msg_state {
+                if message_ids.contains(&id) {
+                    c.selection_change();
+                    c.msg_state = MsgState::Normal;
+                }
+            }
+        })
+        .ok_or(UpdateError::UnknownChat(chat_id))?;
+    Ok(is_open(app, chat_id))
+}
+
+// A message we sent reached Telegram, or couldn't be sent. Either way it replaces the copy
+// added while it was being sent, whose ID was only temporary
+fn message_sent(app: &mut App, obj: &mut Value) -> Result<bool, UpdateError> {
+    let old_id = get_i64(obj, "old_message_id")?;
+    let msg = &mut obj["message"];
+    let chat_id = get_i64(msg, "chat_id")?;
+    let sent = parse_msg(msg, chat_id)?;
+    app.chat_list
+        .with_chat(chat_id, |c| {
+            if let Some(m) = c.history.lock().unwrap().get_mut(old_id) {
+                *m = sent;
+            }
+        })
+        .ok_or(UpdateError::UnknownChat(chat_id))?;
+    Ok(is_open(app, chat_id))
+}
+
+// Received a list of messages for chat `chat_id`, in reply to a GetChatHistory call from
+// `from_message_id` with `offset`
+pub fn messages(
+    app: &mut App,
+    chat_id: i64,
+    from_message_id: i64,
+    offset: i64,
+    obj: &mut Value,
+) -> Result<(), UpdateError> {
+    let msg_list = match obj["messages"].as_array_mut() {
+        Some(l) => l,
+        None => return Err(UpdateError::Malformed("messages")),
+    };
+    let mut parsed = Vec::new();
+    for cur_msg in msg_list.iter_mut() {
+        parsed.push(parse_msg(cur_msg, chat_id)?);
+    }
+    app.chat_list
+        .with_chat(chat_id, |c| {
+            // Messages may be merged in below those on screen, which stay where they are
+            let bottom = c.bottom_message_id();
+            let mut history = c.history.lock().unwrap();
+            history.add(from_message_id, offset, parsed);
+            if let Some(i) = bottom.and_then(|id| history.index_of(id)) {
+                c.bottom_index = i;
+            }
+        })
+        .ok_or(UpdateError::UnknownChat(chat_id))
+}
+
+// Received an error that isn't a reply to any request
+fn error(app: &mut App, obj: &mut Value) -> Result<bool, UpdateError> {
+    let msg = obj["message"].as_str().unwrap_or("Unknown error");
+    eprintln!("{}", msg);
+    // Showing it redraws
+    app.set_status(msg.to_string());
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::fixtures::{private_chat, text_message};
+    use crate::TBlock;
+    use std::path::Path;
+
+    fn test_app() -> App {
+        let config = "[telegram]\napi_id = 1\napi_hash = \"hash\"\n";
+        match Config::parse(Path::new("config.toml"), config, None) {
+            Ok(c) => App::new(c),
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    // An app knowing of chat `chat_id`
+    fn app_with_chat(chat_id: i64) -> App {
+        let mut app = test_app();
+        let chat = private_chat(chat_id, "Alice", "9000000000000000000");
+        if let Err(e) = dispatch(
+            &mut app,
+            &mut json!({"@type": "updateNewChat", "chat": chat}),
+        ) {
+            panic!("{}", e);
+        }
+        app
+    }
+
+    fn new_message_update(chat_id: i64, id: i64) -> Value {
+        json!({"@type": "updateNewMessage", "message": text_message(id, chat_id, chat_id, "Hi")})
+    }
+
+    // Hand `app` the messages with `ids` as got from `from_message_id` with `offset`
+    fn add_messages(app: &mut App, chat_id: i64, from_message_id: i64, offset: i64, ids: &[i64]) {
+        let list: Vec<Value> = ids
+            .iter()
+            .map(|&id| text_message(id, chat_id, chat_id, "Hi"))
+            .collect();
+        let mut obj = json!({"@type": "messages", "total_count": ids.len(), "messages": list});
+        if let Err(e) = messages(app, chat_id, from_message_id, offset, &mut obj) {
+            panic!("{}", e);
+        }
+    }
+
+    // IDs of the messages shown in chat `chat_id`, newest first
+    fn shown_ids(app: &App, chat_id: i64) -> Vec<i64> {
+        app.chat_list
+            .with_chat(chat_id, |c| {
+                let history = c.history.lock().unwrap();
+                history.messages().iter().map(|m| m.msg.id()).collect()
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn last_message_for_unknown_chat() {
+        let mut app = app_with_chat(2);
+        let mut update = json!({
+            "@type": "updateChatLastMessage",
+            "chat_id": 3,
+            "last_message": text_message(1, 3, 3, "Hi"),
+            "positions": [],
+        });
+        let result = dispatch(&mut app, &mut update);
+        assert!(matches!(result, Err(UpdateError::UnknownChat(3))));
+    }
+
+    #[test]
+    fn missing_type() {
+        let mut app = test_app();
+        let result = dispatch(&mut app, &mut json!({"chat_id": 2}));
+        assert!(matches!(result, Err(UpdateError::Malformed("@type"))));
+    }
+
+    #[test]
+    fn new_messages_come_first() {
+        let mut app = app_with_chat(2);
+        for id in 1..=2 {
+            assert!(dispatch(&mut app, &mut new_message_update(2, id)).is_ok());
+        }
+        assert_eq!(shown_ids(&app, 2), vec![2, 1]);
+
+        let result = dispatch(&mut app, &mut new_message_update(3, 1));
+        assert!(matches!(result, Err(UpdateError::UnknownChat(3))));
+    }
+
+    #[test]
+    fn malformed_messages() {
+        let mut app = app_with_chat(2);
+        let mut obj = json!({"@type": "messages", "total_count": 1, "messages": "none"});
+        let result = messages(&mut app, 2, 0, 0, &mut obj);
+        assert!(matches!(result, Err(UpdateError::Malformed("messages"))));
+    }
+
+    #[test]
+    fn messages_for_unknown_chat() {
+        let mut app = app_with_chat(2);
+        let list = vec![text_message(1, 3, 3, "Hi")];
+        let mut obj = json!({"@type": "messages", "total_count": 1, "messages": list});
+        let result = messages(&mut app, 3, 0, 0, &mut obj);
+        assert!(matches!(result, Err(UpdateError::UnknownChat(3))));
+    }
+
+    #[test]
+    fn delete_messages_removes_only_permanent() {
+        let mut app = app_with_chat(2);
+        add_messages(&mut app, 2, 0, 0, &[5, 4, 3]);
+
+        // Only dropped from Tdlib's cache
+        let mut update = json!({
+            "@type": "updateDeleteMessages", "chat_id": 2, "message_ids": [4],
+            "is_permanent": false, "from_cache": true,
+        });
+        assert!(dispatch(&mut app, &mut update).is_ok());
+        assert_eq!(shown_ids(&app, 2), vec![5, 4, 3]);
+
+        update["is_permanent"] = json!(true);
+        assert!(dispatch(&mut app, &mut update).is_ok());
+        assert_eq!(shown_ids(&app, 2), vec![5, 3]);
+
+        // Replying to a message deleted is given up on
+        app.chat_list
+            .with_chat(2, |c| c.msg_state = MsgState::Reply(5))
+            .unwrap();
+        update["message_ids"] = json!([5]);
+        assert!(dispatch(&mut app, &mut update).is_ok());
+        let state = app.chat_list.with_chat(2, |c| c.msg_state.clone()).unwrap();
+        assert!(matches!(state, MsgState::Normal));
+        assert_eq!(shown_ids(&app, 2), vec![3]);
+
+        update["message_ids"] = json!("4");
+        let result = dispatch(&mut app, &mut update);
+        assert!(matches!(result, Err(UpdateError::Malformed("message_ids"))));
+    }
+
+    #[test]
+    fn new_message_redraws_only_open_chat() {
+        // The only chat known is open
+        let mut app = app_with_chat(2);
+        let mut update = new_message_update(2, 1);
+        assert!(matches!(new_message(&mut app, &mut update), Ok(true)));
+
+        let chat = private_chat(3, "Bob", "9000000000000000001");
+        if let Err(e) = dispatch(&mut app, &mut json!({"@type": "updateNewChat", "chat": chat})) {
+            panic!("{}", e);
+        }
+        let mut update = new_message_update(3, 1);
+        assert!(matches!(new_message(&mut app, &mut update), Ok(false)));
+        assert_eq!(shown_ids(&app, 3), vec![1]);
+    }
+
+    #[test]
+    fn messages_merge_segments() {
+        let mut app = app_with_chat(2);
+        add_messages(&mut app, 2, 0, 0, &[9, 8, 7]);
+
+        // Messages around one jumped to are kept apart from the newest
+        add_messages(&mut app, 2, 3, -1, &[4, 3, 2]);
+        assert_eq!(shown_ids(&app, 2), vec![9, 8, 7]);
+
+        // Until those between are got, joining them up
+        add_messages(&mut app, 2, 7, 0, &[7, 6, 5, 4]);
+        assert_eq!(shown_ids(&app, 2), vec![9, 8, 7, 6, 5, 4, 3, 2]);
+    }
+
+    #[test]
+    fn failed_history_waits_for_scrolling() {
+        let mut app = app_with_chat(2);
+        let requests = app.requests.clone();
+        let get_history = |app: &mut App| {
+            app.chat_list
+                .with_chat(2, |c| c.retrieve_history(&requests, 0, 0, 10))
+                .unwrap();
+            requests.take_outgoing()
+        };
+        let sent = get_history(&mut app);
+        assert_eq!(sent.len(), 1);
+
+        // Asking again straight after it fails would only fail again
+        let request: Value = serde_json::from_str(&sent[0]).unwrap();
+        let reply = json!({
+            "@type": "error", "code": 400, "message": "CHAT_INVALID",
+            "@extra": request["@extra"],
+        });
+        crate::requests::handle_reply(&mut app, reply);
+        assert!(get_history(&mut app).is_empty());
+
+        app.chat_list.with_chat(2, |c| c.scroll_up()).unwrap();
+        assert_eq!(get_history(&mut app).len(), 1);
+    }
+}
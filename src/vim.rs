@@ -0,0 +1,636 @@
+use crate::editor::Editor;
+use std::collections::HashMap;
+use termion::event::Key;
+
+// Whether keys typed in the input box are inserted, or are vim commands
+#[derive(Clone, Copy, PartialEq)]
+enum VimMode {
+    Insert,
+    Normal,
+}
+
+// Vim-style modal editing of the input box. Keys of a command are collected until it's
+// complete, e.g. `"a2dw` deletes two words into register a
+pub struct Vim {
+    // When not, Esc leaves the input box straight away
+    enabled: bool,
+    mode: VimMode,
+
+    // Keys typed so far of an unfinished command
+    pending: String,
+
+    // Text deleted or yanked, by register name. `"` is the unnamed register
+    registers: HashMap<char, Register>,
+}
+
+// Largest count a command takes, so that e.g. `99999999p` can't run away
+const MAX_COUNT: usize = 10_000;
+
+// Most text putting a register many times over inserts. Far more than fits in a message
+const MAX_PUT_LEN: usize = 1 << 20;
+
+#[derive(Clone, Default)]
+struct Register {
+    text: String,
+
+    // Whole lines, put back above or below the cursor's line rather than within it
+    linewise: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Operator {
+    Delete,
+    Change,
+    Yank,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Motion {
+    Left,
+    Right,
+    WordStart,
+    WordBack,
+    WordEnd,
+    LineStart,
+    FirstNonBlank,
+    LineEnd,
+    Down,
+    Up,
+    FirstLine,
+    LastLine,
+}
+
+enum Action {
+    Move(Motion),
+    Operate(Operator, Motion),
+
+    // `dd`, `cc` and `yy`
+    Lines(Operator),
+
+    // Any other single key command
+    Other(char),
+}
+
+struct Command {
+    register: char,
+    count: usize,
+    action: Action,
+}
+
+enum Parse<T> {
+    Incomplete,
+    Invalid,
+    Done(T),
+}
+
+impl Motion {
+    // Whether an operator over this motion takes in whole lines
+    fn linewise(self) -> bool {
+        matches!(
+            self,
+            Motion::Down | Motion::Up | Motion::FirstLine | Motion::LastLine
+        )
+    }
+
+    // Whether an operator over this motion includes the character moved onto
+    fn inclusive(self) -> bool {
+        self == Motion::WordEnd
+    }
+}
+
+impl Vim {
+    pub fn new(enabled: bool) -> Vim {
+        Vim {
+            enabled,
+            mode: VimMode::Insert,
+            pending: String::new(),
+            registers: HashMap::new(),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.mode = VimMode::Insert;
+        }
+    }
+
+    // Shown in the input box title while in normal mode, with any unfinished command
+    pub fn indicator(&self) -> Option<String> {
+        match self.mode {
+            VimMode::Insert => None,
+            VimMode::Normal if self.pending.is_empty() => Some("NORMAL".to_string()),
+            VimMode::Normal => Some(format!("NORMAL {}", self.pending)),
+        }
+    }
+
+    // Start inserting at the cursor. Everything typed until leaving insert mode is
+    // undone together
+    pub fn insert(&mut self, editor: &mut Editor) {
+        self.mode = VimMode::Insert;
+        self.pending.clear();
+        editor.checkpoint();
+    }
+
+    // Handle Esc, returning false if already in normal mode with nothing to cancel
+    pub fn escape(&mut self, editor: &mut Editor) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        match self.mode {
+            VimMode::Insert => {
+                // As in vim, the cursor ends up on the last character inserted
+                self.mode = VimMode::Normal;
+                if editor.cursor() > editor.line_start(editor.cursor()) {
+                    editor.set_cursor(editor.prev_boundary(editor.cursor()));
+                }
+                true
+            }
+            VimMode::Normal if !self.pending.is_empty() => {
+                self.pending.clear();
+                true
+            }
+            VimMode::Normal => false,
+        }
+    }
+
+    // Handle a key typed in the input box, other than Enter and Esc
+    pub fn handle_key(&mut self, editor: &mut Editor, key: &Key) {
+        if self.mode == VimMode::Insert {
+            editor.handle_key(key);
+            return;
+        }
+
+        let c = match key {
+            Key::Char(c) if !c.is_control() => *c,
+            Key::Left | Key::Backspace => 'h',
+            Key::Right => 'l',
+            Key::Down => 'j',
+            Key::Up => 'k',
+            Key::Home => '0',
+            Key::End => '$',
+            Key::Ctrl('r') => {
+                let count = take_count(&self.pending).0.unwrap_or(1);
+                self.pending.clear();
+                for _ in 0..count {
+                    editor.redo();
+                }
+                clamp(editor);
+                return;
+            }
+            _ => return,
+        };
+        self.pending.push(c);
+        match parse(&self.pending) {
+            Parse::Incomplete => return,
+            Parse::Invalid => {}
+            Parse::Done(command) => self.run(editor, command),
+        }
+        self.pending.clear();
+        if self.mode == VimMode::Normal {
+            clamp(editor);
+        }
+    }
+
+    fn run(&mut self, editor: &mut Editor, command: Command) {
+        let Command {
+            register,
+            count,
+            action,
+        } = command;
+        let cursor = editor.cursor();
+        match action {
+            Action::Move(Motion::Down) => editor.move_lines(count as i64),
+            Action::Move(Motion::Up) => editor.move_lines(-(count as i64)),
+            Action::Move(m) => editor.set_cursor(target(editor, m, count)),
+            Action::Operate(op, m) if m.linewise() => {
+                let end = target(editor, m, count);
+                self.operate_lines(editor, register, op, cursor, end);
+            }
+            Action::Operate(op, m) => {
+                // `cw` changes to the end of the word, leaving the space after it
+                let m = match (op, m) {
+                    (Operator::Change, Motion::WordStart) => Motion::WordEnd,
+                    _ => m,
+                };
+                let end = target(editor, m, count);
+                let (from, to) = (cursor.min(end), cursor.max(end));
+                let to = if m.inclusive() {
+                    editor.next_boundary(to)
+                } else {
+                    to
+                };
+                self.operate(editor, register, op, from, to);
+            }
+            Action::Lines(op) => {
+                let end = line_from(editor, cursor, count as i64 - 1);
+                self.operate_lines(editor, register, op, cursor, end);
+            }
+            Action::Other(c) => self.other(editor, register, count, c),
+        }
+    }
+
+    fn other(&mut self, editor: &mut Editor, register: char, count: usize, c: char) {
+        let cursor = editor.cursor();
+        let line_end = editor.line_end(cursor);
+        match c {
+            'x' | 's' => {
+                let end = target(editor, Motion::Right, count);
+                let op = if c == 'x' {
+                    Operator::Delete
+                } else {
+                    Operator::Change
+                };
+                self.operate(editor, register, op, cursor, end);
+            }
+            'X' => {
+                let start = target(editor, Motion::Left, count);
+                self.operate(editor, register, Operator::Delete, start, cursor);
+            }
+            'D' => self.operate(editor, register, Operator::Delete, cursor, line_end),
+            'C' => self.operate(editor, register, Operator::Change, cursor, line_end),
+            'S' => self.operate_lines(editor, register, Operator::Change, cursor, cursor),
+            'p' | 'P' => self.put(editor, register, count, c == 'p'),
+            'u' => {
+                for _ in 0..count {
+                    editor.undo();
+                }
+            }
+            'i' => self.insert(editor),
+            'a' => {
+                editor.set_cursor(editor.next_boundary(cursor).min(line_end));
+                self.insert(editor);
+            }
+            'I' => {
+                editor.set_cursor(editor.first_non_blank(cursor));
+                self.insert(editor);
+            }
+            'A' => {
+                editor.set_cursor(line_end);
+                self.insert(editor);
+            }
+            'o' => {
+                self.insert(editor);
+                editor.set_cursor(line_end);
+                editor.insert_char('\n');
+            }
+            'O' => {
+                self.insert(editor);
+                let start = editor.line_start(cursor);
+                editor.set_cursor(start);
+                editor.insert_char('\n');
+                editor.set_cursor(start);
+            }
+            _ => {}
+        }
+    }
+
+    // Apply `op` to the text between byte offsets `from` and `to`
+    fn operate(
+        &mut self,
+        editor: &mut Editor,
+        register: char,
+        op: Operator,
+        from: usize,
+        to: usize,
+    ) {
+        // Nothing is kept when there was nothing there, e.g. `x` on an empty line
+        if from < to {
+            let text = editor.text()[from..to].to_string();
+            self.store(register, text, false);
+        }
+        match op {
+            Operator::Yank => editor.set_cursor(from),
+            Operator::Delete => {
+                editor.checkpoint();
+                editor.delete_range(from, to);
+            }
+            Operator::Change => {
+                self.insert(editor);
+                editor.delete_range(from, to);
+            }
+        }
+    }
+
+    // Apply `op` to the whole lines from the one containing `a` to the one containing `b`
+    fn operate_lines(
+        &mut self,
+        editor: &mut Editor,
+        register: char,
+        op: Operator,
+        a: usize,
+        b: usize,
+    ) {
+        let start = editor.line_start(a.min(b));
+        let end = editor.line_end(a.max(b));
+        let text = editor.text()[start..end].to_string();
+        self.store(register, text, true);
+        match op {
+            Operator::Yank => editor.set_cursor(start),
+            Operator::Delete => {
+                // Take a newline with the lines, the one after if there is one
+                let (from, to) = if end < editor.text().len() {
+                    (start, end + 1)
+                } else {
+                    (start.saturating_sub(1), end)
+                };
+                editor.checkpoint();
+                editor.delete_range(from, to);
+                editor.set_cursor(editor.first_non_blank(from));
+            }
+            Operator::Change => {
+                self.insert(editor);
+                editor.delete_range(start, end);
+            }
+        }
+    }
+
+    // Put the contents of `register` after the cursor, or before it
+    fn put(&mut self, editor: &mut Editor, register: char, count: usize, after: bool) {
+        let r = match self.registers.get(&register.to_ascii_lowercase()) {
+            Some(r) if !r.text.is_empty() || r.linewise => r.clone(),
+            _ => return,
+        };
+        let count = count.min(MAX_PUT_LEN / (r.text.len() + 1)).max(1);
+        let cursor = editor.cursor();
+        editor.checkpoint();
+        if r.linewise {
+            let lines = vec![r.text.as_str(); count].join("\n");
+            let at = if after {
+                let end = editor.line_end(cursor);
+                editor.set_cursor(end);
+                editor.insert_str(&format!("\n{}", lines));
+                end + 1
+            } else {
+                let start = editor.line_start(cursor);
+                editor.set_cursor(start);
+                editor.insert_str(&format!("{}\n", lines));
+                start
+            };
+            editor.set_cursor(editor.first_non_blank(at));
+        } else {
+            let at = if after && cursor < editor.line_end(cursor) {
+                editor.next_boundary(cursor)
+            } else {
+                cursor
+            };
+            editor.set_cursor(at);
+            editor.insert_str(&r.text.repeat(count));
+            editor.set_cursor(editor.prev_boundary(editor.cursor()));
+        }
+    }
+
+    // Keep text deleted or yanked. Uppercase register names append to the lowercase one
+    fn store(&mut self, register: char, text: String, linewise: bool) {
+        if register == '_' {
+            return;
+        }
+        let name = register.to_ascii_lowercase();
+        let r = self.registers.entry(name).or_default();
+        if register.is_ascii_uppercase() && (!r.text.is_empty() || r.linewise) {
+            r.text
+                .push_str(if r.linewise || linewise { "\n" } else { "" });
+            r.text.push_str(&text);
+            r.linewise |= linewise;
+        } else {
+            *r = Register { text, linewise };
+        }
+        let r = r.clone();
+        self.registers.insert('"', r);
+    }
+}
+
+// Keep the cursor on a character in normal mode, rather than past the end of the line
+fn clamp(editor: &mut Editor) {
+    let cursor = editor.cursor();
+    if cursor == editor.line_end(cursor) && cursor > editor.line_start(cursor) {
+        editor.set_cursor(editor.prev_boundary(cursor));
+    }
+}
+
+// Where `motion` repeated `count` times takes the cursor
+fn target(editor: &Editor, motion: Motion, count: usize) -> usize {
+    let mut pos = editor.cursor();
+    match motion {
+        Motion::LineStart => return editor.line_start(pos),
+        Motion::FirstNonBlank => return editor.first_non_blank(pos),
+        Motion::LineEnd => return editor.line_end(line_from(editor, pos, count as i64 - 1)),
+        Motion::Down => return line_from(editor, pos, count as i64),
+        Motion::Up => return line_from(editor, pos, -(count as i64)),
+        Motion::FirstLine => return editor.first_non_blank(0),
+        Motion::LastLine => return editor.first_non_blank(editor.text().len()),
+        _ => {}
+    }
+    for _ in 0..count {
+        pos = match motion {
+            Motion::Left if pos > editor.line_start(pos) => editor.prev_boundary(pos),
+            Motion::Right if pos < editor.line_end(pos) => editor.next_boundary(pos),
+            Motion::WordStart => editor.next_word_start(pos),
+            Motion::WordBack => editor.word_start_before(pos),
+            Motion::WordEnd => {
+                let end = editor.word_end_after(editor.next_boundary(pos));
+                editor.prev_boundary(end)
+            }
+            _ => pos,
+        };
+    }
+    pos
+}
+
+// Start of the line `n` lines below the one containing `pos`, or above if negative
+fn line_from(editor: &Editor, pos: usize, n: i64) -> usize {
+    let mut start = editor.line_start(pos);
+    for _ in 0..n.abs() {
+        if n < 0 {
+            if start == 0 {
+                break;
+            }
+            start = editor.line_start(start - 1);
+        } else {
+            let end = editor.line_end(start);
+            if end == editor.text().len() {
+                break;
+            }
+            start = end + 1;
+        }
+    }
+    start
+}
+
+// Split a count off the start of `keys`, up to `MAX_COUNT`. A leading 0 is the motion,
+// not a count
+fn take_count(keys: &str) -> (Option<usize>, &str) {
+    if keys.starts_with('0') {
+        return (None, keys);
+    }
+    let digits = keys
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(keys.len());
+    let count = keys[..digits]
+        .bytes()
+        .fold(None, |count: Option<usize>, d| {
+            Some((count.unwrap_or(0) * 10 + usize::from(d - b'0')).min(MAX_COUNT))
+        });
+    (count, &keys[digits..])
+}
+
+// Parse the keys of a command: ["x][count](operator[count]motion | motion | command)
+fn parse(keys: &str) -> Parse<Command> {
+    let mut register = '"';
+    let mut rest = keys;
+    if let Some(r) = rest.strip_prefix('"') {
+        let mut chars = r.chars();
+        match chars.next() {
+            None => return Parse::Incomplete,
+            Some(c) if c == '"' || c == '_' || c.is_ascii_alphabetic() => register = c,
+            Some(_) => return Parse::Invalid,
+        }
+        rest = chars.as_str();
+    }
+    let (count, rest) = take_count(rest);
+    let mut count = count.unwrap_or(1);
+
+    let mut chars = rest.chars();
+    let c = match chars.next() {
+        Some(c) => c,
+        None => return Parse::Incomplete,
+    };
+    let op = match c {
+        'd' => Operator::Delete,
+        'c' => Operator::Change,
+        'y' => Operator::Yank,
+        _ => {
+            let action = match parse_motion(rest) {
+                Parse::Done(m) => Action::Move(m),
+                Parse::Incomplete => return Parse::Incomplete,
+                Parse::Invalid if chars.as_str().is_empty() => Action::Other(c),
+                Parse::Invalid => return Parse::Invalid,
+            };
+            return Parse::Done(Command {
+                register,
+                count,
+                action,
+            });
+        }
+    };
+
+    let (motion_count, rest) = take_count(chars.as_str());
+    count = count
+        .saturating_mul(motion_count.unwrap_or(1))
+        .min(MAX_COUNT);
+    let action = if rest.len() == 1 && rest.starts_with(c) {
+        Action::Lines(op)
+    } else {
+        match parse_motion(rest) {
+            Parse::Done(m) => Action::Operate(op, m),
+            Parse::Incomplete => return Parse::Incomplete,
+            Parse::Invalid => return Parse::Invalid,
+        }
+    };
+    Parse::Done(Command {
+        register,
+        count,
+        action,
+    })
+}
+
+fn parse_motion(keys: &str) -> Parse<Motion> {
+    let motion = match keys {
+        "" | "g" => return Parse::Incomplete,
+        "h" => Motion::Left,
+        "l" | " " => Motion::Right,
+        "w" => Motion::WordStart,
+        "b" => Motion::WordBack,
+        "e" => Motion::WordEnd,
+        "0" => Motion::LineStart,
+        "^" => Motion::FirstNonBlank,
+        "$" => Motion::LineEnd,
+        "j" => Motion::Down,
+        "k" => Motion::Up,
+        "gg" => Motion::FirstLine,
+        "G" => Motion::LastLine,
+        _ => return Parse::Invalid,
+    };
+    Parse::Done(motion)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Type `keys` in normal mode over `text`, starting at byte offset `cursor`
+    fn run(text: &str, cursor: usize, keys: &str) -> (Vim, Editor) {
+        let mut vim = Vim::new(true);
+        let mut editor = Editor::new();
+        editor.set_text(text);
+        vim.escape(&mut editor);
+        editor.set_cursor(cursor);
+        for c in keys.chars() {
+            vim.handle_key(&mut editor, &Key::Char(c));
+        }
+        (vim, editor)
+    }
+
+    fn count_of(keys: &str) -> usize {
+        match parse(keys) {
+            Parse::Done(command) => command.count,
+            _ => panic!("{} isn't a command", keys),
+        }
+    }
+
+    #[test]
+    fn counts_compose() {
+        assert_eq!(count_of("w"), 1);
+        assert_eq!(count_of("3w"), 3);
+        assert_eq!(count_of("2d3w"), 6);
+        assert_eq!(count_of("\"a2y4l"), 8);
+        assert!(matches!(parse("2d3"), Parse::Incomplete));
+        assert!(matches!(parse("d0"), Parse::Done(_)));
+
+        let (_, e) = run("a b c d e f g h", 0, "2d3w");
+        assert_eq!(e.text(), "g h");
+        let (_, e) = run("one\ntwo\nthree\nfour", 0, "d2j");
+        assert_eq!(e.text(), "four");
+        let (_, e) = run("abcdef", 1, "2x");
+        assert_eq!(e.text(), "adef");
+    }
+
+    #[test]
+    fn counts_with_registers() {
+        let (mut vim, mut e) = run("one\ntwo\nthree", 0, "\"a2yyG\"ap");
+        assert_eq!(e.text(), "one\ntwo\nthree\none\ntwo");
+        for c in "u\"a3p".chars() {
+            vim.handle_key(&mut e, &Key::Char(c));
+        }
+        assert_eq!(e.text(), "one\ntwo\nthree\none\ntwo\none\ntwo\none\ntwo");
+
+        let (_, e) = run("ab", 0, "yl3p");
+        assert_eq!(e.text(), "aaaab");
+    }
+
+    #[test]
+    fn counts_are_clamped() {
+        assert_eq!(
+            take_count("99999999999999999999999x"),
+            (Some(MAX_COUNT), "x")
+        );
+        assert_eq!(take_count("0x"), (None, "0x"));
+        assert_eq!(count_of("9999d9999w"), MAX_COUNT);
+        assert_eq!(
+            count_of("99999999999999999999d99999999999999999999w"),
+            MAX_COUNT
+        );
+
+        let (_, e) = run("abcdefgh", 0, "y$99999999p");
+        assert_eq!(e.text().len(), 8 * (MAX_COUNT + 1));
+
+        // Putting a lot many times over stops short of running away
+        let long = "a".repeat(MAX_PUT_LEN / 4);
+        let (_, e) = run(&long, 0, "y$10p");
+        assert_eq!(e.text().len(), long.len() * 4);
+    }
+}
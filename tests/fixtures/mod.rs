@@ -0,0 +1,75 @@
+// Tdlib objects for tests to feed the app, shared by the unit tests and those in tests/
+#![allow(dead_code)] // Each test crate uses only some of them
+
+use serde_json::{json, Value};
+
+pub fn user(id: i64, first_name: &str) -> Value {
+    json!({
+        "@type": "user", "id": id, "first_name": first_name, "last_name": "",
+        "username": "", "phone_number": "",
+        "status": {"@type": "userStatusEmpty"},
+        "is_contact": true, "is_mutual_contact": true, "is_verified": false,
+        "is_support": false, "restriction_reason": "", "is_scam": false,
+        "have_access": true, "type": {"@type": "userTypeRegular"}, "language_code": "en",
+    })
+}
+
+// A chat with the user with the same ID, at `order` in the main list
+pub fn private_chat(id: i64, title: &str, order: &str) -> Value {
+    json!({
+        "@type": "chat", "id": id, "type": {"@type": "chatTypePrivate", "user_id": id},
+        "title": title,
+        "permissions": {
+            "@type": "chatPermissions", "can_send_messages": true,
+            "can_send_media_messages": true, "can_send_polls": true,
+            "can_send_other_messages": true, "can_add_web_page_previews": true,
+            "can_change_info": true, "can_invite_users": true, "can_pin_messages": true,
+        },
+        "positions": [{
+            "@type": "chatPosition", "list": {"@type": "chatListMain"},
+            "order": order, "is_pinned": false,
+        }],
+        "is_marked_as_unread": false, "has_scheduled_messages": false,
+        "can_be_deleted_only_for_self": true, "can_be_deleted_for_all_users": true,
+        "can_be_reported": false, "default_disable_notification": false,
+        "unread_count": 0, "last_read_inbox_message_id": 0,
+        "last_read_outbox_message_id": 0, "unread_mention_count": 0,
+        "notification_settings": {
+            "@type": "chatNotificationSettings", "use_default_mute_for": true, "mute_for": 0,
+            "use_default_sound": true, "sound": "default", "use_default_show_preview": true,
+            "show_preview": true, "use_default_disable_pinned_message_notifications": true,
+            "disable_pinned_message_notifications": false,
+            "use_default_disable_mention_notifications": true,
+            "disable_mention_notifications": false,
+        },
+        "reply_markup_message_id": 0, "client_data": "",
+    })
+}
+
+pub fn text_message(id: i64, chat_id: i64, sender_id: i64, text: &str) -> Value {
+    json!({
+        "@type": "message", "id": id,
+        "sender": {"@type": "messageSenderUser", "user_id": sender_id},
+        "chat_id": chat_id, "is_outgoing": false, "is_pinned": false,
+        "can_be_edited": false, "can_be_forwarded": true,
+        "can_be_deleted_only_for_self": true, "can_be_deleted_for_all_users": false,
+        "is_channel_post": false, "contains_unread_mention": false,
+        "date": 1610139200, "edit_date": 0, "reply_in_chat_id": 0,
+        "reply_to_message_id": 0, "message_thread_id": 0, "ttl": 0, "ttl_expires_in": 0,
+        "via_bot_user_id": 0, "author_signature": "", "media_album_id": "0",
+        "restriction_reason": "",
+        "content": {
+            "@type": "messageText",
+            "text": {"@type": "formattedText", "text": text, "entities": []},
+        },
+    })
+}
+
+// An updateAuthorizationState to `state`, with any fields of `extra` added to it
+pub fn auth_state(state: &str, extra: Value) -> Value {
+    let mut state_obj = json!({ "@type": state });
+    if let (Some(s), Some(e)) = (state_obj.as_object_mut(), extra.as_object()) {
+        s.extend(e.clone());
+    }
+    json!({"@type": "updateAuthorizationState", "authorization_state": state_obj})
+}
@@ -0,0 +1,124 @@
+mod fixtures;
+
+use crossbeam::channel;
+use crossbeam::thread;
+use fixtures::{auth_state, private_chat, text_message, user};
+use serde_json::{json, Value};
+use std::fs;
+use std::time::{Duration, Instant};
+use tele::config::Config;
+use tele::transport::FakeTransport;
+use tele::{td_thread, App, MsgCode};
+use tempfile::TempDir;
+
+// Longest to wait for the app to get through a replay
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+// A config with storage in `dir`, which is removed along with everything in it once the
+// test drops it
+fn test_config(dir: &TempDir) -> Config {
+    let path = dir.path().join("config.toml");
+    let contents = format!(
+        r#"
+[telegram]
+api_id = 123456
+api_hash = "0123456789abcdef0123456789abcdef"
+receive_timeout = 0.05
+
+[storage]
+database_directory = "{dir}/database"
+files_directory = "{dir}/files"
+command_history = "{dir}/command_history"
+encrypt = false
+
+[log]
+debug = false
+"#,
+        dir = dir.path().display()
+    );
+    fs::write(&path, contents).unwrap();
+    Config::load(Some(path), None).unwrap_or_else(|e| panic!("{}", e))
+}
+
+// Run the app's Tdlib thread against `fake` until `done` holds for the requests sent,
+// then tell it to exit
+fn run_until(app: &mut App, fake: &FakeTransport, done: impl Fn(&[String]) -> bool) {
+    let (tx, rx) = channel::unbounded::<MsgCode>();
+    thread::scope(|s| {
+        s.spawn(|_| td_thread(fake, app, &rx));
+        let start = Instant::now();
+        while !done(&fake.sent_types()) && start.elapsed() < TIMEOUT {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        tx.send(MsgCode::Exit).unwrap();
+    })
+    .unwrap();
+}
+
+// Requests of type `request_type` sent so far, decoded
+fn sent_of_type(fake: &FakeTransport, request_type: &str) -> Vec<Value> {
+    fake.sent()
+        .iter()
+        .map(|r| serde_json::from_str::<Value>(r).unwrap())
+        .filter(|r| r["@type"] == request_type)
+        .collect()
+}
+
+#[test]
+fn login_then_get_chats_then_new_message() {
+    let fake = FakeTransport::new();
+
+    // Log in to an unencrypted database
+    let wait_parameters = auth_state("authorizationStateWaitTdlibParameters", json!({}));
+    fake.push(&wait_parameters.to_string());
+    let wait_key = auth_state(
+        "authorizationStateWaitEncryptionKey",
+        json!({"is_encrypted": false}),
+    );
+    fake.reply_to("setTdlibParameters", &wait_key.to_string());
+    fake.reply_to("checkDatabaseEncryptionKey", r#"{"@type":"ok"}"#);
+    let ready = auth_state("authorizationStateReady", json!({}));
+    fake.reply_to("checkDatabaseEncryptionKey", &ready.to_string());
+    fake.reply_to("getMe", &user(1, "Me").to_string());
+
+    // The first page of chats, with a message sent from another session arriving before
+    // its reply. The second page is empty, being the end of the list
+    let replies = vec![
+        json!({"@type": "updateUser", "user": user(2, "Alice")}),
+        json!({"@type": "updateNewChat", "chat": private_chat(2, "Alice", "9000000000000000002")}),
+        json!({"@type": "updateNewMessage", "message": text_message(1048576, 2, 1, "Hi")}),
+        json!({"@type": "chats", "total_count": 1, "chat_ids": [2]}),
+    ];
+    for reply in replies {
+        fake.reply_to("getChats", &reply.to_string());
+    }
+    fake.reply_to(
+        "getChats",
+        r#"{"@type":"chats","total_count":1,"chat_ids":[]}"#,
+    );
+
+    let dir = TempDir::new().unwrap();
+    let mut app = App::new(test_config(&dir));
+    run_until(&mut app, &fake, |sent| {
+        sent.iter().filter(|t| *t == "getChats").count() == 2 && sent.iter().any(|t| t == "getMe")
+    });
+
+    let types = fake.sent_types();
+    assert_eq!(
+        &types[..2],
+        &["setTdlibParameters", "checkDatabaseEncryptionKey"],
+        "sent {:?}",
+        types
+    );
+    assert!(types.contains(&"getMe".to_string()), "sent {:?}", types);
+
+    // The first page starts at the top of the main list. The second, asked for once the
+    // message has been handled, starts after the first page's last chat
+    let get_chats = sent_of_type(&fake, "getChats");
+    assert_eq!(get_chats.len(), 2, "sent {:?}", types);
+    assert_eq!(get_chats[0]["chat_list"]["@type"], "chatListMain");
+    assert_eq!(get_chats[0]["offset_order"], i64::MAX.to_string());
+    assert_eq!(get_chats[0]["offset_chat_id"], 0);
+    assert_eq!(get_chats[1]["offset_order"], "9000000000000000002");
+    assert_eq!(get_chats[1]["offset_chat_id"], 2);
+}